@@ -0,0 +1,207 @@
+//! Build and probe Polyglot opening books.
+//!
+//! Usage:
+//! - `book build <games.csv|games.pgn> <book.bin> [--min-games N] [--min-rating N] [--max-ply N]`
+//! - `book probe <book.bin> [moves...]`
+
+extern crate csv;
+#[macro_use]
+extern crate failure;
+extern crate pgn_reader;
+extern crate shared;
+
+use pgn_reader::{Reader, San, Skip, Visitor};
+use shared::polyglot::{self, Book, BookBuilder};
+use shared::{BoardState, CurrentPlayer, Result};
+use std::fs;
+
+const COLUMN_WINNER: usize = 6;
+const COLUMN_WHITE_RATING: usize = 9;
+const COLUMN_BLACK_RATING: usize = 11;
+const COLUMN_MOVES: usize = 12;
+
+struct BuildOptions {
+    min_games: u32,
+    min_rating: u32,
+    max_ply: usize,
+}
+
+/// A game with a known result, read from either a csv or a pgn file.
+#[derive(Default)]
+struct Game {
+    white_rating: Option<u32>,
+    black_rating: Option<u32>,
+    /// `None` if the game has no result, `Some(None)` for a draw.
+    winner: Option<Option<CurrentPlayer>>,
+    moves: Vec<String>,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("build") if args.len() >= 3 => build(&args[1], &args[2], &args[3..]),
+        Some("probe") if args.len() >= 2 => probe(&args[1], &args[2..]),
+        _ => {
+            println!("Usage:");
+            println!("  book build <games.csv|games.pgn> <book.bin> [--min-games N] [--min-rating N] [--max-ply N]");
+            println!("  book probe <book.bin> [moves...]");
+            return;
+        }
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_build_options(args: &[String]) -> Result<BuildOptions> {
+    let mut options = BuildOptions {
+        min_games: 3,
+        min_rating: 0,
+        max_ply: 20,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format_err!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--min-games" => options.min_games = value.parse()?,
+            "--min-rating" => options.min_rating = value.parse()?,
+            "--max-ply" => options.max_ply = value.parse()?,
+            _ => bail!("Unknown argument {:?}", arg),
+        }
+    }
+    Ok(options)
+}
+
+fn build(input: &str, output: &str, args: &[String]) -> Result<()> {
+    let options = parse_build_options(args)?;
+    let games = if input.ends_with(".pgn") {
+        read_pgn(input)?
+    } else {
+        read_csv(input)?
+    };
+
+    let mut builder = BookBuilder::new();
+    let (mut added, mut filtered, mut failed) = (0, 0, 0);
+    for game in games {
+        let rating = game
+            .white_rating
+            .unwrap_or(0)
+            .min(game.black_rating.unwrap_or(0));
+        let winner = match game.winner {
+            Some(winner) if rating >= options.min_rating => winner,
+            _ => {
+                filtered += 1;
+                continue;
+            }
+        };
+        let moves = game.moves.iter().map(String::as_str);
+        match builder.add_game(moves, winner, options.max_ply) {
+            Ok(()) => added += 1,
+            Err(_) => failed += 1,
+        }
+    }
+
+    let book = builder.build(options.min_games);
+    book.save(output)?;
+    println!(
+        "Added {} games ({} filtered, {} could not be replayed), written {} entries to {:?}",
+        added,
+        filtered,
+        failed,
+        book.len(),
+        output
+    );
+    Ok(())
+}
+
+fn read_csv(input: &str) -> Result<Vec<Game>> {
+    let mut parser = csv::Reader::from_path(input)?;
+    let mut games = Vec::new();
+    for record in parser.records() {
+        let record = record?;
+        games.push(Game {
+            white_rating: record.get(COLUMN_WHITE_RATING).and_then(|r| r.parse().ok()),
+            black_rating: record.get(COLUMN_BLACK_RATING).and_then(|r| r.parse().ok()),
+            winner: match record.get(COLUMN_WINNER) {
+                Some("white") => Some(Some(CurrentPlayer::White)),
+                Some("black") => Some(Some(CurrentPlayer::Black)),
+                Some("draw") => Some(None),
+                _ => None,
+            },
+            moves: record
+                .get(COLUMN_MOVES)
+                .unwrap_or_default()
+                .split(' ')
+                .filter(|m| !m.is_empty())
+                .map(String::from)
+                .collect(),
+        });
+    }
+    Ok(games)
+}
+
+#[derive(Default)]
+struct GameVisitor {
+    game: Game,
+}
+
+impl<'pgn> Visitor<'pgn> for GameVisitor {
+    type Result = Game;
+
+    fn begin_game(&mut self) {
+        self.game = Game::default();
+    }
+
+    fn header(&mut self, key: &'pgn [u8], value: &'pgn [u8]) {
+        let value = String::from_utf8_lossy(value);
+        match key {
+            b"WhiteElo" => self.game.white_rating = value.parse().ok(),
+            b"BlackElo" => self.game.black_rating = value.parse().ok(),
+            b"Result" => {
+                self.game.winner = match value.as_ref() {
+                    "1-0" => Some(Some(CurrentPlayer::White)),
+                    "0-1" => Some(Some(CurrentPlayer::Black)),
+                    "1/2-1/2" => Some(None),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn san(&mut self, san: San) {
+        self.game.moves.push(san.to_string());
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true) // stay in the mainline
+    }
+
+    fn end_game(&mut self, _game: &'pgn [u8]) -> Self::Result {
+        std::mem::take(&mut self.game)
+    }
+}
+
+fn read_pgn(input: &str) -> Result<Vec<Game>> {
+    let pgn = fs::read(input)?;
+    let mut visitor = GameVisitor::default();
+    let reader = Reader::new(&mut visitor, &pgn);
+    Ok(reader.into_iter().collect())
+}
+
+fn probe(book: &str, moves: &[String]) -> Result<()> {
+    let book = Book::open(book)?;
+    let mut state = BoardState::init();
+    for m in moves {
+        state.make_move(m)?;
+    }
+    let key = polyglot::hash(&state);
+    println!("Key {:016x}", key);
+    for (m, weight) in book.moves(&state) {
+        println!("{} weight {}", m, weight);
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! The value network as a TensorFlow graph, for the `tensorflow` feature.
+//!
+//! The weights are loaded into the graph as constants, and positions are fed in batches through
+//! the `input` placeholder. The `value` output is the probability that white wins, counting a draw
+//! as half a win. With a policy head, the `policy` output has the logits of every move index of
+//! `shared::policy`.
+
+use crate::network::{self, Evaluator};
+use crate::weights::{Layer, Weights, INPUTS};
+use shared::mcts::{Evaluate, Evaluation};
+use shared::policy::{self, Policy, POLICY_SIZE};
+use shared::{BoardState, Result};
+use tensorflow::{
+    DataType, Graph, Operation, Output, Session, SessionOptions, SessionRunArgs, Shape, Status,
+    Tensor,
+};
+
+/// `Status` holds a raw pointer, so it can't be turned into a `failure::Error` directly.
+pub(crate) fn status(status: Status) -> failure::Error {
+    format_err!("TensorFlow: {}", status)
+}
+
+fn output(operation: &Operation) -> Output {
+    Output {
+        operation: operation.clone(),
+        index: 0,
+    }
+}
+
+fn constant(graph: &mut Graph, name: &str, dims: &[u64], values: &[f32]) -> Result<Operation> {
+    let tensor = Tensor::new(dims).with_values(values).map_err(status)?;
+    let mut description = graph.new_operation("Const", name)?;
+    description.set_attr_type("dtype", DataType::Float)?;
+    description
+        .set_attr_tensor("value", tensor)
+        .map_err(status)?;
+    description.finish().map_err(status)
+}
+
+/// An operation on float tensors with the given inputs.
+fn operation(
+    graph: &mut Graph,
+    kind: &str,
+    name: &str,
+    inputs: &[&Operation],
+) -> Result<Operation> {
+    let mut description = graph.new_operation(kind, name)?;
+    for input in inputs {
+        description.add_input(output(input));
+    }
+    description.set_attr_type("T", DataType::Float)?;
+    description.finish().map_err(status)
+}
+
+/// A fully connected layer, without an activation.
+fn dense(graph: &mut Graph, name: &str, input: &Operation, layer: &Layer) -> Result<Operation> {
+    let w = constant(
+        graph,
+        &format!("{}/weights", name),
+        &[layer.inputs as u64, layer.outputs as u64],
+        &layer.weights,
+    )?;
+    let b = constant(
+        graph,
+        &format!("{}/biases", name),
+        &[layer.outputs as u64],
+        &layer.biases,
+    )?;
+    let product = operation(graph, "MatMul", &format!("{}/matmul", name), &[input, &w])?;
+    operation(graph, "BiasAdd", &format!("{}/add", name), &[&product, &b])
+}
+
+pub struct GraphNetwork {
+    session: Session,
+    input: Operation,
+    value: Operation,
+    policy: Option<Operation>,
+}
+
+impl GraphNetwork {
+    pub fn new(weights: &Weights) -> Result<GraphNetwork> {
+        let mut graph = Graph::new();
+
+        let mut description = graph.new_operation("Placeholder", "input")?;
+        description.set_attr_type("dtype", DataType::Float)?;
+        description.set_attr_shape("shape", &Shape::from(Some(vec![None, Some(INPUTS as i64)])))?;
+        let input = description.finish().map_err(status)?;
+
+        let mut last = input.clone();
+        let mut policy = None;
+        for (i, layer) in weights.layers.iter().enumerate() {
+            let is_last = i + 1 == weights.layers.len();
+            if let (true, Some(head)) = (is_last, &weights.policy) {
+                // The policy head reads the output of the last hidden layer.
+                let logits = dense(&mut graph, "policy_head", &last, head)?;
+                policy = Some(operation(&mut graph, "Identity", "policy", &[&logits])?);
+            }
+            let sum = dense(&mut graph, &format!("layer_{}", i), &last, layer)?;
+            last = if is_last {
+                operation(&mut graph, "Sigmoid", "value", &[&sum])?
+            } else {
+                operation(&mut graph, "Relu", &format!("layer_{}/relu", i), &[&sum])?
+            };
+        }
+
+        let session = Session::new(&SessionOptions::new(), &graph).map_err(status)?;
+        Ok(GraphNetwork {
+            session,
+            input,
+            value: last,
+            policy,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<GraphNetwork> {
+        GraphNetwork::new(&Weights::load(path)?)
+    }
+
+    /// Run the graph on the positions, returning the values and, if asked for, the policy logits.
+    fn run(&mut self, states: &[BoardState], with_policy: bool) -> Result<(Vec<f32>, Vec<f32>)> {
+        if states.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let mut features = Vec::with_capacity(states.len() * INPUTS);
+        for state in states {
+            features.extend(state.to_piece_vec());
+        }
+        let input = Tensor::new(&[states.len() as u64, INPUTS as u64])
+            .with_values(&features)
+            .map_err(status)?;
+
+        let mut step = SessionRunArgs::new();
+        step.add_feed(&self.input, 0, &input);
+        let token = step.request_fetch(&self.value, 0);
+        let policy_token = match (&self.policy, with_policy) {
+            (Some(policy), true) => Some(step.request_fetch(policy, 0)),
+            _ => None,
+        };
+        self.session.run(&mut step).map_err(status)?;
+        let values = step.fetch::<f32>(token).map_err(status)?;
+        let logits = match policy_token {
+            Some(token) => step.fetch::<f32>(token).map_err(status)?.to_vec(),
+            None => Vec::new(),
+        };
+        Ok((values.to_vec(), logits))
+    }
+}
+
+impl Evaluator for GraphNetwork {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<f32>> {
+        Ok(self.run(states, false)?.0)
+    }
+
+    fn has_policy(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    fn evaluate_batch_with_policy(&mut self, states: &[BoardState]) -> Result<Vec<(f32, Policy)>> {
+        if !self.has_policy() {
+            bail!("The network has no policy head");
+        }
+        let (values, logits) = self.run(states, true)?;
+        Ok(values
+            .into_iter()
+            .zip(logits.chunks(POLICY_SIZE))
+            .zip(states)
+            .map(|((value, logits), state)| (value, policy::masked_softmax(state, logits)))
+            .collect())
+    }
+}
+
+/// Lets the network guide `shared::mcts::Mcts`, with its policy head if it has one.
+impl Evaluate for GraphNetwork {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<Evaluation>> {
+        network::evaluations(self, states)
+    }
+}
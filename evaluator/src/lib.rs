@@ -0,0 +1,33 @@
+//! A neural network that estimates how likely white is to win a position.
+//!
+//! `Weights` holds the network and its file format, `Network` runs it and `train` fits it to the
+//! results of played games. `selfplay` plays games with the network to train on. With the
+//! `tensorflow` feature, `model` runs networks trained elsewhere on the features of the `export`
+//! binary.
+
+#[macro_use]
+extern crate failure;
+extern crate csv;
+extern crate random;
+extern crate shared;
+#[cfg(feature = "tensorflow")]
+extern crate tensorflow;
+
+#[cfg(feature = "tensorflow")]
+pub mod graph;
+#[cfg(feature = "tensorflow")]
+pub mod model;
+pub mod native;
+pub mod network;
+pub mod samples;
+pub mod selfplay;
+pub mod signature;
+pub mod train;
+pub mod weights;
+
+#[cfg(all(feature = "tensorflow", not(feature = "native")))]
+pub use crate::graph::GraphNetwork as Network;
+#[cfg(any(feature = "native", not(feature = "tensorflow")))]
+pub use crate::native::NativeNetwork as Network;
+pub use crate::network::Evaluator;
+pub use crate::weights::Weights;
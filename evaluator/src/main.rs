@@ -1,26 +1,49 @@
-#![cfg_attr(feature = "nightly", feature(alloc_system))]
-#[cfg(feature = "nightly")]
-extern crate alloc_system;
+//! Usage:
+//! - `evaluator init <weights.bin>` writes a network with random weights.
+//! - `evaluator eval <weights.bin> [moves...]` prints the value of the position after the moves,
+//!   and the most likely moves if the network has a policy head.
+//! - `evaluator model <saved_model|graph.pb> [--flip true|false] [moves...]` does the same with a
+//!   TensorFlow SavedModel directory or frozen graph trained on the features of `export`, using
+//!   the moves before the position as its history. `--flip` must match the export, and is `true`
+//!   by default. This needs the `tensorflow` feature.
+//! - `evaluator search <weights.bin> <playouts> [moves...]` runs a Monte Carlo Tree Search guided
+//!   by the network from the position after the moves, and prints the most visited moves.
+//! - `evaluator train <games.csv|samples.bin> <weights.bin> [--epochs N] [--batch-size N]
+//!   [--learning-rate F] [--validation F] [--augment true|false]` trains the network on the games
+//!   or samples, continuing from `weights.bin` if it exists. The weights are written back after
+//!   every epoch. `--validation` of the games are kept apart to measure the loss on. Positions
+//!   of the other games in `games.csv` are also used flipped and mirrored, unless
+//!   `--augment false`.
+//! - `evaluator selfplay <weights.bin|static> <samples.bin> [--games N] [--playouts N]
+//!   [--random-plies N] [--temperature-plies N] [--max-plies N] [--syzygy-path P]
+//!   [--eval-file P] [--book P]` plays games of the network against itself, or of the static
+//!   evaluation with `static`, and writes the positions to a samples file for `train`. The static
+//!   evaluation uses the weights of the `tuner` in `--eval-file`, or the default weights. With
+//!   `--book`, the moves of the polyglot book are played while the game is in it.
+
+extern crate evaluator;
+#[macro_use]
+extern crate failure;
 extern crate random;
-extern crate tensorflow;
+extern crate shared;
 
-use std::error::Error;
+#[cfg(feature = "tensorflow")]
+use evaluator::model::Model;
+use evaluator::samples::{self, SampleWriter};
+use evaluator::selfplay::{self, SelfPlayOptions};
+use evaluator::train::{self, TrainOptions};
+use evaluator::{Evaluator, Network, Weights};
+use shared::mcts::{Evaluate, Mcts, MctsOptions};
+use shared::options::Options;
+use shared::{BoardState, Result};
+use std::path::Path;
 use std::process::exit;
-use std::result::Result;
-use tensorflow::expr::{Compiler, Placeholder};
-use tensorflow::Code;
-use tensorflow::Graph;
-use tensorflow::Session;
-use tensorflow::SessionOptions;
-use tensorflow::SessionRunArgs;
-use tensorflow::Status;
-use tensorflow::Tensor;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
-    // Putting the main code in another function serves two purposes:
-    // 1. We can use the `?` operator.
-    // 2. We can call exit safely, which does not run any destructors.
-    exit(match run() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // Putting the main code in another function lets us use the `?` operator.
+    exit(match run(&args) {
         Ok(_) => 0,
         Err(e) => {
             println!("{}", e);
@@ -29,99 +52,219 @@ fn main() {
     })
 }
 
-struct Checker {
-    success: bool,
-    epsilon: f32,
+fn usage() -> ! {
+    println!("Usage:");
+    println!("  evaluator init <weights.bin>");
+    println!("  evaluator eval <weights.bin> [moves...]");
+    println!("  evaluator model <saved_model|graph.pb> [--flip true|false] [moves...]");
+    println!("  evaluator search <weights.bin> <playouts> [moves...]");
+    println!("  evaluator train <games.csv|samples.bin> <weights.bin> [--epochs N] [--batch-size N] [--learning-rate F] [--validation F] [--augment true|false]");
+    println!("  evaluator selfplay <weights.bin|static> <samples.bin> [--games N] [--playouts N] [--random-plies N] [--temperature-plies N] [--max-plies N] [--syzygy-path P] [--eval-file P] [--book P]");
+    exit(2)
 }
 
-impl Checker {
-    fn new(epsilon: f32) -> Self {
-        Checker {
-            success: true,
-            epsilon: epsilon,
-        }
-    }
-
-    fn check(&mut self, name: &str, expected: f32, actual: f32) {
-        let success = (expected - actual).abs() < self.epsilon;
-        println!(
-            "Checking {}: expected {}, got {}. {}",
-            name,
-            expected,
-            actual,
-            if success { "Success!" } else { "FAIL" }
-        );
-        self.success &= success;
-    }
+fn source() -> Result<random::Default> {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    Ok(random::default().seed([seed, 0x5eed]))
+}
 
-    fn result(&self) -> Result<(), Box<Error>> {
-        if self.success {
+fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("init") if args.len() == 2 => {
+            Weights::random(&mut source()?).save(&args[1])?;
+            println!("Written random weights to {:?}", args[1]);
+            Ok(())
+        }
+        Some("eval") if args.len() >= 2 => {
+            let mut network = Network::load(&args[1])?;
+            let mut state = BoardState::init();
+            for m in &args[2..] {
+                state.make_move(m)?;
+            }
+            if !network.has_policy() {
+                println!("{:.4}", network.evaluate(&state)?);
+                return Ok(());
+            }
+            let (value, mut policy) = network.evaluate_with_policy(&state)?;
+            println!("{:.4}", value);
+            policy.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+            for (m, probability) in policy.iter().take(5) {
+                println!("{} {:.4}", m, probability);
+            }
+            Ok(())
+        }
+        #[cfg(feature = "tensorflow")]
+        Some("model") if args.len() >= 2 => {
+            let (flip, moves) = match args.get(2).map(String::as_str) {
+                Some("--flip") if args.len() >= 4 => (args[3].parse()?, &args[4..]),
+                _ => (true, &args[2..]),
+            };
+            let mut model = Model::load(&args[1], flip)?;
+            let mut game = vec![BoardState::init()];
+            for m in moves {
+                let mut state = game[game.len() - 1].clone();
+                state.make_move(m)?;
+                game.push(state);
+            }
+            let evaluation = model.evaluate_games(&[&game])?.remove(0);
+            println!("{:.4}", evaluation.value);
+            if let Some(mut policy) = evaluation.policy {
+                policy.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+                for (m, probability) in policy.iter().take(5) {
+                    println!("{} {:.4}", m, probability);
+                }
+            }
             Ok(())
-        } else {
-            Err(Box::new(Status::new_set(
-                Code::Internal,
-                "At least one check failed",
-            )?))
         }
+        Some("search") if args.len() >= 3 => {
+            let mut network = Network::load(&args[1])?;
+            let playouts = args[2].parse()?;
+            let mut state = BoardState::init();
+            for m in &args[3..] {
+                state.make_move(m)?;
+            }
+            let options = MctsOptions {
+                dirichlet_alpha: 0.0,
+                ..MctsOptions::default()
+            };
+            let mut mcts = Mcts::new(state, options);
+            mcts.search(&mut network, playouts, &mut source()?)?;
+            println!("{:.4}", mcts.value());
+            let mut counts = mcts.visit_counts();
+            counts.sort_by_key(|(_, visits)| std::cmp::Reverse(*visits));
+            for (m, visits) in counts.iter().take(5) {
+                println!("{} {}", m, visits);
+            }
+            Ok(())
+        }
+        Some("train") if args.len() >= 3 => train(&args[1], &args[2], &args[3..]),
+        Some("selfplay") if args.len() >= 3 => selfplay(&args[1], &args[2], &args[3..]),
+        _ => usage(),
     }
 }
 
-fn run() -> Result<(), Box<Error>> {
-    // Build the graph
-    let mut g = Graph::new();
-    let mut compiler = Compiler::new(&mut g);
+fn parse_train_options(args: &[String]) -> Result<TrainOptions> {
+    let mut options = TrainOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format_err!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--epochs" => options.epochs = value.parse()?,
+            "--batch-size" => options.batch_size = value.parse()?,
+            "--learning-rate" => options.learning_rate = value.parse()?,
+            "--validation" => options.validation = value.parse()?,
+            "--augment" => options.augment = value.parse()?,
+            _ => bail!("Unknown argument {:?}", arg),
+        }
+    }
+    Ok(options)
+}
 
-    let mut input_nodes = Vec::new();
-    let mut layers = Vec::new();
-    let mut output_nodes = Vec::new();
+fn train(input: &str, output: &str, args: &[String]) -> Result<()> {
+    let options = parse_train_options(args)?;
+    let mut source = source()?;
+    let mut weights = if Path::new(output).exists() {
+        println!("Continuing from {:?}", output);
+        Weights::load(output)?
+    } else {
+        Weights::random(&mut source)
+    };
 
-    for i in 0..5 {
-        let node = <Placeholder<f32>>::new_expr(&[2], &format!("input_{}", i));
-        let node = compiler.compile(node)?;
-        input_nodes.push(node);
+    let mut games = Vec::new();
+    let skipped = samples::read(input, options.augment, |game| games.push(game))?;
+    println!("Loaded {} games, skipped {} games", games.len(), skipped);
+    let (mut training, validation) = train::split(games, options.validation, &mut source);
+    if training.is_empty() {
+        bail!("No positions to train on");
     }
 
-    let last_layer = &mut input_nodes;
+    println!(
+        "Training on {} samples, validating on {}",
+        training.len(),
+        validation.len()
+    );
+    train::train(
+        &mut weights,
+        &mut training,
+        &validation,
+        &options,
+        &mut source,
+        |epoch, weights| {
+            weights.save(output)?;
+            println!(
+                "Epoch {}: training loss {:.5}, validation loss {:.5}, written checkpoint to {:?}",
+                epoch.number, epoch.training_loss, epoch.validation_loss, output
+            );
+            Ok(())
+        },
+    )
+}
 
-    for layer in 0..5 {
-        for i in 0..5 {
-            let node = <Placeholder<f32>>::new_expr(&[0], &format!("node_{}_{}", layer, i));
-            let mut expr = node;
-            for item in last_layer {
-                expr = expr * item * 1.0;
+fn selfplay(evaluator: &str, output: &str, args: &[String]) -> Result<()> {
+    let mut options = SelfPlayOptions::default();
+    let mut engine = Options::default();
+    let mut games = 1;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format_err!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--games" => games = value.parse()?,
+            "--playouts" => options.playouts = value.parse()?,
+            "--random-plies" => options.random_plies = value.parse()?,
+            "--temperature-plies" => options.temperature_plies = value.parse()?,
+            "--max-plies" => options.max_plies = value.parse()?,
+            "--syzygy-path" => engine.set("SyzygyPath", value)?,
+            "--eval-file" => engine.set("EvalFile", value)?,
+            "--book" => {
+                engine.set("BookFile", value)?;
+                engine.set("OwnBook", "true")?;
             }
-            let node = compiler.compile(expr);
+            _ => bail!("Unknown argument {:?}", arg),
         }
     }
-    let y_node = {
-        let x_expr = <Placeholder<f32>>::new_expr(&[2], "x");
-        compiler.compile(x_expr * 2.0f32 + 1.0f32)?
-    };
-    let x_node = g.operation_by_name_required("x")?;
-    // This is another valid way to get x_node and y_node:
-    // let (x_node, y_node) = {
-    //   let mut compiler = Compiler::new(&mut g);
-    //   let x_expr = <Placeholder<f32>>::new_expr(&vec![2], "x");
-    //   let x_node = compiler.compile(x_expr.clone())?;
-    //   let y_node = compiler.compile(x_expr * 2.0f32 + 1.0f32)?;
-    //   (x_node, y_node)
-    // };
-    let options = SessionOptions::new();
-    let mut session = Session::new(&options, &g)?;
 
-    // Evaluate the graph.
-    let mut x = <Tensor<f32>>::new(&[2]);
-    x[0] = 2.0;
-    x[1] = 3.0;
-    let mut step = SessionRunArgs::new();
-    step.add_feed(&x_node, 0, &x);
-    let output_token = step.request_fetch(&y_node, 0);
-    session.run(&mut step).unwrap();
+    if evaluator == "static" {
+        play_games(engine.static_eval(), &mut engine, output, games, &options)
+    } else {
+        play_games(
+            Network::load(evaluator)?,
+            &mut engine,
+            output,
+            games,
+            &options,
+        )
+    }
+}
 
-    // Check our results.
-    let output_tensor = step.fetch::<f32>(output_token)?;
-    let mut checker = Checker::new(std::f32::EPSILON);
-    checker.check("output_tensor[0]", 5.0, output_tensor[0]);
-    checker.check("output_tensor[1]", 7.0, output_tensor[1]);
-    checker.result()
+fn play_games(
+    mut evaluator: impl Evaluate,
+    engine: &mut Options,
+    output: &str,
+    games: usize,
+    options: &SelfPlayOptions,
+) -> Result<()> {
+    let mut source = source()?;
+    let mut writer = SampleWriter::create(output)?;
+    let mut positions = 0;
+    for i in 1..=games {
+        let game = selfplay::play_game(&mut evaluator, engine, options, &mut source)?;
+        writer.write_game(&game.samples)?;
+        writer.flush()?;
+        positions += game.samples.len();
+        let moves: Vec<String> = game.moves.iter().map(|m| m.to_string()).collect();
+        println!(
+            "Game {}: {} ({:?}) after {} plies: {}",
+            i,
+            game.result,
+            game.ending,
+            game.moves.len(),
+            moves.join(" ")
+        );
+    }
+    println!("Written {} positions to {:?}", positions, output);
+    Ok(())
 }
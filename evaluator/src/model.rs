@@ -0,0 +1,236 @@
+//! Networks trained outside of Rust, loaded from a TensorFlow SavedModel or a frozen graph.
+//!
+//! The input of the model is the encoding of `shared::features::Encoder`, as written by the
+//! `export` binary. It can be flat (`[batch, planes * 64]`), channels first (`[batch, planes, 8,
+//! 8]`) or channels last (`[batch, 8, 8, planes]`). The number of planes tells how much history the
+//! model expects. The `value` output is the result for the player to move, between -1 for a loss
+//! and 1 for a win. An optional `policy` output has the logits of the `POLICY_SIZE` moves of
+//! `shared::policy`.
+//!
+//! In a SavedModel, the tensors are found through the `serving_default` signature, or the only
+//! signature if there is one. A frozen `.pb` graph has no signatures, so its input is the only
+//! placeholder and its outputs are the operations named `value` and `policy`.
+
+use crate::graph::status;
+use crate::signature::{input_layout, parse_signatures, Layout};
+use shared::features::{
+    Encoder, EncoderConfig, CONSTANT_PLANES, EXTRA_PLANES, PLANES_PER_POSITION,
+};
+use shared::mcts::{Evaluate, Evaluation};
+use shared::policy::{self, POLICY_SIZE};
+use shared::{BoardState, CurrentPlayer, Result};
+use std::fs;
+use std::path::Path;
+use tensorflow::{
+    DataType, Graph, ImportGraphDefOptions, Operation, SavedModelBundle, Session, SessionOptions,
+    SessionRunArgs, Tensor,
+};
+
+/// The tag TensorFlow gives the graph for inference when saving a model.
+const SERVE_TAG: &str = "serve";
+const DEFAULT_SIGNATURE: &str = "serving_default";
+
+/// The operation and output index of a tensor name like `value:0`.
+fn find_tensor(graph: &Graph, name: &str) -> Result<(Operation, i32)> {
+    let (operation, index) = match name.rfind(':') {
+        Some(i) => (&name[..i], name[i + 1..].parse()?),
+        None => (name, 0),
+    };
+    let operation = graph
+        .operation_by_name(operation)?
+        .ok_or_else(|| format_err!("The graph has no operation {:?}", operation))?;
+    Ok((operation, index))
+}
+
+pub struct Model {
+    session: Session,
+    input: (Operation, i32),
+    value: (Operation, i32),
+    policy: Option<(Operation, i32)>,
+    layout: Layout,
+    pub encoder: Encoder,
+}
+
+impl Model {
+    /// Load a SavedModel directory, or a frozen graph if the path is a file. `flip` is whether
+    /// the model was trained on positions seen from the player to move, see `EncoderConfig`.
+    pub fn load(path: &str, flip: bool) -> Result<Model> {
+        let mut graph = Graph::new();
+        let (session, input, value, policy, dims) = if Path::new(path).is_dir() {
+            let bundle =
+                SavedModelBundle::load(&SessionOptions::new(), [SERVE_TAG], &mut graph, path)
+                    .map_err(status)?;
+            let signatures = parse_signatures(&bundle.meta_graph_def)?;
+            let signature = match signatures.iter().find(|s| s.key == DEFAULT_SIGNATURE) {
+                Some(signature) => signature,
+                None if signatures.len() == 1 => &signatures[0],
+                None => bail!(
+                    "No {:?} signature in {:?}, found {:?}",
+                    DEFAULT_SIGNATURE,
+                    path,
+                    signatures.iter().map(|s| &s.key).collect::<Vec<_>>()
+                ),
+            };
+            if signature.inputs.len() != 1 {
+                bail!("Expected a single input, got {}", signature.inputs.len());
+            }
+            let output = |key: &str| signature.outputs.iter().find(|o| o.key == key);
+            let value = match (output("value"), signature.outputs.len()) {
+                (Some(value), _) => value,
+                (None, 1) => &signature.outputs[0],
+                _ => bail!("The signature has no \"value\" output"),
+            };
+            let input = &signature.inputs[0];
+            (
+                bundle.session,
+                find_tensor(&graph, &input.name)?,
+                find_tensor(&graph, &value.name)?,
+                output("policy")
+                    .map(|policy| find_tensor(&graph, &policy.name))
+                    .transpose()?,
+                input.dims.clone(),
+            )
+        } else {
+            graph
+                .import_graph_def(&fs::read(path)?, &ImportGraphDefOptions::new())
+                .map_err(status)?;
+            let placeholders: Vec<Operation> = graph
+                .operation_iter()
+                .filter(|o| o.op_type().map(|t| t == "Placeholder").unwrap_or(false))
+                .collect();
+            if placeholders.len() != 1 {
+                bail!(
+                    "Expected a single placeholder as input, found {}",
+                    placeholders.len()
+                );
+            }
+            let input = (placeholders[0].clone(), 0);
+            let session = Session::new(&SessionOptions::new(), &graph).map_err(status)?;
+            (
+                session,
+                input,
+                find_tensor(&graph, "value")?,
+                graph.operation_by_name("policy")?.map(|o| (o, 0)),
+                None,
+            )
+        };
+
+        if input.0.output_type(input.1 as usize) != DataType::Float {
+            bail!("The input should be a float tensor");
+        }
+        // Signatures may leave out the shape, the graph knows it too.
+        let dims = match dims {
+            Some(dims) => dims,
+            None => {
+                let shape = graph
+                    .tensor_shape(tensorflow::Output {
+                        operation: input.0.clone(),
+                        index: input.1,
+                    })
+                    .map_err(status)?;
+                let rank = shape
+                    .dims()
+                    .ok_or_else(|| format_err!("The rank of the input is unknown"))?;
+                (0..rank).map(|i| shape[i].unwrap_or(-1)).collect()
+            }
+        };
+        if dims.is_empty() {
+            bail!("The input has no batch dimension");
+        }
+        let (layout, planes) = input_layout(&dims[1..])?;
+        let history = (planes - CONSTANT_PLANES - EXTRA_PLANES) / PLANES_PER_POSITION;
+        Ok(Model {
+            session,
+            input,
+            value,
+            policy,
+            layout,
+            encoder: Encoder::new(EncoderConfig { history, flip }),
+        })
+    }
+
+    pub fn has_policy(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Evaluate the last position of every game, with the positions before it as history.
+    pub fn evaluate_games(&mut self, games: &[&[BoardState]]) -> Result<Vec<Evaluation>> {
+        if games.is_empty() {
+            return Ok(Vec::new());
+        }
+        let len = self.encoder.len();
+        let planes = self.encoder.planes();
+        let mut features = Vec::with_capacity(games.len() * len);
+        for game in games {
+            if game.is_empty() {
+                bail!("Can't evaluate a game without positions");
+            }
+            let start = features.len();
+            self.encoder.encode_into(game, &mut features);
+            if self.layout == Layout::ChannelsLast {
+                let encoded = features[start..].to_vec();
+                for (i, value) in encoded.iter().enumerate() {
+                    features[start + i % 64 * planes + i / 64] = *value;
+                }
+            }
+        }
+        let dims: Vec<u64> = match self.layout {
+            Layout::Flat => vec![games.len() as u64, len as u64],
+            Layout::ChannelsFirst => vec![games.len() as u64, planes as u64, 8, 8],
+            Layout::ChannelsLast => vec![games.len() as u64, 8, 8, planes as u64],
+        };
+        let input = Tensor::new(&dims).with_values(&features).map_err(status)?;
+
+        let mut step = SessionRunArgs::new();
+        step.add_feed(&self.input.0, self.input.1, &input);
+        let value_token = step.request_fetch(&self.value.0, self.value.1);
+        let policy_token = self
+            .policy
+            .as_ref()
+            .map(|(operation, index)| step.request_fetch(operation, *index));
+        self.session.run(&mut step).map_err(status)?;
+        let values = step.fetch::<f32>(value_token).map_err(status)?;
+        let logits = match policy_token {
+            Some(token) => Some(step.fetch::<f32>(token).map_err(status)?),
+            None => None,
+        };
+        if values.len() != games.len() {
+            bail!("Expected {} values, got {}", games.len(), values.len());
+        }
+        if let Some(logits) = &logits {
+            if logits.len() != games.len() * POLICY_SIZE {
+                bail!(
+                    "Expected {} policy logits per position, got {}",
+                    POLICY_SIZE,
+                    logits.len() / games.len()
+                );
+            }
+        }
+
+        Ok(games
+            .iter()
+            .enumerate()
+            .map(|(i, game)| {
+                let state = &game[game.len() - 1];
+                // From the player to move between -1 and 1, to the probability that white wins.
+                let wins = (values[i].clamp(-1.0, 1.0) + 1.0) / 2.0;
+                let value = match state.current_player {
+                    CurrentPlayer::White => wins,
+                    CurrentPlayer::Black => 1.0 - wins,
+                };
+                let policy = logits.as_ref().map(|logits| {
+                    policy::masked_softmax(state, &logits[i * POLICY_SIZE..(i + 1) * POLICY_SIZE])
+                });
+                Evaluation { value, policy }
+            })
+            .collect())
+    }
+}
+
+/// Positions in the search tree have no history, so they are evaluated on their own.
+impl Evaluate for Model {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<Evaluation>> {
+        let games: Vec<&[BoardState]> = states.iter().map(std::slice::from_ref).collect();
+        self.evaluate_games(&games)
+    }
+}
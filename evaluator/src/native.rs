@@ -0,0 +1,183 @@
+//! The value network in plain Rust, for the `native` feature.
+//!
+//! This does the same as the TensorFlow graph of `graph::GraphNetwork`, without needing the native
+//! libtensorflow. Most inputs of `BoardState::to_piece_vec()` are 0, and so are many outputs of a
+//! ReLU, so rows of the weights are only added for the inputs that are not.
+
+use crate::network::{self, Evaluator};
+use crate::weights::{Layer, Weights};
+use shared::mcts::{Evaluate, Evaluation};
+use shared::policy::{self, Policy};
+use shared::{BoardState, Result};
+
+/// A fully connected layer, without an activation.
+fn dense(layer: &Layer, input: &[f32]) -> Vec<f32> {
+    let mut output = layer.biases.clone();
+    for (i, value) in input.iter().enumerate() {
+        if *value == 0.0 {
+            continue;
+        }
+        let row = i * layer.outputs;
+        for (o, w) in output
+            .iter_mut()
+            .zip(&layer.weights[row..row + layer.outputs])
+        {
+            *o += value * w;
+        }
+    }
+    output
+}
+
+pub struct NativeNetwork {
+    weights: Weights,
+}
+
+impl NativeNetwork {
+    pub fn new(weights: &Weights) -> Result<NativeNetwork> {
+        if weights.layers.is_empty() || weights.layers.last().unwrap().outputs != 1 {
+            bail!("The network should end in a layer with a single output");
+        }
+        Ok(NativeNetwork {
+            weights: weights.clone(),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<NativeNetwork> {
+        NativeNetwork::new(&Weights::load(path)?)
+    }
+
+    /// The value of the position and, if asked for, the policy logits.
+    fn run(&self, state: &BoardState, with_policy: bool) -> (f32, Option<Vec<f32>>) {
+        let mut last = state.to_piece_vec();
+        let mut logits = None;
+        for (i, layer) in self.weights.layers.iter().enumerate() {
+            let is_last = i + 1 == self.weights.layers.len();
+            if let (true, true, Some(head)) = (is_last, with_policy, &self.weights.policy) {
+                // The policy head reads the output of the last hidden layer.
+                logits = Some(dense(head, &last));
+            }
+            last = dense(layer, &last);
+            if !is_last {
+                last.iter_mut().for_each(|x| *x = x.max(0.0));
+            }
+        }
+        (1.0 / (1.0 + (-last[0]).exp()), logits)
+    }
+}
+
+impl Evaluator for NativeNetwork {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<f32>> {
+        Ok(states
+            .iter()
+            .map(|state| self.run(state, false).0)
+            .collect())
+    }
+
+    fn has_policy(&self) -> bool {
+        self.weights.policy.is_some()
+    }
+
+    fn evaluate_batch_with_policy(&mut self, states: &[BoardState]) -> Result<Vec<(f32, Policy)>> {
+        if !self.has_policy() {
+            bail!("The network has no policy head");
+        }
+        Ok(states
+            .iter()
+            .map(|state| {
+                let (value, logits) = self.run(state, true);
+                (value, policy::masked_softmax(state, &logits.unwrap()))
+            })
+            .collect())
+    }
+}
+
+/// Lets the network guide `shared::mcts::Mcts`, with its policy head if it has one.
+impl Evaluate for NativeNetwork {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<Evaluation>> {
+        network::evaluations(self, states)
+    }
+}
+
+/// Positions from the start, the middle and the end of a game, with both players to move.
+#[cfg(test)]
+fn test_positions() -> Vec<BoardState> {
+    let mut state = BoardState::init();
+    let mut result = vec![state.clone()];
+    for m in &[
+        "e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Bxc6", "dxc6", "O-O", "f6", "d4", "exd4", "Nxd4",
+        "c5", "Nb3", "Qxd1", "Rxd1",
+    ] {
+        state.make_move(m).unwrap();
+        result.push(state.clone());
+    }
+    result
+}
+
+#[test]
+fn test_matches_training_forward_pass() {
+    let weights = Weights::random(&mut random::default().seed([4, 2]));
+    let mut network = NativeNetwork::new(&weights).unwrap();
+    let states = test_positions();
+    let values = Evaluator::evaluate_batch(&mut network, &states).unwrap();
+    for (state, value) in states.iter().zip(values) {
+        let sample = crate::samples::Sample::new(state, 0.5);
+        assert!((crate::train::evaluate(&weights, &sample) - value).abs() < 1e-6);
+    }
+}
+
+#[cfg(feature = "tensorflow")]
+#[test]
+fn test_matches_tensorflow() {
+    let weights = Weights::random(&mut random::default().seed([4, 2]));
+    let mut native = NativeNetwork::new(&weights).unwrap();
+    let mut graph = crate::graph::GraphNetwork::new(&weights).unwrap();
+    let states = test_positions();
+    let expected = graph.evaluate_batch_with_policy(&states).unwrap();
+    let actual = native.evaluate_batch_with_policy(&states).unwrap();
+    for ((value, policy), (expected_value, expected_policy)) in actual.iter().zip(&expected) {
+        assert!((value - expected_value).abs() < 1e-5);
+        assert_eq!(expected_policy.len(), policy.len());
+        for ((m, p), (expected_m, expected_p)) in policy.iter().zip(expected_policy) {
+            assert_eq!(expected_m, m);
+            assert!((p - expected_p).abs() < 1e-5);
+        }
+    }
+}
+
+/// `fixtures/native.bin` is a small network with a policy head, and the values and move
+/// probabilities were worked out from its weights outside of Rust. This checks the native network
+/// without TensorFlow, which `test_matches_tensorflow` needs.
+#[test]
+fn test_fixture() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/native.bin");
+    let mut network = NativeNetwork::load(path).unwrap();
+    // The value, and the probability of a move, of every test position.
+    let expected = [
+        (0.598415, "d2d4", 0.122796),
+        (0.576751, "d7d5", 0.111691),
+        (0.534992, "d1e2", 0.123421),
+        (0.579876, "d8e7", 0.103592),
+        (0.699229, "f1b5", 0.104659),
+        (0.711769, "c6d4", 0.102034),
+        (0.723566, "d2d4", 0.094645),
+        (0.641268, "f8b4", 0.093406),
+        (0.619327, "d1e2", 0.099579),
+        (0.709875, "c8f5", 0.091505),
+        (0.670743, "d2d4", 0.114176),
+        (0.759694, "f8b4", 0.090400),
+        (0.733953, "c1f4", 0.078980),
+        (0.741276, "f8b4", 0.087993),
+        (0.761760, "c1f4", 0.076946),
+        (0.684251, "c8f5", 0.064206),
+        (0.642839, "b3c5", 0.074183),
+        (0.643973, "c8f5", 0.095374),
+    ];
+    let states = test_positions();
+    assert_eq!(expected.len(), states.len());
+    let actual = network.evaluate_batch_with_policy(&states).unwrap();
+    for ((value, policy), (expected_value, m, p)) in actual.iter().zip(&expected) {
+        assert!((value - expected_value).abs() < 1e-5);
+        let (_, probability) = policy.iter().find(|(n, _)| n.to_string() == *m).unwrap();
+        assert!((probability - p).abs() < 1e-5, "{}", m);
+    }
+}
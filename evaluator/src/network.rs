@@ -0,0 +1,57 @@
+//! What the backends that run the value network have in common.
+//!
+//! `graph::GraphNetwork` runs the network with TensorFlow, and needs the native libtensorflow.
+//! `native::NativeNetwork` runs it in plain Rust. Both give the same outputs for the same weights.
+//! The backend is picked with the cargo features `tensorflow` (the default) and `native`, and is
+//! then available as `evaluator::Network`.
+
+use shared::mcts::Evaluation;
+use shared::policy::Policy;
+use shared::{BoardState, Result};
+
+pub trait Evaluator {
+    /// Evaluate many positions at once, which is a lot faster than one by one. The value of a
+    /// position is the probability that white wins it, with a draw counting as half a win.
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<f32>>;
+
+    fn has_policy(&self) -> bool;
+
+    /// Like `evaluate_batch`, with the probabilities of the legal moves of every position. The
+    /// network needs a policy head.
+    fn evaluate_batch_with_policy(&mut self, states: &[BoardState]) -> Result<Vec<(f32, Policy)>>;
+
+    fn evaluate(&mut self, state: &BoardState) -> Result<f32> {
+        Ok(self.evaluate_batch(std::slice::from_ref(state))?[0])
+    }
+
+    /// The value of the position, and the probability of every legal move.
+    fn evaluate_with_policy(&mut self, state: &BoardState) -> Result<(f32, Policy)> {
+        let mut result = self.evaluate_batch_with_policy(std::slice::from_ref(state))?;
+        Ok(result.remove(0))
+    }
+}
+
+/// The evaluations `shared::mcts::Mcts` asks for, with the policy head if the network has one.
+pub(crate) fn evaluations(
+    network: &mut impl Evaluator,
+    states: &[BoardState],
+) -> Result<Vec<Evaluation>> {
+    if !network.has_policy() {
+        return Ok(network
+            .evaluate_batch(states)?
+            .into_iter()
+            .map(|value| Evaluation {
+                value,
+                policy: None,
+            })
+            .collect());
+    }
+    Ok(network
+        .evaluate_batch_with_policy(states)?
+        .into_iter()
+        .map(|(value, policy)| Evaluation {
+            value,
+            policy: Some(policy),
+        })
+        .collect())
+}
@@ -0,0 +1,356 @@
+//! Training samples for the network, from the games in `games.csv` or from a samples file.
+//!
+//! A samples file starts with the magic `b"CSMP"` and a version (`u32`). Every game then has the
+//! number of its samples (`u32`), and every sample the number of features (`u16`) and their indices
+//! (`u16`), the result (`f32`), the number of moves in the policy (`u16`) and for every move its
+//! index (`u16`) and probability (`f32`). All numbers are little endian. The file ends after the
+//! last game. Version 1 files have no games, only samples, and are read with every sample as a game
+//! of its own.
+
+use shared::policy;
+use shared::{BoardState, Move, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+const MAGIC: &[u8; 4] = b"CSMP";
+const VERSION: u32 = 2;
+
+const COLUMN_WINNER: usize = 6;
+const COLUMN_MOVES: usize = 12;
+
+/// A position with the result of the game it was played in, and the moves that should be played
+/// in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The indices of the features in `BoardState::to_piece_vec()` that are 1. All others are 0.
+    pub features: Vec<u16>,
+    /// 1.0 if white won, 0.5 for a draw and 0.0 if black won.
+    pub result: f32,
+    /// The probability of moves by their index in `shared::policy`. For a played game this is the
+    /// move that was played, for self-play the visits of the search. Empty for the last position of
+    /// a game.
+    pub policy: Vec<(u16, f32)>,
+}
+
+impl Sample {
+    pub fn new(state: &BoardState, result: f32) -> Sample {
+        let features = state
+            .to_piece_vec()
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| **value > 0.0)
+            .map(|(index, _)| index as u16)
+            .collect();
+        Sample {
+            features,
+            result,
+            policy: Vec::new(),
+        }
+    }
+
+    /// The full feature vector, as `BoardState::to_piece_vec()` would return it.
+    pub fn to_vec(&self, inputs: usize) -> Vec<f32> {
+        let mut result = vec![0.0; inputs];
+        for index in &self.features {
+            result[usize::from(*index)] = 1.0;
+        }
+        result
+    }
+}
+
+/// The samples of the positions of one game. Games are kept together, so the positions of a game
+/// (and the `augmented` samples made from them) end up on one side of the validation split.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameSamples {
+    pub samples: Vec<Sample>,
+    /// The `augmented` samples of the positions, to train on but not to validate with.
+    pub augmented: Vec<Sample>,
+}
+
+/// More samples from the same position: with the colours flipped, and mirrored from the a-file to
+/// the h-file if neither player can castle. The games in `games.csv` are played from white's side
+/// more often than not, these balance that out.
+pub fn augmented(state: &BoardState, sample: &Sample) -> Vec<Sample> {
+    let flip = |state: &BoardState, sample: &Sample| {
+        let mut flipped = Sample::new(&state.flipped(), 1.0 - sample.result);
+        // Moves are numbered from the view of the player to move, so they stay the same.
+        flipped.policy = sample.policy.clone();
+        flipped
+    };
+    let mut result = vec![flip(state, sample)];
+    if let Some(mirrored) = state.mirrored() {
+        let mut mirrored_sample = Sample::new(&mirrored, sample.result);
+        mirrored_sample.policy = sample
+            .policy
+            .iter()
+            .map(|(index, p)| (policy::mirror_index(usize::from(*index)) as u16, *p))
+            .collect();
+        result.push(flip(&mirrored, &mirrored_sample));
+        result.push(mirrored_sample);
+    }
+    result
+}
+
+/// Replay every game in the csv file, and call `f` with a sample for every position in it,
+/// including the initial position. With `augment`, the `augmented` samples are made as well.
+/// Returns the number of games that were skipped because they could not be replayed.
+pub fn read_games_csv(path: &str, augment: bool, mut f: impl FnMut(GameSamples)) -> Result<usize> {
+    let mut parser = csv::Reader::from_path(path)?;
+    let mut skipped = 0;
+
+    for record in parser.records() {
+        let record = record?;
+        let result = match record.get(COLUMN_WINNER) {
+            Some("white") => 1.0,
+            Some("black") => 0.0,
+            Some("draw") => 0.5,
+            _ => {
+                skipped += 1;
+                continue;
+            }
+        };
+        let moves = record.get(COLUMN_MOVES).unwrap_or_default();
+        let mut game = GameSamples::default();
+        let mut replay = || -> Result<()> {
+            let mut state = BoardState::init();
+            let mut add = |state: &BoardState, sample: Sample| {
+                if augment {
+                    game.augmented.extend(augmented(state, &sample));
+                }
+                game.samples.push(sample);
+            };
+            for m in moves.split(' ') {
+                let mut sample = Sample::new(&state, result);
+                let before = state.clone();
+                state.make_move(m)?;
+                let played = Move::between(&before, &state)?;
+                if let Some(index) = policy::move_index(before.current_player, played) {
+                    sample.policy.push((index as u16, 1.0));
+                }
+                add(&before, sample);
+            }
+            add(&state, Sample::new(&state, result));
+            Ok(())
+        };
+        // Only use games that could be replayed completely, a partial game doesn't match its result.
+        match replay() {
+            Ok(()) => f(game),
+            Err(_) => skipped += 1,
+        }
+    }
+    Ok(skipped)
+}
+
+/// Writes the samples of games to a samples file, as they are played.
+pub struct SampleWriter {
+    writer: BufWriter<File>,
+}
+
+impl SampleWriter {
+    pub fn create(path: &str) -> Result<SampleWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(SampleWriter { writer })
+    }
+
+    /// Write the samples of the positions of one game.
+    pub fn write_game(&mut self, samples: &[Sample]) -> Result<()> {
+        self.writer
+            .write_all(&(samples.len() as u32).to_le_bytes())?;
+        for sample in samples {
+            self.write(sample)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, sample: &Sample) -> Result<()> {
+        let w = &mut self.writer;
+        w.write_all(&(sample.features.len() as u16).to_le_bytes())?;
+        for feature in &sample.features {
+            w.write_all(&feature.to_le_bytes())?;
+        }
+        w.write_all(&sample.result.to_le_bytes())?;
+        w.write_all(&(sample.policy.len() as u16).to_le_bytes())?;
+        for (index, probability) in &sample.policy {
+            w.write_all(&index.to_le_bytes())?;
+            w.write_all(&probability.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// `None` for the end of the file, which `read` reached before reading anything.
+fn or_end<T>(value: Result<T>) -> Result<Option<T>> {
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => match e.downcast_ref::<std::io::Error>() {
+            Some(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            _ => Err(e),
+        },
+    }
+}
+
+fn read_sample(reader: &mut impl Read, features: u16) -> Result<Sample> {
+    let features = (0..features)
+        .map(|_| read_u16(reader))
+        .collect::<Result<_>>()?;
+    let result = read_f32(reader)?;
+    let moves = read_u16(reader)?;
+    let policy = (0..moves)
+        .map(|_| Ok((read_u16(reader)?, read_f32(reader)?)))
+        .collect::<Result<_>>()?;
+    Ok(Sample {
+        features,
+        result,
+        policy,
+    })
+}
+
+/// Call `f` with the samples of every game in a samples file. Returns the number of games.
+pub fn read_samples(path: &str, mut f: impl FnMut(GameSamples)) -> Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        bail!("{:?} is not a samples file", path);
+    }
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    let mut count = 0;
+    loop {
+        let samples = match version {
+            1 => match or_end(read_u16(&mut reader))? {
+                Some(features) => vec![read_sample(&mut reader, features)?],
+                None => return Ok(count),
+            },
+            2 => match or_end(read_u32(&mut reader))? {
+                Some(samples) => (0..samples)
+                    .map(|_| {
+                        let features = read_u16(&mut reader)?;
+                        read_sample(&mut reader, features)
+                    })
+                    .collect::<Result<_>>()?,
+                None => return Ok(count),
+            },
+            _ => bail!("Unsupported samples version {}", version),
+        };
+        f(GameSamples {
+            samples,
+            augmented: Vec::new(),
+        });
+        count += 1;
+    }
+}
+
+/// Read the games of `games.csv` if the path ends with `.csv`, and the games of a samples file
+/// otherwise. Samples files are read as they are, as the positions they come from are not in them.
+/// Returns the number of games that were skipped.
+pub fn read(path: &str, augment: bool, f: impl FnMut(GameSamples)) -> Result<usize> {
+    if path.ends_with(".csv") {
+        read_games_csv(path, augment, f)
+    } else {
+        read_samples(path, f).map(|_| 0)
+    }
+}
+
+#[test]
+fn test_sample_file_roundtrip() {
+    let mut state = BoardState::init();
+    state.make_move("e4").unwrap();
+    let mut sample = Sample::new(&state, 1.0);
+    sample.policy = vec![(12, 0.25), (4000, 0.75)];
+    let game = |samples| GameSamples {
+        samples,
+        augmented: Vec::new(),
+    };
+    let games = vec![
+        game(vec![Sample::new(&BoardState::init(), 0.5), sample]),
+        game(Vec::new()),
+        game(vec![Sample::new(&state, 0.0)]),
+    ];
+
+    let path = std::env::temp_dir().join(format!("samples-{}.bin", std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut writer = SampleWriter::create(path).unwrap();
+    for game in &games {
+        writer.write_game(&game.samples).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+
+    let mut read = Vec::new();
+    assert_eq!(3, read_samples(path, |game| read.push(game)).unwrap());
+    assert_eq!(games, read);
+
+    // A version 1 file has the samples without the games.
+    let mut writer = SampleWriter::create(path).unwrap();
+    writer.write_game(&games[0].samples).unwrap();
+    drop(writer);
+    let mut bytes = std::fs::read(path).unwrap();
+    bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+    bytes.drain(8..12);
+    std::fs::write(path, bytes).unwrap();
+    let mut read = Vec::new();
+    assert_eq!(2, read_samples(path, |game| read.push(game)).unwrap());
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(games[0].samples[..1], read[0].samples[..]);
+    assert_eq!(1, read[1].samples.len());
+}
+
+#[test]
+fn test_sample_features() {
+    let state = BoardState::init();
+    let sample = Sample::new(&state, 0.5);
+    assert_eq!(32, sample.features.len());
+    assert_eq!(state.to_piece_vec(), sample.to_vec(crate::weights::INPUTS));
+}
+
+#[test]
+fn test_augmented_samples() {
+    let mut state = BoardState::init();
+    let mut sample = Sample::new(&state, 1.0);
+    sample.policy = vec![(64 + 12, 1.0)];
+    // With castling rights there is only the flipped position.
+    let samples = augmented(&state, &sample);
+    assert_eq!(1, samples.len());
+    assert_eq!(0.0, samples[0].result);
+    assert_eq!(sample.policy, samples[0].policy);
+    assert_eq!(
+        Sample::new(&state.flipped(), 0.0).features,
+        samples[0].features
+    );
+
+    for m in &["e4", "e5", "Ke2", "Ke7"] {
+        state.make_move(m).unwrap();
+    }
+    let samples = augmented(&state, &sample);
+    assert_eq!(3, samples.len());
+    // e2e4 becomes d2d4 in the mirrored positions.
+    assert_eq!(vec![(64 + 11, 1.0)], samples[2].policy);
+    assert_eq!(1.0, samples[2].result);
+    assert_eq!(0.0, samples[1].result);
+}
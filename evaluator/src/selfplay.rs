@@ -0,0 +1,320 @@
+//! Self-play, to make training samples beyond the games in `games.csv`.
+//!
+//! The engine plays both sides with `shared::mcts::Mcts`, keeping the tree between moves. A game
+//! starts with a few random moves, so no two games are the same, and the moves after that are
+//! picked in proportion to their visits for a while before always playing the most visited move.
+//! Every position after the random opening becomes a sample with the visits of the search as its
+//! policy, and gets the result of the game once it is over.
+//!
+//! With `OwnBook` set in the engine options, positions in the opening book are played from the
+//! book without a search, and don't become samples.
+
+use crate::samples::Sample;
+use random::Source;
+use shared::mcts::{Evaluate, Mcts, MctsOptions};
+use shared::options::Options;
+use shared::syzygy::{Tablebases, Wdl};
+use shared::{policy, zobrist, BoardState, CurrentPlayer, Move, PieceKind, Result};
+
+#[derive(Debug, Clone)]
+pub struct SelfPlayOptions {
+    /// The number of playouts per move, including those kept from the search of the move before.
+    pub playouts: usize,
+    /// The number of random moves at the start of the game. These don't become samples.
+    pub random_plies: usize,
+    /// The number of moves after the random opening that are picked in proportion to their visits.
+    pub temperature_plies: usize,
+    /// A player resigns when the value of their position stays below this for `resign_moves` of
+    /// their moves in a row.
+    pub resign_value: f32,
+    pub resign_moves: usize,
+    /// Games that take longer than this are a draw.
+    pub max_plies: usize,
+    pub mcts: MctsOptions,
+}
+
+impl Default for SelfPlayOptions {
+    fn default() -> SelfPlayOptions {
+        SelfPlayOptions {
+            playouts: 400,
+            random_plies: 4,
+            temperature_plies: 30,
+            resign_value: -0.95,
+            resign_moves: 3,
+            max_plies: 500,
+            mcts: MctsOptions::default(),
+        }
+    }
+}
+
+/// How a game ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ending {
+    Checkmate,
+    Stalemate,
+    /// The tablebases know the result of the position.
+    Tablebase,
+    Resignation,
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+    MaxLength,
+}
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub moves: Vec<Move>,
+    /// 1.0 if white won, 0.5 for a draw and 0.0 if black won.
+    pub result: f32,
+    pub ending: Ending,
+    pub samples: Vec<Sample>,
+}
+
+/// The result for white when `player` wins.
+fn win_for(player: CurrentPlayer) -> f32 {
+    match player {
+        CurrentPlayer::White => 1.0,
+        CurrentPlayer::Black => 0.0,
+    }
+}
+
+/// Only kings, or kings and a single knight or bishop.
+fn insufficient_material(state: &BoardState) -> bool {
+    let mut minors = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            match state.get_piece(x, y).kind() {
+                None | Some(PieceKind::King) => {}
+                Some(PieceKind::Knight) | Some(PieceKind::Bishop) => minors += 1,
+                _ => return false,
+            }
+        }
+    }
+    minors <= 1
+}
+
+/// The ending of the game and its result, if it is over before the player to move picks a move.
+fn adjudicate(
+    state: &BoardState,
+    history: &[u64],
+    no_progress: usize,
+    tablebases: Option<&mut Tablebases>,
+) -> Option<(Ending, f32)> {
+    let player = state.current_player;
+    if state.legal_moves().is_empty() {
+        return Some(if state.is_in_check(player) {
+            (Ending::Checkmate, win_for(player.opponent()))
+        } else {
+            (Ending::Stalemate, 0.5)
+        });
+    }
+    if insufficient_material(state) {
+        return Some((Ending::InsufficientMaterial, 0.5));
+    }
+    let hash = zobrist::hash(state);
+    if history.iter().filter(|h| **h == hash).count() >= 3 {
+        return Some((Ending::Repetition, 0.5));
+    }
+    if no_progress >= 100 {
+        return Some((Ending::FiftyMoves, 0.5));
+    }
+    if let Some(tablebases) = tablebases {
+        if tablebases.can_probe(state) {
+            // Cursed wins and blessed losses are draws with the 50 move rule.
+            let result = match tablebases.probe_wdl(state).ok()? {
+                Wdl::Win => win_for(player),
+                Wdl::Loss => win_for(player.opponent()),
+                _ => 0.5,
+            };
+            return Some((Ending::Tablebase, result));
+        }
+    }
+    None
+}
+
+/// Play a move, keeping the positions so far and the number of plies without a capture or pawn
+/// move.
+fn play(
+    state: &mut BoardState,
+    m: Move,
+    history: &mut Vec<u64>,
+    no_progress: &mut usize,
+) -> Result<()> {
+    let pawn = state.get_piece(m.from.0, m.from.1).kind() == Some(PieceKind::Pawn);
+    *no_progress = if pawn || state.is_capture(m) {
+        0
+    } else {
+        *no_progress + 1
+    };
+    state.apply_move(m)?;
+    history.push(zobrist::hash(state));
+    Ok(())
+}
+
+/// Play a game of the evaluator against itself, with the opening book and tablebases of `engine`.
+pub fn play_game(
+    evaluator: &mut impl Evaluate,
+    engine: &mut Options,
+    options: &SelfPlayOptions,
+    source: &mut impl Source,
+) -> Result<Game> {
+    let mut state = BoardState::init();
+    let mut moves = Vec::new();
+    let mut history = vec![zobrist::hash(&state)];
+    let mut no_progress = 0;
+
+    while moves.len() < options.random_plies {
+        let legal = state.legal_moves();
+        if legal.is_empty() {
+            break;
+        }
+        let m = legal[(source.read_u64() % legal.len() as u64) as usize];
+        play(&mut state, m, &mut history, &mut no_progress)?;
+        moves.push(m);
+    }
+
+    let mut mcts = Mcts::new(state.clone(), options.mcts.clone());
+    let mut samples = Vec::new();
+    let mut losing_moves = [0, 0];
+    let (ending, result) = loop {
+        if let Some(ending) = adjudicate(&state, &history, no_progress, engine.tablebases()) {
+            break ending;
+        }
+        if moves.len() >= options.max_plies {
+            break (Ending::MaxLength, 0.5);
+        }
+
+        if let Some(m) = engine.book_move(&state, source) {
+            play(&mut state, m, &mut history, &mut no_progress)?;
+            moves.push(m);
+            mcts.advance(m)?;
+            continue;
+        }
+
+        let playouts = options.playouts.saturating_sub(mcts.visits() as usize);
+        mcts.search_with(evaluator, engine, playouts.max(1), source)?;
+
+        let player = state.current_player;
+        let losing = &mut losing_moves[player as usize];
+        *losing = if mcts.value() < options.resign_value {
+            *losing + 1
+        } else {
+            0
+        };
+        if options.resign_moves > 0 && *losing >= options.resign_moves {
+            break (Ending::Resignation, win_for(player.opponent()));
+        }
+
+        let mut sample = Sample::new(&state, 0.5);
+        sample.policy = mcts
+            .policy(1.0)
+            .into_iter()
+            .filter_map(|(m, p)| policy::move_index(player, m).map(|i| (i as u16, p)))
+            .collect();
+        samples.push(sample);
+
+        let searched = moves.len() - options.random_plies.min(moves.len());
+        let temperature = if searched < options.temperature_plies {
+            1.0
+        } else {
+            0.0
+        };
+        let m = match mcts.select_move(temperature, source) {
+            Some(m) => m,
+            None => bail!("The search found no move in a position with legal moves"),
+        };
+        play(&mut state, m, &mut history, &mut no_progress)?;
+        moves.push(m);
+        mcts.advance(m)?;
+    };
+
+    samples.push(Sample::new(&state, 0.5));
+    for sample in &mut samples {
+        sample.result = result;
+    }
+    Ok(Game {
+        moves,
+        result,
+        ending,
+        samples,
+    })
+}
+
+#[test]
+fn test_insufficient_material() {
+    assert!(!insufficient_material(&BoardState::init()));
+    assert!(insufficient_material(&BoardState::empty()));
+}
+
+#[test]
+fn test_play_game() {
+    use shared::evaluation::StaticEval;
+    let mut source = random::default().seed([7, 7]);
+    let options = SelfPlayOptions {
+        playouts: 16,
+        max_plies: 12,
+        ..SelfPlayOptions::default()
+    };
+    let mut engine = Options::default();
+    let game = play_game(
+        &mut StaticEval::default(),
+        &mut engine,
+        &options,
+        &mut source,
+    )
+    .unwrap();
+    assert_eq!(Ending::MaxLength, game.ending);
+    assert_eq!(12, game.moves.len());
+    // A sample for every searched position, and for the last position.
+    assert_eq!(12 - 4 + 1, game.samples.len());
+    for sample in &game.samples[..game.samples.len() - 1] {
+        let total: f32 = sample.policy.iter().map(|(_, p)| p).sum();
+        assert!((total - 1.0).abs() < 1e-4);
+        assert_eq!(0.5, sample.result);
+    }
+    assert!(game.samples.last().unwrap().policy.is_empty());
+}
+
+#[cfg(test)]
+struct CountingEval(usize);
+
+#[cfg(test)]
+impl Evaluate for CountingEval {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<shared::mcts::Evaluation>> {
+        self.0 += states.len();
+        shared::evaluation::StaticEval::default().evaluate_batch(states)
+    }
+}
+
+#[test]
+fn test_book_moves_skip_the_search() {
+    use shared::polyglot::BookBuilder;
+    let mut builder = BookBuilder::new();
+    builder.add_game(vec!["e4", "e5", "Nf3"], None, 10).unwrap();
+    let path = std::env::temp_dir().join(format!("selfplay-{}.bin", std::process::id()));
+    builder.build(1).save(path.to_str().unwrap()).unwrap();
+    let mut engine = Options::default();
+    engine.set("BookFile", path.to_str().unwrap()).unwrap();
+    engine.set("OwnBook", "true").unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut source = random::default().seed([8, 8]);
+    let options = SelfPlayOptions {
+        playouts: 16,
+        random_plies: 0,
+        max_plies: 4,
+        ..SelfPlayOptions::default()
+    };
+    let mut eval = CountingEval(0);
+    let game = play_game(&mut eval, &mut engine, &options, &mut source).unwrap();
+    let moves: Vec<String> = game.moves.iter().map(|m| m.to_string()).collect();
+    assert_eq!(vec!["e2e4", "e7e5", "g1f3"], moves[..3].to_vec());
+    // Only the position after the book was searched.
+    assert_eq!(2, game.samples.len());
+    assert!(eval.0 > 0 && eval.0 <= options.playouts);
+
+    // Out of the book, or without OwnBook, every position is searched.
+    engine.set("OwnBook", "false").unwrap();
+    let game = play_game(&mut eval, &mut engine, &options, &mut source).unwrap();
+    assert_eq!(5, game.samples.len());
+}
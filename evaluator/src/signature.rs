@@ -0,0 +1,291 @@
+//! The signatures and input shapes of models trained outside of Rust.
+//!
+//! `model` needs these to find its tensors, but they are read from the bytes of a `MetaGraphDef`
+//! and the dimensions of a shape, so they don't need TensorFlow.
+
+use shared::features::{
+    Encoder, EncoderConfig, CONSTANT_PLANES, EXTRA_PLANES, PLANES_PER_POSITION,
+};
+use shared::Result;
+
+/// A tensor of a signature, by the name it has in the signature and its name in the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorSpec {
+    pub key: String,
+    /// `<operation>:<output index>`.
+    pub name: String,
+    /// `None` if the rank is unknown, and -1 for dimensions of unknown size.
+    pub dims: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub key: String,
+    pub inputs: Vec<TensorSpec>,
+    pub outputs: Vec<TensorSpec>,
+}
+
+/// Just enough of the protocol buffer wire format to read the signatures of a `MetaGraphDef`.
+struct Proto<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+impl<'a> Proto<'a> {
+    fn new(bytes: &'a [u8]) -> Proto<'a> {
+        Proto { bytes, offset: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut result = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = match self.bytes.get(self.offset) {
+                Some(byte) => *byte,
+                None => bail!("Unexpected end of a protocol buffer"),
+            };
+            self.offset += 1;
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        bail!("Invalid varint in a protocol buffer")
+    }
+
+    fn skip(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.offset + len > self.bytes.len() {
+            bail!("Unexpected end of a protocol buffer");
+        }
+        let result = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(result)
+    }
+
+    /// The next field number and its value, or `None` at the end.
+    fn next(&mut self) -> Result<Option<(u64, Field<'a>)>> {
+        if self.offset >= self.bytes.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => {
+                self.skip(8)?;
+                Field::Other
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.skip(len)?)
+            }
+            5 => {
+                self.skip(4)?;
+                Field::Other
+            }
+            wire => bail!("Unsupported wire type {} in a protocol buffer", wire),
+        };
+        Ok(Some((key >> 3, field)))
+    }
+}
+
+fn string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// A `map<string, T>` entry, with the key in field 1 and the value in field 2.
+fn map_entry(bytes: &[u8]) -> Result<(String, &[u8])> {
+    let (mut key, mut value) = (String::new(), &[][..]);
+    let mut proto = Proto::new(bytes);
+    while let Some((number, field)) = proto.next()? {
+        match (number, field) {
+            (1, Field::Bytes(bytes)) => key = string(bytes),
+            (2, Field::Bytes(bytes)) => value = bytes,
+            _ => {}
+        }
+    }
+    Ok((key, value))
+}
+
+/// A `TensorInfo`, with the name in field 1 and a `TensorShapeProto` in field 3.
+fn tensor_spec(key: String, bytes: &[u8]) -> Result<TensorSpec> {
+    let mut spec = TensorSpec {
+        key,
+        name: String::new(),
+        dims: None,
+    };
+    let mut proto = Proto::new(bytes);
+    while let Some((number, field)) = proto.next()? {
+        match (number, field) {
+            (1, Field::Bytes(bytes)) => spec.name = string(bytes),
+            (3, Field::Bytes(shape)) => {
+                let mut dims = Vec::new();
+                let mut unknown_rank = false;
+                let mut shape = Proto::new(shape);
+                while let Some((number, field)) = shape.next()? {
+                    match (number, field) {
+                        (2, Field::Bytes(dim)) => {
+                            let mut size = 0;
+                            let mut dim = Proto::new(dim);
+                            while let Some((number, field)) = dim.next()? {
+                                if let (1, Field::Varint(value)) = (number, field) {
+                                    size = value as i64;
+                                }
+                            }
+                            dims.push(size);
+                        }
+                        (3, Field::Varint(value)) => unknown_rank = value != 0,
+                        _ => {}
+                    }
+                }
+                spec.dims = if unknown_rank { None } else { Some(dims) };
+            }
+            _ => {}
+        }
+    }
+    Ok(spec)
+}
+
+/// The signatures in a serialized `MetaGraphDef`, which has them in field 5.
+pub fn parse_signatures(meta_graph_def: &[u8]) -> Result<Vec<Signature>> {
+    let mut signatures = Vec::new();
+    let mut proto = Proto::new(meta_graph_def);
+    while let Some((number, field)) = proto.next()? {
+        let entry = match (number, field) {
+            (5, Field::Bytes(entry)) => entry,
+            _ => continue,
+        };
+        let (key, definition) = map_entry(entry)?;
+        let mut signature = Signature {
+            key,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        };
+        // `SignatureDef` has the inputs in field 1 and the outputs in field 2.
+        let mut definition = Proto::new(definition);
+        while let Some((number, field)) = definition.next()? {
+            if let Field::Bytes(entry) = field {
+                let (key, info) = map_entry(entry)?;
+                match number {
+                    1 => signature.inputs.push(tensor_spec(key, info)?),
+                    2 => signature.outputs.push(tensor_spec(key, info)?),
+                    _ => {}
+                }
+            }
+        }
+        signatures.push(signature);
+    }
+    Ok(signatures)
+}
+
+/// How the planes of the encoder are laid out in the input tensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Flat,
+    ChannelsFirst,
+    ChannelsLast,
+}
+
+/// The layout and the number of planes of an input shape, without the batch dimension.
+pub fn input_layout(dims: &[i64]) -> Result<(Layout, usize)> {
+    let (layout, planes) = match dims {
+        [len] if *len > 0 && len % 64 == 0 => (Layout::Flat, len / 64),
+        [planes, 8, 8] if *planes > 0 => (Layout::ChannelsFirst, *planes),
+        [8, 8, planes] if *planes > 0 => (Layout::ChannelsLast, *planes),
+        _ => bail!(
+            "Expected an input of [planes * 64], [planes, 8, 8] or [8, 8, planes], got {:?}",
+            dims
+        ),
+    };
+    let planes = planes as usize;
+    let history = planes.saturating_sub(CONSTANT_PLANES + EXTRA_PLANES) / PLANES_PER_POSITION;
+    if history == 0
+        || Encoder::new(EncoderConfig {
+            history,
+            flip: true,
+        })
+        .planes()
+            != planes
+    {
+        bail!(
+            "{} input planes is not {} planes per position plus {}",
+            planes,
+            PLANES_PER_POSITION,
+            CONSTANT_PLANES + EXTRA_PLANES
+        );
+    }
+    Ok((layout, planes))
+}
+
+#[test]
+fn test_parse_signatures() {
+    fn field(number: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut result = vec![number << 3 | 2, bytes.len() as u8];
+        result.extend_from_slice(bytes);
+        result
+    }
+    fn entry(key: &str, value: &[u8]) -> Vec<u8> {
+        [field(1, key.as_bytes()), field(2, value)].concat()
+    }
+    // A shape of [-1, 22, 8, 8], with -1 as a 10 byte varint.
+    let mut dims = field(
+        2,
+        &[
+            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+        ],
+    );
+    for size in &[22, 8, 8] {
+        dims.extend(field(2, &[0x08, *size]));
+    }
+    let input = [
+        field(1, b"serving_input:0"),
+        vec![0x10, 0x01],
+        field(3, &dims),
+    ]
+    .concat();
+    let value = field(1, b"StatefulPartitionedCall:0");
+    let definition = [
+        field(1, &entry("planes", &input)),
+        field(2, &entry("value", &value)),
+    ]
+    .concat();
+    let meta_graph_def = [
+        field(1, b"ignored"),
+        field(5, &entry("serving_default", &definition)),
+    ]
+    .concat();
+
+    let signatures = parse_signatures(&meta_graph_def).unwrap();
+    assert_eq!(1, signatures.len());
+    assert_eq!("serving_default", signatures[0].key);
+    assert_eq!(
+        TensorSpec {
+            key: "planes".to_owned(),
+            name: "serving_input:0".to_owned(),
+            dims: Some(vec![-1, 22, 8, 8]),
+        },
+        signatures[0].inputs[0]
+    );
+    assert_eq!("StatefulPartitionedCall:0", signatures[0].outputs[0].name);
+    assert_eq!(None, signatures[0].outputs[0].dims);
+}
+
+#[test]
+fn test_input_layout() {
+    assert_eq!(
+        (Layout::ChannelsFirst, 22),
+        input_layout(&[22, 8, 8]).unwrap()
+    );
+    assert_eq!(
+        (Layout::ChannelsLast, 120),
+        input_layout(&[8, 8, 120]).unwrap()
+    );
+    assert_eq!((Layout::Flat, 22), input_layout(&[22 * 64]).unwrap());
+    // `BoardState::to_piece_vec` has 18 planes, which is not an encoder layout.
+    assert!(input_layout(&[18 * 64]).is_err());
+    assert!(input_layout(&[22, 8]).is_err());
+}
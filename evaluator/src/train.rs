@@ -0,0 +1,506 @@
+//! Training of the value network on the CPU, with mini-batches and Adam.
+//!
+//! The loss is the binary cross entropy between the output of the network and the result of the
+//! game. If the network has a policy head, the cross entropy between its softmax and the policy
+//! of the sample is added to it. The first layer only sees a few active features per position, so its
+//! forward and backward pass only touch the rows of those features.
+
+use crate::samples::{GameSamples, Sample};
+use crate::weights::{Layer, Weights};
+use random::Source;
+use shared::Result;
+
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    /// The part of the games that is kept apart to measure the loss on.
+    pub validation: f64,
+    /// Add flipped and mirrored positions to the samples from `games.csv`.
+    pub augment: bool,
+}
+
+impl Default for TrainOptions {
+    fn default() -> TrainOptions {
+        TrainOptions {
+            epochs: 10,
+            batch_size: 256,
+            learning_rate: 0.001,
+            validation: 0.1,
+            augment: true,
+        }
+    }
+}
+
+/// The losses after an epoch of training.
+#[derive(Debug, Clone, Copy)]
+pub struct Epoch {
+    /// Counting from 1.
+    pub number: usize,
+    /// The mean loss on the training samples while going through them.
+    pub training_loss: f64,
+    pub validation_loss: f64,
+}
+
+/// Shuffle with Fisher-Yates.
+pub fn shuffle<T>(items: &mut [T], source: &mut impl Source) {
+    for i in (1..items.len()).rev() {
+        let j = (source.read_u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Binary cross entropy, clamped so a confident wrong answer doesn't give an infinite loss.
+fn loss(output: f32, result: f32) -> f32 {
+    let output = output.clamp(1e-7, 1.0 - 1e-7);
+    -(result * output.ln() + (1.0 - result) * (1.0 - output).ln())
+}
+
+/// Run the network on a sample, keeping the output of every layer in `activations`. Returns the
+/// output of the network.
+fn forward(weights: &Weights, sample: &Sample, activations: &mut Vec<Vec<f32>>) -> f32 {
+    activations.resize(weights.layers.len(), Vec::new());
+    for (i, layer) in weights.layers.iter().enumerate() {
+        let mut output = layer.biases.clone();
+        if i == 0 {
+            for feature in &sample.features {
+                let row = usize::from(*feature) * layer.outputs;
+                for (o, w) in output
+                    .iter_mut()
+                    .zip(&layer.weights[row..row + layer.outputs])
+                {
+                    *o += w;
+                }
+            }
+        } else {
+            for (input, value) in activations[i - 1].iter().enumerate() {
+                if *value == 0.0 {
+                    continue;
+                }
+                let row = input * layer.outputs;
+                for (o, w) in output
+                    .iter_mut()
+                    .zip(&layer.weights[row..row + layer.outputs])
+                {
+                    *o += value * w;
+                }
+            }
+        }
+        if i + 1 == weights.layers.len() {
+            output.iter_mut().for_each(|o| *o = sigmoid(*o));
+        } else {
+            output.iter_mut().for_each(|o| *o = o.max(0.0));
+        }
+        activations[i] = output;
+    }
+    activations[weights.layers.len() - 1][0]
+}
+
+/// The value of a single sample, without keeping the activations.
+pub fn evaluate(weights: &Weights, sample: &Sample) -> f32 {
+    forward(weights, sample, &mut Vec::new())
+}
+
+/// Run the policy head on the activations of `forward`, leaving the softmax over all moves in
+/// `policy`. Returns the policy loss, which is 0 without a policy head or a policy in the sample,
+/// in which case `policy` is left empty.
+fn forward_policy(
+    weights: &Weights,
+    sample: &Sample,
+    activations: &[Vec<f32>],
+    policy: &mut Vec<f32>,
+) -> f32 {
+    policy.clear();
+    let head = match &weights.policy {
+        Some(head) if !sample.policy.is_empty() => head,
+        _ => return 0.0,
+    };
+    policy.extend_from_slice(&head.biases);
+    for (input, value) in activations[activations.len() - 2].iter().enumerate() {
+        if *value == 0.0 {
+            continue;
+        }
+        let row = input * head.outputs;
+        for (p, w) in policy
+            .iter_mut()
+            .zip(&head.weights[row..row + head.outputs])
+        {
+            *p += value * w;
+        }
+    }
+    let max = policy.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    policy.iter_mut().for_each(|p| *p = (*p - max).exp());
+    let total: f32 = policy.iter().sum();
+    policy.iter_mut().for_each(|p| *p /= total);
+    -sample
+        .policy
+        .iter()
+        .map(|(index, target)| target * policy[usize::from(*index)].max(1e-7).ln())
+        .sum::<f32>()
+}
+
+/// Add the gradient of the policy loss to the gradients of the policy head, and to `delta`, the
+/// gradient of the output of the last hidden layer.
+fn backward_policy(
+    head: &Layer,
+    sample: &Sample,
+    hidden: &[f32],
+    policy: &[f32],
+    gradient: &mut Layer,
+    delta: &mut [f32],
+) {
+    // The derivative of the cross entropy through the softmax.
+    let mut policy_delta = policy.to_vec();
+    for (index, target) in &sample.policy {
+        policy_delta[usize::from(*index)] -= target;
+    }
+    for (b, d) in gradient.biases.iter_mut().zip(&policy_delta) {
+        *b += d;
+    }
+    for (input, value) in hidden.iter().enumerate() {
+        if *value <= 0.0 {
+            continue;
+        }
+        let row = input * head.outputs;
+        let mut sum = 0.0;
+        for ((g, w), d) in gradient.weights[row..row + head.outputs]
+            .iter_mut()
+            .zip(&head.weights[row..row + head.outputs])
+            .zip(&policy_delta)
+        {
+            *g += value * d;
+            sum += w * d;
+        }
+        delta[input] += sum;
+    }
+}
+
+/// Add the gradient of the loss of a sample to `gradients`. `policy` is the softmax left by
+/// `forward_policy`.
+fn backward(
+    weights: &Weights,
+    sample: &Sample,
+    activations: &[Vec<f32>],
+    policy: &[f32],
+    gradients: &mut Weights,
+) {
+    let output = activations[activations.len() - 1][0];
+    // The derivative of the cross entropy through the sigmoid.
+    let mut delta = vec![output - sample.result];
+    for i in (0..weights.layers.len()).rev() {
+        let layer = &weights.layers[i];
+        let gradient = &mut gradients.layers[i];
+        for (b, d) in gradient.biases.iter_mut().zip(&delta) {
+            *b += d;
+        }
+        if i == 0 {
+            for feature in &sample.features {
+                let row = usize::from(*feature) * layer.outputs;
+                for (g, d) in gradient.weights[row..row + layer.outputs]
+                    .iter_mut()
+                    .zip(&delta)
+                {
+                    *g += d;
+                }
+            }
+            break;
+        }
+
+        let inputs = &activations[i - 1];
+        let mut next_delta = vec![0.0; layer.inputs];
+        for (input, value) in inputs.iter().enumerate() {
+            // The ReLU of the layer before lets no gradient through for inactive outputs.
+            if *value <= 0.0 {
+                continue;
+            }
+            let row = input * layer.outputs;
+            let mut sum = 0.0;
+            for ((g, w), d) in gradient.weights[row..row + layer.outputs]
+                .iter_mut()
+                .zip(&layer.weights[row..row + layer.outputs])
+                .zip(&delta)
+            {
+                *g += value * d;
+                sum += w * d;
+            }
+            next_delta[input] = sum;
+        }
+        if i + 1 == weights.layers.len() && !policy.is_empty() {
+            if let (Some(head), Some(gradient)) = (&weights.policy, &mut gradients.policy) {
+                backward_policy(head, sample, inputs, policy, gradient, &mut next_delta);
+            }
+        }
+        delta = next_delta;
+    }
+}
+
+fn zeros_like(weights: &Weights) -> Weights {
+    let zeros = |layer: &Layer| Layer {
+        inputs: layer.inputs,
+        outputs: layer.outputs,
+        weights: vec![0.0; layer.weights.len()],
+        biases: vec![0.0; layer.biases.len()],
+    };
+    Weights {
+        layers: weights.layers.iter().map(zeros).collect(),
+        policy: weights.policy.as_ref().map(zeros),
+    }
+}
+
+fn values_mut(weights: &mut Weights) -> impl Iterator<Item = &mut f32> {
+    weights
+        .layers
+        .iter_mut()
+        .chain(weights.policy.as_mut())
+        .flat_map(|layer| layer.weights.iter_mut().chain(layer.biases.iter_mut()))
+}
+
+/// The Adam optimizer, with the first and second moment of every weight.
+struct Adam {
+    m: Weights,
+    v: Weights,
+    step: i32,
+}
+
+impl Adam {
+    fn new(weights: &Weights) -> Adam {
+        Adam {
+            m: zeros_like(weights),
+            v: zeros_like(weights),
+            step: 0,
+        }
+    }
+
+    fn update(&mut self, weights: &mut Weights, gradients: &mut Weights, scale: f32, rate: f32) {
+        self.step += 1;
+        let m_correction = 1.0 - BETA1.powi(self.step);
+        let v_correction = 1.0 - BETA2.powi(self.step);
+        for (((w, g), m), v) in values_mut(weights)
+            .zip(values_mut(gradients))
+            .zip(values_mut(&mut self.m))
+            .zip(values_mut(&mut self.v))
+        {
+            let gradient = *g * scale;
+            *g = 0.0;
+            *m = BETA1 * *m + (1.0 - BETA1) * gradient;
+            *v = BETA2 * *v + (1.0 - BETA2) * gradient * gradient;
+            *w -= rate * (*m / m_correction) / ((*v / v_correction).sqrt() + EPSILON);
+        }
+    }
+}
+
+/// The loss of the value and the policy of a sample.
+fn sample_loss(
+    weights: &Weights,
+    sample: &Sample,
+    activations: &mut Vec<Vec<f32>>,
+    policy: &mut Vec<f32>,
+) -> f32 {
+    let output = forward(weights, sample, activations);
+    loss(output, sample.result) + forward_policy(weights, sample, activations, policy)
+}
+
+/// The mean loss of the network over the samples.
+pub fn mean_loss(weights: &Weights, samples: &[Sample]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let (mut activations, mut policy) = (Vec::new(), Vec::new());
+    let total: f64 = samples
+        .iter()
+        .map(|sample| f64::from(sample_loss(weights, sample, &mut activations, &mut policy)))
+        .sum();
+    (total / samples.len() as f64) as f32
+}
+
+/// Split the games into training and validation samples, keeping `validation` of the games apart.
+/// The positions of a game are close to each other, and its augmented samples are the same
+/// positions, so a game is never split. Only the training games keep their augmented samples.
+pub fn split(
+    mut games: Vec<GameSamples>,
+    validation: f64,
+    source: &mut impl Source,
+) -> (Vec<Sample>, Vec<Sample>) {
+    shuffle(&mut games, source);
+    let validation_len = (games.len() as f64 * validation) as usize;
+    let training_games = games.split_off(validation_len);
+    let validation = games.into_iter().flat_map(|game| game.samples).collect();
+    let training = training_games
+        .into_iter()
+        .flat_map(|game| game.samples.into_iter().chain(game.augmented))
+        .collect();
+    (training, validation)
+}
+
+/// Train on the `training` samples, measuring the loss on the `validation` samples after every
+/// epoch. `checkpoint` is called after every epoch with its losses and the weights so far.
+pub fn train(
+    weights: &mut Weights,
+    training: &mut [Sample],
+    validation: &[Sample],
+    options: &TrainOptions,
+    source: &mut impl Source,
+    mut checkpoint: impl FnMut(Epoch, &Weights) -> Result<()>,
+) -> Result<()> {
+    if options.batch_size == 0 {
+        bail!("The batch size should be at least 1");
+    }
+
+    let mut adam = Adam::new(weights);
+    let mut gradients = zeros_like(weights);
+    let (mut activations, mut policy) = (Vec::new(), Vec::new());
+    for epoch in 1..=options.epochs {
+        shuffle(training, source);
+        let mut total_loss = 0f64;
+        for batch in training.chunks(options.batch_size) {
+            for sample in batch {
+                let loss = sample_loss(weights, sample, &mut activations, &mut policy);
+                total_loss += f64::from(loss);
+                backward(weights, sample, &activations, &policy, &mut gradients);
+            }
+            let scale = 1.0 / batch.len() as f32;
+            adam.update(weights, &mut gradients, scale, options.learning_rate);
+        }
+
+        let epoch = Epoch {
+            number: epoch,
+            training_loss: total_loss / training.len().max(1) as f64,
+            validation_loss: f64::from(mean_loss(weights, validation)),
+        };
+        checkpoint(epoch, weights)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_gradient_matches_finite_difference() {
+    let mut source = random::default().seed([3, 4]);
+    let mut weights = Weights {
+        layers: vec![
+            Layer::random(16, 4, &mut source),
+            Layer::random(4, 3, &mut source),
+            Layer::random(3, 1, &mut source),
+        ],
+        policy: Some(Layer::random(3, 5, &mut source)),
+    };
+    // Positive biases so no ReLU sits exactly on its kink.
+    for layer in &mut weights.layers {
+        layer.biases.iter_mut().for_each(|b| *b = 0.5);
+    }
+    let sample = Sample {
+        features: vec![1, 5, 12],
+        result: 1.0,
+        policy: vec![(2, 0.75), (4, 0.25)],
+    };
+
+    let (mut activations, mut policy) = (Vec::new(), Vec::new());
+    let mut gradients = zeros_like(&weights);
+    sample_loss(&weights, &sample, &mut activations, &mut policy);
+    backward(&weights, &sample, &activations, &policy, &mut gradients);
+
+    // Layer 3 is the policy head.
+    fn weight(weights: &mut Weights, layer: usize, index: usize) -> &mut f32 {
+        match layer {
+            3 => &mut weights.policy.as_mut().unwrap().weights[index],
+            _ => &mut weights.layers[layer].weights[index],
+        }
+    }
+    let total_loss = |weights: &Weights| sample_loss(weights, &sample, &mut vec![], &mut vec![]);
+    let h = 1e-3;
+    for (layer, index) in &[(0, 5 * 4 + 2), (1, 7), (2, 1), (3, 6)] {
+        let mut plus = weights.clone();
+        *weight(&mut plus, *layer, *index) += h;
+        let mut minus = weights.clone();
+        *weight(&mut minus, *layer, *index) -= h;
+        let numeric = (total_loss(&plus) - total_loss(&minus)) / (2.0 * h);
+        let analytic = *weight(&mut gradients, *layer, *index);
+        assert!(
+            (numeric - analytic).abs() < 1e-2,
+            "layer {}: {} vs {}",
+            layer,
+            numeric,
+            analytic
+        );
+    }
+}
+
+#[test]
+fn test_training_reduces_loss() {
+    let mut source = random::default().seed([5, 6]);
+    let mut weights = Weights {
+        layers: vec![
+            Layer::random(8, 16, &mut source),
+            Layer::random(16, 1, &mut source),
+        ],
+        policy: None,
+    };
+    // Feature 0 means white wins, feature 1 that black wins.
+    let mut samples: Vec<Sample> = (0..64)
+        .map(|i| Sample {
+            features: vec![(i % 2) as u16, 2 + (i % 6) as u16],
+            result: if i % 2 == 0 { 1.0 } else { 0.0 },
+            policy: Vec::new(),
+        })
+        .collect();
+    let before = mean_loss(&weights, &samples);
+    let options = TrainOptions {
+        epochs: 50,
+        batch_size: 8,
+        learning_rate: 0.01,
+        validation: 0.0,
+        augment: false,
+    };
+    let mut losses = Vec::new();
+    train(
+        &mut weights,
+        &mut samples,
+        &[],
+        &options,
+        &mut source,
+        |epoch, _| {
+            losses.push(epoch.training_loss);
+            assert_eq!(losses.len(), epoch.number);
+            Ok(())
+        },
+    )
+    .unwrap();
+    assert_eq!(50, losses.len());
+    assert!(losses[49] < losses[0]);
+    let after = mean_loss(&weights, &samples);
+    assert!(after < before / 2.0, "{} {}", before, after);
+}
+
+#[test]
+fn test_split_by_game() {
+    let mut source = random::default().seed([7, 8]);
+    // The result of a sample tells which game it is from.
+    let games: Vec<GameSamples> = (0..10)
+        .map(|game| {
+            let sample = |i: u16| Sample {
+                features: vec![i],
+                result: game as f32,
+                policy: Vec::new(),
+            };
+            GameSamples {
+                samples: (0..5).map(sample).collect(),
+                augmented: (5..8).map(sample).collect(),
+            }
+        })
+        .collect();
+    let (training, validation) = split(games, 0.2, &mut source);
+    assert_eq!(8 * 8, training.len());
+    assert_eq!(2 * 5, validation.len());
+    for sample in &validation {
+        assert!(sample.features[0] < 5);
+        assert!(training.iter().all(|t| t.result != sample.result));
+    }
+}
@@ -0,0 +1,230 @@
+//! The weights of the value network, and the file they are stored in.
+//!
+//! The network is a stack of fully connected layers over `BoardState::to_piece_vec()`, with a ReLU
+//! after every hidden layer and a sigmoid on the single output. A network can also have a policy
+//! head: a layer from the output of the last hidden layer to a logit for every move in
+//! `shared::policy`.
+//!
+//! A weights file starts with the magic `b"CEVN"`, a version (`u32`) and the number of layers
+//! (`u32`). Every layer then has its number of inputs and outputs (`u32`), the weights as
+//! `inputs * outputs` floats (`f32`, row `i` holds the weights of input `i`) and `outputs` biases.
+//! Since version 2, this is followed by a `u32` that is 1 if a policy head follows, stored like
+//! the other layers. All numbers are little endian.
+
+use random::Source;
+use shared::policy::POLICY_SIZE;
+use shared::Result;
+use std::fs;
+
+const MAGIC: &[u8; 4] = b"CEVN";
+const VERSION: u32 = 2;
+
+/// The size of `BoardState::to_piece_vec()`: one plane of 64 squares for every piece.
+pub const INPUTS: usize = 18 * 64;
+
+/// The sizes of the layers of a new network, from the input to the output.
+pub const LAYER_SIZES: [usize; 4] = [INPUTS, 256, 32, 1];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// `inputs * outputs` weights, row `i` holds the weights of input `i`.
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+impl Layer {
+    /// A layer with uniform Xavier initialization, so the activations keep about the same
+    /// variance through the network.
+    pub fn random(inputs: usize, outputs: usize, source: &mut impl Source) -> Layer {
+        let limit = (6.0 / (inputs + outputs) as f64).sqrt();
+        let weights = (0..inputs * outputs)
+            .map(|_| ((source.read_f64() * 2.0 - 1.0) * limit) as f32)
+            .collect();
+        Layer {
+            inputs,
+            outputs,
+            weights,
+            biases: vec![0.0; outputs],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    pub layers: Vec<Layer>,
+    /// The policy head, with a logit for every move index.
+    pub policy: Option<Layer>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.offset + len > self.bytes.len() {
+            bail!("Unexpected end of file at byte {}", self.offset);
+        }
+        let result = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(result)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn floats(&mut self, count: usize) -> Result<Vec<f32>> {
+        Ok(self
+            .take(count * 4)?
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    fn layer(&mut self) -> Result<Layer> {
+        let inputs = self.u32()? as usize;
+        let outputs = self.u32()? as usize;
+        Ok(Layer {
+            inputs,
+            outputs,
+            weights: self.floats(inputs * outputs)?,
+            biases: self.floats(outputs)?,
+        })
+    }
+}
+
+fn write_layer(bytes: &mut Vec<u8>, layer: &Layer) {
+    bytes.extend_from_slice(&(layer.inputs as u32).to_le_bytes());
+    bytes.extend_from_slice(&(layer.outputs as u32).to_le_bytes());
+    for value in layer.weights.iter().chain(&layer.biases) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl Weights {
+    /// A new network with the sizes of `LAYER_SIZES`, and a policy head.
+    pub fn random(source: &mut impl Source) -> Weights {
+        let layers = LAYER_SIZES
+            .windows(2)
+            .map(|sizes| Layer::random(sizes[0], sizes[1], source))
+            .collect();
+        let hidden = LAYER_SIZES[LAYER_SIZES.len() - 2];
+        Weights {
+            layers,
+            policy: Some(Layer::random(hidden, POLICY_SIZE, source)),
+        }
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.layers.first().map(|l| l.inputs).unwrap_or(0)
+    }
+
+    pub fn load(path: &str) -> Result<Weights> {
+        let bytes = fs::read(path)?;
+        Weights::from_bytes(&bytes).map_err(|e| format_err!("Could not read {:?}: {}", path, e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Weights> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            bail!("Not a weights file");
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > VERSION {
+            bail!("Unsupported version {}", version);
+        }
+        let count = reader.u32()?;
+        let mut layers: Vec<Layer> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let layer = reader.layer()?;
+            if let Some(previous) = layers.last() {
+                if previous.outputs != layer.inputs {
+                    bail!(
+                        "Layer {} has {} inputs, but the layer before it has {} outputs",
+                        layers.len(),
+                        layer.inputs,
+                        previous.outputs
+                    );
+                }
+            }
+            layers.push(layer);
+        }
+        match layers.last() {
+            Some(layer) if layer.outputs == 1 => {}
+            _ => bail!("The last layer should have a single output"),
+        }
+        if layers[0].inputs != INPUTS {
+            bail!("Expected {} inputs, got {}", INPUTS, layers[0].inputs);
+        }
+
+        let policy = if version >= 2 && reader.u32()? == 1 {
+            let layer = reader.layer()?;
+            let hidden = layers[layers.len() - 1].inputs;
+            if layers.len() < 2 || layer.inputs != hidden || layer.outputs != POLICY_SIZE {
+                bail!(
+                    "The policy head should have {} inputs and {} outputs, got {} and {}",
+                    hidden,
+                    POLICY_SIZE,
+                    layer.inputs,
+                    layer.outputs
+                );
+            }
+            Some(layer)
+        } else {
+            None
+        };
+        Ok(Weights { layers, policy })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.layers.len() as u32).to_le_bytes());
+        for layer in &self.layers {
+            write_layer(&mut bytes, layer);
+        }
+        match &self.policy {
+            Some(layer) => {
+                bytes.extend_from_slice(&1u32.to_le_bytes());
+                write_layer(&mut bytes, layer);
+            }
+            None => bytes.extend_from_slice(&0u32.to_le_bytes()),
+        }
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_weights_roundtrip() {
+    let mut source = random::default().seed([1, 2]);
+    let weights = Weights::random(&mut source);
+    assert_eq!(INPUTS, weights.inputs());
+    assert_eq!(3, weights.layers.len());
+
+    assert_eq!(32, weights.policy.as_ref().unwrap().inputs);
+
+    let bytes = weights.to_bytes();
+    assert_eq!(weights, Weights::from_bytes(&bytes).unwrap());
+    assert!(Weights::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    // Version 1 files have no policy head.
+    let mut old = Weights {
+        layers: weights.layers.clone(),
+        policy: None,
+    }
+    .to_bytes();
+    old.truncate(old.len() - 4);
+    old[4..8].copy_from_slice(&1u32.to_le_bytes());
+    assert_eq!(None, Weights::from_bytes(&old).unwrap().policy);
+}
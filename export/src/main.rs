@@ -0,0 +1,361 @@
+//! Export training samples to NumPy files, to train networks outside of Rust.
+//!
+//! Usage: `export <games.csv|games.pgn> <output> [--shard-size N] [--history N] [--flip true|false]
+//! [--format npz|npy]`
+//!
+//! Every position in a game, before a move is played, becomes a sample. Samples are written in
+//! shards of `--shard-size` positions (default 100000), as `<output>-00000.npz` or as a directory
+//! `<output>-00000` with an `.npy` file per array. Every shard has the arrays:
+//!
+//! - `features` (`float32`, `N x planes x 8 x 8`): the planes of `shared::features::Encoder`, with
+//!   `--history` positions (default 1), seen from the player to move unless `--flip false`.
+//! - `result` (`float32`): 1 if white won, 0.5 for a draw and 0 if black won.
+//! - `value` (`float32`): the result for the player to move, 1 for a win, 0 for a draw, -1 for a
+//!   loss.
+//! - `policy` (`int32`): the move that was played, as its index in `shared::policy`, from 0 to
+//!   `POLICY_SIZE - 1`. The index is always seen from the player to move, with or without
+//!   `--flip`, so a network trained on it has the policy output the engine expects.
+//! - `promotion` (`int8`): 0 for no promotion, or 1 to 4 for a knight, bishop, rook or queen.
+//! - `game_id` (bytes), `ply` (`int32`, 0 for the first move), `white_rating` and `black_rating`
+//!   (`int32`, -1 if unknown).
+
+extern crate csv;
+#[macro_use]
+extern crate failure;
+extern crate flate2;
+extern crate pgn_reader;
+extern crate shared;
+
+mod npy;
+
+use crate::npy::Array;
+use pgn_reader::{Reader, San, Skip, Visitor};
+use shared::features::{Encoder, EncoderConfig};
+use shared::policy;
+use shared::{BoardState, CurrentPlayer, Move, PieceKind, Result};
+use std::fs;
+
+const COLUMN_ID: usize = 0;
+const COLUMN_WINNER: usize = 6;
+const COLUMN_WHITE_RATING: usize = 9;
+const COLUMN_BLACK_RATING: usize = 11;
+const COLUMN_MOVES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Npz,
+    Npy,
+}
+
+struct ExportOptions {
+    shard_size: usize,
+    encoder: Encoder,
+    format: Format,
+}
+
+/// A game with a known result, read from either a csv or a pgn file.
+#[derive(Default)]
+struct Game {
+    id: String,
+    white_rating: Option<i32>,
+    black_rating: Option<i32>,
+    /// `None` if the game has no result, `Some(None)` for a draw.
+    winner: Option<Option<CurrentPlayer>>,
+    moves: Vec<String>,
+}
+
+/// The samples of the shard that is being filled.
+#[derive(Default)]
+struct Shard {
+    features: Vec<f32>,
+    result: Vec<f32>,
+    value: Vec<f32>,
+    policy: Vec<i32>,
+    promotion: Vec<i8>,
+    game_id: Vec<String>,
+    ply: Vec<i32>,
+    white_rating: Vec<i32>,
+    black_rating: Vec<i32>,
+}
+
+impl Shard {
+    fn len(&self) -> usize {
+        self.result.len()
+    }
+
+    fn arrays(&self, encoder: &Encoder) -> Vec<Array> {
+        let n = self.len();
+        let ids: Vec<&[u8]> = self.game_id.iter().map(|id| id.as_bytes()).collect();
+        vec![
+            Array::f32("features", &[n, encoder.planes(), 8, 8], &self.features),
+            Array::f32("result", &[n], &self.result),
+            Array::f32("value", &[n], &self.value),
+            Array::i32("policy", &[n], &self.policy),
+            Array::i8("promotion", &[n], &self.promotion),
+            Array::bytes("game_id", &ids),
+            Array::i32("ply", &[n], &self.ply),
+            Array::i32("white_rating", &[n], &self.white_rating),
+            Array::i32("black_rating", &[n], &self.black_rating),
+        ]
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        println!("Usage:");
+        println!("  export <games.csv|games.pgn> <output> [--shard-size N] [--history N] [--flip true|false] [--format npz|npy]");
+        return;
+    }
+    if let Err(e) = export(&args[0], &args[1], &args[2..]) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<ExportOptions> {
+    let mut options = ExportOptions {
+        shard_size: 100_000,
+        encoder: Encoder::default(),
+        format: Format::Npz,
+    };
+    let mut config = EncoderConfig::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format_err!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--shard-size" => options.shard_size = value.parse()?,
+            "--history" => config.history = value.parse()?,
+            "--flip" => config.flip = value.parse()?,
+            "--format" => {
+                options.format = match value.as_str() {
+                    "npz" => Format::Npz,
+                    "npy" => Format::Npy,
+                    _ => bail!("Unknown format {:?}", value),
+                }
+            }
+            _ => bail!("Unknown argument {:?}", arg),
+        }
+    }
+    if options.shard_size == 0 || config.history == 0 {
+        bail!("The shard size and history should be at least 1");
+    }
+    options.encoder = Encoder::new(config);
+    Ok(options)
+}
+
+fn export(input: &str, output: &str, args: &[String]) -> Result<()> {
+    let options = parse_options(args)?;
+    let mut shard = Shard::default();
+    let mut shards = 0;
+    let (mut exported, mut skipped) = (0, 0);
+
+    let mut add_game = |game: Game| -> Result<()> {
+        match add_samples(&game, &options.encoder, &mut shard) {
+            Ok(true) => exported += 1,
+            _ => skipped += 1,
+        }
+        if shard.len() >= options.shard_size {
+            write_shard(&std::mem::take(&mut shard), output, shards, &options)?;
+            shards += 1;
+        }
+        Ok(())
+    };
+    if input.ends_with(".pgn") {
+        read_pgn(input)?.into_iter().try_for_each(&mut add_game)?;
+    } else {
+        read_csv(input, &mut add_game)?;
+    }
+
+    if shard.len() > 0 {
+        write_shard(&shard, output, shards, &options)?;
+        shards += 1;
+    }
+    println!(
+        "Exported {} games to {} shards, skipped {} games",
+        exported, shards, skipped
+    );
+    Ok(())
+}
+
+/// Add a sample for every position of the game. Returns `false` if the game has no result.
+fn add_samples(game: &Game, encoder: &Encoder, shard: &mut Shard) -> Result<bool> {
+    let result = match game.winner {
+        Some(Some(CurrentPlayer::White)) => 1.0,
+        Some(Some(CurrentPlayer::Black)) => 0.0,
+        Some(None) => 0.5,
+        None => return Ok(false),
+    };
+    // Replay the whole game first, so a game that fails halfway adds nothing.
+    let mut positions = vec![BoardState::init()];
+    let mut moves = Vec::with_capacity(game.moves.len());
+    for m in &game.moves {
+        let before = positions.last().unwrap();
+        let mut after = before.clone();
+        after.make_move(m)?;
+        let played = Move::between(before, &after)?;
+        let index = match policy::move_index(before.current_player, played) {
+            Some(index) => index,
+            None => bail!("Move {} has no policy index", played),
+        };
+        moves.push((played, index));
+        positions.push(after);
+    }
+
+    for (ply, (m, index)) in moves.iter().enumerate() {
+        let state = &positions[ply];
+        encoder.encode_into(&positions[..=ply], &mut shard.features);
+        let white = state.current_player == CurrentPlayer::White;
+        shard.result.push(result);
+        shard.value.push(if white {
+            result * 2.0 - 1.0
+        } else {
+            1.0 - result * 2.0
+        });
+        shard.policy.push(*index as i32);
+        shard.promotion.push(match m.promotion {
+            Some(PieceKind::Knight) => 1,
+            Some(PieceKind::Bishop) => 2,
+            Some(PieceKind::Rook) => 3,
+            Some(PieceKind::Queen) => 4,
+            _ => 0,
+        });
+        shard.game_id.push(game.id.clone());
+        shard.ply.push(ply as i32);
+        shard.white_rating.push(game.white_rating.unwrap_or(-1));
+        shard.black_rating.push(game.black_rating.unwrap_or(-1));
+    }
+    Ok(true)
+}
+
+fn write_shard(shard: &Shard, output: &str, index: usize, options: &ExportOptions) -> Result<()> {
+    let arrays = shard.arrays(&options.encoder);
+    let path = format!("{}-{:05}", output, index);
+    match options.format {
+        Format::Npz => {
+            let path = format!("{}.npz", path);
+            fs::write(&path, npy::to_npz(&arrays)?)?;
+            println!("Written {} samples to {:?}", shard.len(), path);
+        }
+        Format::Npy => {
+            fs::create_dir_all(&path)?;
+            for array in &arrays {
+                array.save(&format!("{}/{}.npy", path, array.name))?;
+            }
+            println!("Written {} samples to {:?}", shard.len(), path);
+        }
+    }
+    Ok(())
+}
+
+fn read_csv(input: &str, mut f: impl FnMut(Game) -> Result<()>) -> Result<()> {
+    let mut parser = csv::Reader::from_path(input)?;
+    for record in parser.records() {
+        let record = record?;
+        f(Game {
+            id: record.get(COLUMN_ID).unwrap_or_default().to_owned(),
+            white_rating: record.get(COLUMN_WHITE_RATING).and_then(|r| r.parse().ok()),
+            black_rating: record.get(COLUMN_BLACK_RATING).and_then(|r| r.parse().ok()),
+            winner: match record.get(COLUMN_WINNER) {
+                Some("white") => Some(Some(CurrentPlayer::White)),
+                Some("black") => Some(Some(CurrentPlayer::Black)),
+                Some("draw") => Some(None),
+                _ => None,
+            },
+            moves: record
+                .get(COLUMN_MOVES)
+                .unwrap_or_default()
+                .split(' ')
+                .filter(|m| !m.is_empty())
+                .map(String::from)
+                .collect(),
+        })?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct GameVisitor {
+    game: Game,
+    games: usize,
+}
+
+impl<'pgn> Visitor<'pgn> for GameVisitor {
+    type Result = Game;
+
+    fn begin_game(&mut self) {
+        self.game = Game::default();
+        self.game.id = format!("{}", self.games);
+        self.games += 1;
+    }
+
+    fn header(&mut self, key: &'pgn [u8], value: &'pgn [u8]) {
+        let value = String::from_utf8_lossy(value);
+        match key {
+            // Lichess puts the link to the game in `Site`, which makes a good id.
+            b"Site" | b"GameId" => self.game.id = value.into_owned(),
+            b"WhiteElo" => self.game.white_rating = value.parse().ok(),
+            b"BlackElo" => self.game.black_rating = value.parse().ok(),
+            b"Result" => {
+                self.game.winner = match value.as_ref() {
+                    "1-0" => Some(Some(CurrentPlayer::White)),
+                    "0-1" => Some(Some(CurrentPlayer::Black)),
+                    "1/2-1/2" => Some(None),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn san(&mut self, san: San) {
+        self.game.moves.push(san.to_string());
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true) // stay in the mainline
+    }
+
+    fn end_game(&mut self, _game: &'pgn [u8]) -> Self::Result {
+        std::mem::take(&mut self.game)
+    }
+}
+
+fn read_pgn(input: &str) -> Result<Vec<Game>> {
+    let pgn = fs::read(input)?;
+    let mut visitor = GameVisitor::default();
+    let reader = Reader::new(&mut visitor, &pgn);
+    Ok(reader.into_iter().collect())
+}
+
+#[test]
+fn test_policy_labels() {
+    let game = Game {
+        winner: Some(None),
+        moves: vec!["e4".to_owned(), "e5".to_owned(), "Nf3".to_owned()],
+        ..Game::default()
+    };
+    for flip in &[true, false] {
+        let encoder = Encoder::new(EncoderConfig {
+            flip: *flip,
+            ..EncoderConfig::default()
+        });
+        let mut shard = Shard::default();
+        assert!(add_samples(&game, &encoder, &mut shard).unwrap());
+        let mut state = BoardState::init();
+        for (m, label) in game.moves.iter().zip(&shard.policy) {
+            let before = state.clone();
+            state.make_move(m).unwrap();
+            let index = *label as usize;
+            assert!(index < policy::POLICY_SIZE);
+            assert_eq!(
+                Some(Move::between(&before, &state).unwrap()),
+                policy::index_move(&before, index)
+            );
+        }
+        // e2e4 and e7e5 are the same move for the player to move.
+        assert_eq!(shard.policy[0], shard.policy[1]);
+    }
+}
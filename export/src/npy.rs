@@ -0,0 +1,175 @@
+//! Writing NumPy `.npy` arrays, and `.npz` archives of them.
+//!
+//! An `.npy` file is a magic string, a version, and a header with a Python dict literal that
+//! describes the type and shape of the array, followed by the raw data in C order. An `.npz` file
+//! is a zip archive with an `.npy` file per array, which is what `numpy.savez` writes. The
+//! archives written here are not compressed.
+
+use flate2::Crc;
+use shared::Result;
+use std::fs;
+
+/// An array to write, with its data already in little endian bytes.
+pub struct Array {
+    pub name: String,
+    /// The NumPy type, e.g. `<f4` or `|S12`.
+    pub descr: String,
+    pub shape: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+impl Array {
+    pub fn f32(name: &str, shape: &[usize], values: &[f32]) -> Array {
+        Array::new(
+            name,
+            "<f4",
+            shape,
+            values.iter().flat_map(|v| v.to_le_bytes()),
+        )
+    }
+
+    pub fn i32(name: &str, shape: &[usize], values: &[i32]) -> Array {
+        Array::new(
+            name,
+            "<i4",
+            shape,
+            values.iter().flat_map(|v| v.to_le_bytes()),
+        )
+    }
+
+    pub fn i8(name: &str, shape: &[usize], values: &[i8]) -> Array {
+        Array::new(name, "|i1", shape, values.iter().map(|v| *v as u8))
+    }
+
+    /// Byte strings, padded with zeroes to the longest one.
+    pub fn bytes(name: &str, values: &[&[u8]]) -> Array {
+        let width = values.iter().map(|v| v.len()).max().unwrap_or(0).max(1);
+        let data = values.iter().flat_map(|v| {
+            v.iter()
+                .cloned()
+                .chain(std::iter::repeat_n(0, width - v.len()))
+        });
+        Array::new(name, &format!("|S{}", width), &[values.len()], data)
+    }
+
+    fn new(name: &str, descr: &str, shape: &[usize], data: impl Iterator<Item = u8>) -> Array {
+        Array {
+            name: name.to_owned(),
+            descr: descr.to_owned(),
+            shape: shape.to_vec(),
+            data: data.collect(),
+        }
+    }
+
+    /// The contents of the `.npy` file.
+    pub fn to_npy(&self) -> Vec<u8> {
+        let shape = match self.shape.len() {
+            // A tuple with one element needs a trailing comma in Python.
+            1 => format!("({},)", self.shape[0]),
+            _ => format!(
+                "({})",
+                self.shape
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            self.descr, shape
+        );
+        // The data starts aligned to 64 bytes, the header ends with a newline.
+        let unpadded = 10 + header.len() + 1;
+        header.extend(std::iter::repeat_n(' ', (64 - unpadded % 64) % 64));
+        header.push('\n');
+
+        let mut bytes = Vec::with_capacity(10 + header.len() + self.data.len());
+        bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_npy())?;
+        Ok(())
+    }
+}
+
+/// A zip archive with the arrays as `<name>.npy`, stored without compression.
+pub fn to_npz(arrays: &[Array]) -> Result<Vec<u8>> {
+    // MS-DOS date of 1980-01-01, the earliest a zip file can have.
+    const DATE: u16 = 0x21;
+    let mut bytes = Vec::new();
+    let mut directory = Vec::new();
+
+    for array in arrays {
+        let name = format!("{}.npy", array.name);
+        let data = array.to_npy();
+        if data.len() > u32::MAX as usize || bytes.len() > u32::MAX as usize {
+            bail!(
+                "Array {:?} is too large for a zip file, use smaller shards",
+                array.name
+            );
+        }
+        let mut crc = Crc::new();
+        crc.update(&data);
+
+        // Version needed, flags, compression (stored), time and date, crc, sizes, name length and
+        // extra field length are the same in the local header and the central directory.
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+        common.extend_from_slice(&DATE.to_le_bytes());
+        common.extend_from_slice(&crc.sum().to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        let offset = bytes.len() as u32;
+        bytes.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        bytes.extend_from_slice(&common);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&data);
+
+        directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes());
+        directory.extend_from_slice(&common);
+        // Comment length, disk number, internal and external attributes.
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = bytes.len() as u32;
+    bytes.extend_from_slice(&directory);
+    bytes.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&(arrays.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(arrays.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&directory_offset.to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    Ok(bytes)
+}
+
+#[test]
+fn test_npy_header() {
+    let array = Array::f32("value", &[2], &[1.0, -1.0]);
+    let bytes = array.to_npy();
+    assert_eq!(b"\x93NUMPY\x01\x00", &bytes[..8]);
+    assert_eq!(128 + 8, bytes.len());
+    let header = String::from_utf8_lossy(&bytes[10..128]);
+    assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2,), }"));
+    assert!(header.ends_with('\n'));
+    assert_eq!(&1f32.to_le_bytes(), &bytes[128..132]);
+
+    let ids = Array::bytes("id", &[b"abc", b"a"]);
+    assert_eq!("|S3", ids.descr);
+    assert_eq!(b"abca\0\0".to_vec(), ids.data);
+}
@@ -0,0 +1,218 @@
+//! Boards as text, for debugging in a terminal.
+//!
+//! `BoardState` implements `Display` with a letter per piece, uppercase for white and `.` for an
+//! empty square, and the files and ranks around it. `BoardState::display` can draw Unicode chess
+//! glyphs on squares coloured with ANSI escape codes instead, which also shows the last move.
+
+use crate::{BoardState, CurrentPlayer, Move, PieceKind};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// A letter per piece, as in FEN.
+    Ascii,
+    /// Chess glyphs on coloured squares, for terminals with 256 colours.
+    Unicode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayOptions {
+    pub style: Style,
+    /// Whether to label the files and ranks.
+    pub coordinates: bool,
+    /// Whether to draw the board from black's side.
+    pub flipped: bool,
+    /// A move to highlight. Only the `Unicode` style has colours to show it with.
+    pub last_move: Option<Move>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> DisplayOptions {
+        DisplayOptions {
+            style: Style::Ascii,
+            coordinates: true,
+            flipped: false,
+            last_move: None,
+        }
+    }
+}
+
+/// The 256 colour palette indices of the squares.
+const LIGHT_SQUARE: u8 = 223;
+const DARK_SQUARE: u8 = 137;
+const LIGHT_HIGHLIGHT: u8 = 187;
+const DARK_HIGHLIGHT: u8 = 143;
+const WHITE_PIECE: u8 = 231;
+const BLACK_PIECE: u8 = 16;
+
+fn letter(kind: PieceKind, player: CurrentPlayer) -> char {
+    let letter = match kind {
+        PieceKind::Pawn => 'p',
+        PieceKind::Knight => 'n',
+        PieceKind::Bishop => 'b',
+        PieceKind::Rook => 'r',
+        PieceKind::Queen => 'q',
+        PieceKind::King => 'k',
+    };
+    match player {
+        CurrentPlayer::White => letter.to_ascii_uppercase(),
+        CurrentPlayer::Black => letter,
+    }
+}
+
+/// The filled glyph of a piece, which is coloured for either player.
+fn glyph(kind: PieceKind) -> char {
+    match kind {
+        PieceKind::King => '\u{265a}',
+        PieceKind::Queen => '\u{265b}',
+        PieceKind::Rook => '\u{265c}',
+        PieceKind::Bishop => '\u{265d}',
+        PieceKind::Knight => '\u{265e}',
+        PieceKind::Pawn => '\u{265f}',
+    }
+}
+
+/// A `BoardState` drawn with `DisplayOptions`.
+pub struct BoardDisplay<'a> {
+    state: &'a BoardState,
+    options: DisplayOptions,
+}
+
+impl BoardState {
+    pub fn display(&self, options: DisplayOptions) -> BoardDisplay<'_> {
+        BoardDisplay {
+            state: self,
+            options,
+        }
+    }
+}
+
+impl fmt::Display for BoardState {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.display(DisplayOptions::default()).fmt(fmt)
+    }
+}
+
+impl<'a> fmt::Display for BoardDisplay<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let options = &self.options;
+        // The files from left to right, as `x`, and the ranks from top to bottom, as `y`.
+        let mut columns = [7, 6, 5, 4, 3, 2, 1, 0];
+        let mut rows = [7, 6, 5, 4, 3, 2, 1, 0];
+        if options.flipped {
+            columns.reverse();
+            rows.reverse();
+        }
+        let width = match options.style {
+            Style::Ascii => 2,
+            Style::Unicode => 3,
+        };
+
+        // Lines are built first to leave out the spaces at their ends.
+        for y in &rows {
+            let mut line = String::new();
+            if options.coordinates {
+                line.push_str(&format!("{} ", y + 1));
+            }
+            for x in &columns {
+                let piece = self.state.get_piece(*x, *y);
+                let piece = match (piece.kind(), piece.owner()) {
+                    (Some(kind), Some(player)) => Some((kind, player)),
+                    _ => None,
+                };
+                match options.style {
+                    Style::Ascii => {
+                        let c = piece.map_or('.', |(kind, player)| letter(kind, player));
+                        line.push_str(&format!("{} ", c));
+                    }
+                    Style::Unicode => {
+                        let highlighted = options
+                            .last_move
+                            .is_some_and(|m| m.from == (*x, *y) || m.to == (*x, *y));
+                        // `x` counts from the h-file, so a1 at (7, 0) is dark.
+                        let background = match ((x + y) % 2 == 0, highlighted) {
+                            (true, false) => LIGHT_SQUARE,
+                            (true, true) => LIGHT_HIGHLIGHT,
+                            (false, false) => DARK_SQUARE,
+                            (false, true) => DARK_HIGHLIGHT,
+                        };
+                        line.push_str(&format!("\x1b[48;5;{}m", background));
+                        match piece {
+                            Some((kind, player)) => {
+                                let foreground = match player {
+                                    CurrentPlayer::White => WHITE_PIECE,
+                                    CurrentPlayer::Black => BLACK_PIECE,
+                                };
+                                line.push_str(&format!(
+                                    "\x1b[38;5;{}m {} ",
+                                    foreground,
+                                    glyph(kind)
+                                ));
+                            }
+                            None => line.push_str("   "),
+                        }
+                    }
+                }
+            }
+            if options.style == Style::Unicode {
+                line.push_str("\x1b[0m");
+            }
+            writeln!(fmt, "{}", line.trim_end())?;
+        }
+
+        if options.coordinates {
+            let mut line = "  ".to_owned();
+            for x in &columns {
+                let file = (b'a' + 7 - x) as char;
+                line.push_str(&format!("{:^width$}", file, width = width));
+            }
+            writeln!(fmt, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_display_ascii() {
+    let mut state = BoardState::init();
+    state.make_move("e4").unwrap();
+    let expected = "\
+8 r n b q k b n r
+7 p p p p p p p p
+6 . . . . . . . .
+5 . . . . . . . .
+4 . . . . P . . .
+3 . . . . . . . .
+2 P P P P . P P P
+1 R N B Q K B N R
+  a b c d e f g h
+";
+    assert_eq!(expected, state.to_string());
+
+    let options = DisplayOptions {
+        coordinates: false,
+        flipped: true,
+        ..DisplayOptions::default()
+    };
+    let flipped = state.display(options).to_string();
+    assert_eq!(Some("R N B K Q B N R"), flipped.lines().next());
+}
+
+#[test]
+fn test_display_unicode() {
+    let before = BoardState::init();
+    let mut state = before.clone();
+    state.make_move("e4").unwrap();
+    let options = DisplayOptions {
+        style: Style::Unicode,
+        last_move: Some(Move::between(&before, &state).unwrap()),
+        ..DisplayOptions::default()
+    };
+    let text = state.display(options).to_string();
+    assert_eq!(32, text.chars().filter(|c| "♚♛♜♝♞♟".contains(*c)).count());
+    // e2 and e4 are both light squares.
+    let highlight = format!("[48;5;{}m", LIGHT_HIGHLIGHT);
+    assert_eq!(2, text.matches(&highlight).count());
+    assert_eq!(32, text.matches(&format!("[48;5;{}m", DARK_SQUARE)).count());
+    assert!(text.ends_with("\n   a  b  c  d  e  f  g  h\n"));
+}
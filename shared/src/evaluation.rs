@@ -0,0 +1,598 @@
+//! Static evaluation of a `BoardState`.
+//!
+//! The evaluation is a weighted sum of terms. Every term is counted as "white minus black", so
+//! a positive score is good for white. Scores are in centipawns.
+//!
+//! Pawn structure terms only depend on the pawns on the board, so they are cached in a
+//! `PawnHashTable` keyed by `zobrist::pawn_hash`.
+
+use crate::enum_primitive::FromPrimitive;
+use crate::movegen::for_each_attack;
+use crate::{zobrist, BoardState, CurrentPlayer, Piece, PieceKind, Result};
+use std::fs;
+
+enum_from_primitive! {
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+    pub enum Term {
+        PawnValue,
+        KnightValue,
+        BishopValue,
+        RookValue,
+        QueenValue,
+
+        DoubledPawn,
+        IsolatedPawn,
+        BackwardPawn,
+        ConnectedPawn,
+        PassedPawnRank2,
+        PassedPawnRank3,
+        PassedPawnRank4,
+        PassedPawnRank5,
+        PassedPawnRank6,
+        PassedPawnRank7,
+        /// Counted once per rank the blocked passed pawn has advanced, so blockers hurt more the
+        /// further the pawn is.
+        PassedPawnBlocked,
+
+        /// Own pawn directly in front of the king, or diagonally in front of it.
+        KingShelterNear,
+        /// Own pawn two ranks in front of the king, on the same or an adjacent file.
+        KingShelterFar,
+        /// Enemy pawn on the king's or an adjacent file, at most three ranks in front of the king.
+        PawnStorm,
+        /// Number of squares in the enemy king zone attacked by a piece of the given kind.
+        KingAttackKnight,
+        KingAttackBishop,
+        KingAttackRook,
+        KingAttackQueen,
+    }
+}
+
+pub const TERM_COUNT: usize = Term::KingAttackQueen as usize + 1;
+
+impl Term {
+    pub fn all() -> impl Iterator<Item = Term> {
+        (0..TERM_COUNT as u8).map(|i| Term::from_u8(i).unwrap())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weights {
+    values: [i32; TERM_COUNT],
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        let mut weights = Weights {
+            values: [0; TERM_COUNT],
+        };
+        for (term, value) in &[
+            (Term::PawnValue, 100),
+            (Term::KnightValue, 320),
+            (Term::BishopValue, 330),
+            (Term::RookValue, 500),
+            (Term::QueenValue, 900),
+            (Term::DoubledPawn, -15),
+            (Term::IsolatedPawn, -12),
+            (Term::BackwardPawn, -8),
+            (Term::ConnectedPawn, 8),
+            (Term::PassedPawnRank2, 5),
+            (Term::PassedPawnRank3, 10),
+            (Term::PassedPawnRank4, 20),
+            (Term::PassedPawnRank5, 35),
+            (Term::PassedPawnRank6, 60),
+            (Term::PassedPawnRank7, 100),
+            (Term::PassedPawnBlocked, -4),
+            (Term::KingShelterNear, 12),
+            (Term::KingShelterFar, 6),
+            (Term::PawnStorm, -8),
+            (Term::KingAttackKnight, 6),
+            (Term::KingAttackBishop, 5),
+            (Term::KingAttackRook, 7),
+            (Term::KingAttackQueen, 10),
+        ] {
+            weights.set(*term, *value);
+        }
+        weights
+    }
+}
+
+impl Weights {
+    #[inline]
+    pub fn get(&self, term: Term) -> i32 {
+        self.values[term as usize]
+    }
+
+    #[inline]
+    pub fn set(&mut self, term: Term, value: i32) {
+        self.values[term as usize] = value;
+    }
+
+    /// Load weights from a parameter file, as written by `Weights::save` (or the `tuner`).
+    ///
+    /// Every line contains a term name and its value, separated by whitespace. Empty lines and
+    /// lines starting with `#` are ignored. Terms that are missing from the file keep their
+    /// default value.
+    pub fn load(path: &str) -> Result<Weights> {
+        let contents = fs::read_to_string(path)?;
+        Weights::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Weights> {
+        let mut weights = Weights::default();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap();
+            let value = parts
+                .next()
+                .ok_or_else(|| format_err!("Line {}: missing value for {:?}", index + 1, name))?;
+            let term = Term::all()
+                .find(|term| format!("{:?}", term) == name)
+                .ok_or_else(|| format_err!("Line {}: unknown term {:?}", index + 1, name))?;
+            weights.set(term, value.parse()?);
+        }
+        Ok(weights)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Weights {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for term in Term::all() {
+            writeln!(fmt, "{:?} {}", term, self.get(term))?;
+        }
+        Ok(())
+    }
+}
+
+/// How often every term occurs in a position, white minus black.
+/// The score of a position is the dot product of the trace and the weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trace {
+    pub counts: [i32; TERM_COUNT],
+}
+
+impl Default for Trace {
+    fn default() -> Trace {
+        Trace {
+            counts: [0; TERM_COUNT],
+        }
+    }
+}
+
+impl Trace {
+    #[inline]
+    pub fn get(&self, term: Term) -> i32 {
+        self.counts[term as usize]
+    }
+
+    #[inline]
+    fn add(&mut self, term: Term, player: CurrentPlayer, count: i32) {
+        match player {
+            CurrentPlayer::White => self.counts[term as usize] += count,
+            CurrentPlayer::Black => self.counts[term as usize] -= count,
+        }
+    }
+
+    pub fn score(&self, weights: &Weights) -> i32 {
+        self.counts
+            .iter()
+            .zip(weights.values.iter())
+            .map(|(count, weight)| count * weight)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PawnEntry {
+    key: u64,
+    trace: Trace,
+    /// Bitmask of the squares (`y * 8 + x`) that contain a passed pawn.
+    passed: u64,
+}
+
+/// Fixed size, always-replace cache of pawn structure evaluations.
+pub struct PawnHashTable {
+    entries: Vec<Option<PawnEntry>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl PawnHashTable {
+    /// Create a new table. `size` is rounded up to the next power of two.
+    pub fn new(size: usize) -> PawnHashTable {
+        PawnHashTable {
+            entries: vec![None; size.max(1).next_power_of_two()],
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    fn probe(&mut self, state: &BoardState) -> PawnEntry {
+        let key = zobrist::pawn_hash(state);
+        let index = key as usize & (self.entries.len() - 1);
+        if let Some(entry) = self.entries[index] {
+            if entry.key == key {
+                self.hits += 1;
+                return entry;
+            }
+        }
+        self.misses += 1;
+        let entry = evaluate_pawns(state, key);
+        self.entries[index] = Some(entry);
+        entry
+    }
+}
+
+/// Evaluates positions with a set of weights, caching pawn structure terms between calls.
+pub struct StaticEval {
+    pub weights: Weights,
+    pawns: PawnHashTable,
+}
+
+impl Default for StaticEval {
+    fn default() -> StaticEval {
+        StaticEval::new(Weights::default())
+    }
+}
+
+impl StaticEval {
+    pub fn new(weights: Weights) -> StaticEval {
+        StaticEval {
+            weights,
+            pawns: PawnHashTable::new(1 << 14),
+        }
+    }
+
+    pub fn pawn_table(&self) -> &PawnHashTable {
+        &self.pawns
+    }
+
+    /// The score of the position in centipawns, positive when white is better.
+    pub fn evaluate(&mut self, state: &BoardState) -> i32 {
+        self.trace(state).score(&self.weights)
+    }
+
+    pub fn trace(&mut self, state: &BoardState) -> Trace {
+        let pawns = self.pawns.probe(state);
+        let mut trace = pawns.trace;
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let piece = state.get_piece(x, y);
+                let player = match piece.owner() {
+                    Some(player) => player,
+                    None => continue,
+                };
+                let term = match piece.kind() {
+                    Some(PieceKind::Pawn) => Term::PawnValue,
+                    Some(PieceKind::Knight) => Term::KnightValue,
+                    Some(PieceKind::Bishop) => Term::BishopValue,
+                    Some(PieceKind::Rook) => Term::RookValue,
+                    Some(PieceKind::Queen) => Term::QueenValue,
+                    _ => continue,
+                };
+                trace.add(term, player, 1);
+            }
+        }
+
+        // Blockers depend on every piece, so they can't be stored in the pawn hash.
+        for square in 0..64u8 {
+            if pawns.passed & (1 << square) == 0 {
+                continue;
+            }
+            let (x, y) = (square % 8, square / 8);
+            let player = state.get_piece(x, y).owner().unwrap();
+            let stop_y = forward(y, player);
+            if state.get_piece(x, stop_y) != Piece::None {
+                // Pawns start on the second rank, so this is how far the pawn has come.
+                trace.add(
+                    Term::PassedPawnBlocked,
+                    player,
+                    i32::from(relative_rank(y, player)) - 1,
+                );
+            }
+        }
+
+        for player in &[CurrentPlayer::White, CurrentPlayer::Black] {
+            if let Some(king) = state.find_king(*player) {
+                king_shelter(state, *player, king, &mut trace);
+                king_attacks(state, *player, king, &mut trace);
+            }
+        }
+
+        trace
+    }
+}
+
+/// Rank seen from the given player's side, `0` being their back rank.
+#[inline]
+fn relative_rank(y: u8, player: CurrentPlayer) -> u8 {
+    match player {
+        CurrentPlayer::White => y,
+        CurrentPlayer::Black => 7 - y,
+    }
+}
+
+/// The rank in front of `y` for the given player. Pawns never stand on the last rank, so this
+/// can't go out of bounds for them.
+#[inline]
+fn forward(y: u8, player: CurrentPlayer) -> u8 {
+    match player {
+        CurrentPlayer::White => y + 1,
+        CurrentPlayer::Black => y - 1,
+    }
+}
+
+fn is_pawn_of(piece: Piece, player: CurrentPlayer) -> bool {
+    piece.kind() == Some(PieceKind::Pawn) && piece.owner() == Some(player)
+}
+
+fn evaluate_pawns(state: &BoardState, key: u64) -> PawnEntry {
+    let mut trace = Trace::default();
+    let mut passed = 0u64;
+
+    // relative ranks of the pawns of every player on every file
+    let mut files: [[Vec<u8>; 8]; 2] = Default::default();
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = state.get_piece(x, y);
+            if piece.kind() == Some(PieceKind::Pawn) {
+                let player = piece.owner().unwrap();
+                files[player as usize][x as usize].push(relative_rank(y, player));
+            }
+        }
+    }
+
+    for player in &[CurrentPlayer::White, CurrentPlayer::Black] {
+        let player = *player;
+        let own = &files[player as usize];
+        let enemy = &files[player.opponent() as usize];
+
+        for x in 0..8usize {
+            if own[x].len() > 1 {
+                trace.add(Term::DoubledPawn, player, own[x].len() as i32 - 1);
+            }
+            let neighbours = [x.checked_sub(1), if x < 7 { Some(x + 1) } else { None }];
+
+            for rank in &own[x] {
+                let rank = *rank;
+                let mut isolated = true;
+                let mut connected = false;
+                let mut all_neighbours_ahead = true;
+                for neighbour in neighbours.iter().filter_map(|n| *n) {
+                    for other in &own[neighbour] {
+                        isolated = false;
+                        if *other == rank || *other + 1 == rank {
+                            connected = true;
+                        }
+                        if *other <= rank {
+                            all_neighbours_ahead = false;
+                        }
+                    }
+                }
+                if isolated {
+                    trace.add(Term::IsolatedPawn, player, 1);
+                }
+                if connected {
+                    trace.add(Term::ConnectedPawn, player, 1);
+                }
+
+                // enemy ranks are seen from the enemy side, so an enemy pawn on relative rank `r`
+                // is on our relative rank `7 - r`
+                let stop_rank = rank + 1;
+                if !isolated && all_neighbours_ahead {
+                    let stop_attacked = neighbours
+                        .iter()
+                        .filter_map(|n| *n)
+                        .any(|n| enemy[n].iter().any(|r| 7 - *r == stop_rank + 1));
+                    if stop_attacked {
+                        trace.add(Term::BackwardPawn, player, 1);
+                    }
+                }
+
+                let is_frontmost = own[x].iter().all(|r| *r <= rank);
+                let is_passed = is_frontmost
+                    && neighbours
+                        .iter()
+                        .filter_map(|n| *n)
+                        .chain(Some(x))
+                        .all(|n| enemy[n].iter().all(|r| 7 - *r <= rank));
+                if is_passed {
+                    let term = Term::from_u8(Term::PassedPawnRank2 as u8 + rank - 1).unwrap();
+                    trace.add(term, player, 1);
+                    let y = relative_rank(rank, player);
+                    passed |= 1 << (y * 8 + x as u8);
+                }
+            }
+        }
+    }
+
+    PawnEntry { key, trace, passed }
+}
+
+fn king_shelter(state: &BoardState, player: CurrentPlayer, king: (u8, u8), trace: &mut Trace) {
+    let king_rank = relative_rank(king.1, player);
+    let min_x = king.0.saturating_sub(1);
+    let max_x = (king.0 + 1).min(7);
+    for x in min_x..=max_x {
+        for distance in 1..=3u8 {
+            let rank = king_rank + distance;
+            if rank > 7 {
+                break;
+            }
+            let piece = state.get_piece(x, relative_rank(rank, player));
+            if distance <= 2 && is_pawn_of(piece, player) {
+                let term = if distance == 1 {
+                    Term::KingShelterNear
+                } else {
+                    Term::KingShelterFar
+                };
+                trace.add(term, player, 1);
+            }
+            if is_pawn_of(piece, player.opponent()) {
+                // storms are bad for the player being stormed
+                trace.add(Term::PawnStorm, player, 1);
+            }
+        }
+    }
+}
+
+/// Count the attacks of `player.opponent()`'s pieces on the zone around `player`'s king.
+fn king_attacks(state: &BoardState, player: CurrentPlayer, king: (u8, u8), trace: &mut Trace) {
+    let attacker = player.opponent();
+    let in_zone = |x: u8, y: u8| {
+        (i16::from(x) - i16::from(king.0)).abs() <= 1
+            && (i16::from(y) - i16::from(king.1)).abs() <= 1
+    };
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = state.get_piece(x, y);
+            if piece.owner() != Some(attacker) {
+                continue;
+            }
+            let term = match piece.kind() {
+                Some(PieceKind::Knight) => Term::KingAttackKnight,
+                Some(PieceKind::Bishop) => Term::KingAttackBishop,
+                Some(PieceKind::Rook) => Term::KingAttackRook,
+                Some(PieceKind::Queen) => Term::KingAttackQueen,
+                _ => continue,
+            };
+            let mut count = 0;
+            for_each_attack(state, x, y, |x, y| {
+                if in_zone(x, y) {
+                    count += 1;
+                }
+            });
+            trace.add(term, attacker, count);
+        }
+    }
+}
+
+#[cfg(test)]
+fn empty_board() -> BoardState {
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKing);
+    state.set_piece((3, 7), Piece::BlackKing);
+    state
+}
+
+#[test]
+fn test_initial_position_is_equal() {
+    let mut eval = StaticEval::default();
+    assert_eq!(0, eval.evaluate(&BoardState::init()));
+}
+
+#[test]
+fn test_pawn_structure() {
+    let mut state = empty_board();
+    // white: doubled, isolated pawns on the h file, only the front one is passed
+    state.set_piece((0, 1), Piece::WhitePawn);
+    state.set_piece((0, 2), Piece::WhitePawn);
+    // black: a connected pair on d7/e7, which blocks nothing on the h file
+    state.set_piece((3, 6), Piece::BlackPawn);
+    state.set_piece((4, 6), Piece::BlackPawn);
+
+    let trace = StaticEval::default().trace(&state);
+    assert_eq!(1, trace.get(Term::DoubledPawn));
+    assert_eq!(2, trace.get(Term::IsolatedPawn));
+    assert_eq!(-2, trace.get(Term::ConnectedPawn));
+    assert_eq!(-2, trace.get(Term::PassedPawnRank2));
+    assert_eq!(1, trace.get(Term::PassedPawnRank3));
+}
+
+#[test]
+fn test_passed_pawn_blocked() {
+    let mut state = empty_board();
+    state.set_piece((0, 5), Piece::WhitePawn);
+    state.set_piece((0, 6), Piece::BlackKnight);
+
+    let trace = StaticEval::default().trace(&state);
+    assert_eq!(1, trace.get(Term::PassedPawnRank6));
+    // Four ranks up from h2.
+    assert_eq!(4, trace.get(Term::PassedPawnBlocked));
+
+    // Black on a5 has come two ranks from a7.
+    state.set_piece((7, 4), Piece::BlackPawn);
+    state.set_piece((7, 3), Piece::WhiteKnight);
+    let trace = StaticEval::default().trace(&state);
+    assert_eq!(2, trace.get(Term::PassedPawnBlocked));
+}
+
+#[test]
+fn test_king_safety() {
+    let mut state = empty_board();
+    // white king on e1 with an intact shelter, black queen on e5 looking at it
+    state.set_piece((2, 1), Piece::WhitePawn);
+    state.set_piece((3, 1), Piece::WhitePawn);
+    state.set_piece((4, 2), Piece::WhitePawn);
+    state.set_piece((3, 4), Piece::BlackQueen);
+
+    let trace = StaticEval::default().trace(&state);
+    assert_eq!(2, trace.get(Term::KingShelterNear));
+    assert_eq!(1, trace.get(Term::KingShelterFar));
+    // the queen only reaches e2, the rest of the e-file is blocked by the pawn standing there
+    assert_eq!(-1, trace.get(Term::KingAttackQueen));
+}
+
+#[test]
+fn test_weights_roundtrip() {
+    let mut weights = Weights::default();
+    weights.set(Term::PawnStorm, -13);
+    assert_eq!(weights, Weights::parse(&weights.to_string()).unwrap());
+
+    let weights = Weights::parse("# tuned\n\nQueenValue 950\n").unwrap();
+    assert_eq!(950, weights.get(Term::QueenValue));
+    assert_eq!(100, weights.get(Term::PawnValue));
+
+    assert!(Weights::parse("KingValue 1000").is_err());
+}
+
+#[test]
+fn test_pawn_hash_hits() {
+    let mut eval = StaticEval::default();
+    let mut state = BoardState::init();
+    eval.evaluate(&state);
+    state.set_piece((1, 0), Piece::None);
+    eval.evaluate(&state);
+    assert_eq!(1, eval.pawn_table().misses());
+    assert_eq!(1, eval.pawn_table().hits());
+}
+
+#[test]
+fn test_evaluation_is_symmetric_under_flip() {
+    let mut eval = StaticEval::default();
+    let mut state = BoardState::init();
+    for m in &[
+        "e4", "c5", "Nf3", "d6", "d4", "cxd4", "Nxd4", "Nf6", "Nc3", "a6", "f4",
+    ] {
+        state.make_move(m).unwrap();
+        let flipped = state.flipped();
+        assert_eq!(
+            eval.evaluate(&state),
+            -eval.evaluate(&flipped),
+            "after {}",
+            m
+        );
+        assert_eq!(zobrist::hash(&state), zobrist::hash(&flipped.flipped()));
+    }
+    let state = state.flipped();
+    assert_eq!(CurrentPlayer::White, state.current_player);
+    assert_eq!([true, true], state.castling_rights(CurrentPlayer::Black));
+}
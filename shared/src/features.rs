@@ -0,0 +1,260 @@
+//! Input planes for neural networks, in the style of AlphaZero.
+//!
+//! A position is encoded as a stack of 8x8 planes, flattened into one `Vec<f32>` with index
+//! `plane * 64 + rank * 8 + file` (file a = 0, rank 1 = 0). With `flip` on (the default), the
+//! board is seen from the player to move: for black the ranks are mirrored, and "own" pieces are
+//! those of the player to move. With `flip` off, "own" always means white.
+//!
+//! For each of the last `history` positions, starting with the current one, there are
+//! `PLANES_PER_POSITION` planes:
+//!
+//! | plane | contents                                                       |
+//! |-------|----------------------------------------------------------------|
+//! | 0-5   | own pawns, knights, bishops, rooks, queens, king               |
+//! | 6-11  | opponent pawns, knights, bishops, rooks, queens, king          |
+//! | 12    | 1 if the position occurred before in the game                  |
+//! | 13    | 1 if the position occurred at least twice before in the game   |
+//!
+//! Positions before the start of the game are all zeroes. After those come `CONSTANT_PLANES`
+//! planes that have the same value on every square:
+//!
+//! | plane | contents                                                       |
+//! |-------|----------------------------------------------------------------|
+//! | 0     | 1 if white is to move                                          |
+//! | 1     | the number of moves played, divided by 100                     |
+//! | 2-3   | own castling rights, king side and queen side                  |
+//! | 4-5   | opponent castling rights, king side and queen side             |
+//! | 6     | plies since the last capture or pawn move, divided by 100      |
+//!
+//! The last plane has a 1 on the en passant square, if there is one.
+//!
+//! `BoardState` has no move counters, so these are counted in the positions passed to `encode`.
+//! Pass the whole game to get them right.
+
+use crate::zobrist;
+use crate::{BoardState, CurrentPlayer, Piece, PieceKind};
+
+pub const PLANES_PER_POSITION: usize = 14;
+pub const CONSTANT_PLANES: usize = 7;
+/// The en passant plane.
+pub const EXTRA_PLANES: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderConfig {
+    /// The number of positions to encode, including the current one.
+    pub history: usize,
+    /// Show the board from the view of the player to move.
+    pub flip: bool,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> EncoderConfig {
+        EncoderConfig {
+            history: 1,
+            flip: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Encoder {
+    pub config: EncoderConfig,
+}
+
+fn kind_index(kind: PieceKind) -> usize {
+    match kind {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight => 1,
+        PieceKind::Bishop => 2,
+        PieceKind::Rook => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => 5,
+    }
+}
+
+/// Whether the move between two positions was a capture or a pawn move.
+fn is_zeroing(before: &BoardState, after: &BoardState) -> bool {
+    let mut pieces = (0, 0);
+    for y in 0..8 {
+        for x in 0..8 {
+            let (old, new) = (before.get_piece(x, y), after.get_piece(x, y));
+            pieces.0 += (old != Piece::None) as usize;
+            pieces.1 += (new != Piece::None) as usize;
+            let pawn = |piece: Piece| piece.kind() == Some(PieceKind::Pawn);
+            if pawn(old) != pawn(new) {
+                return true;
+            }
+        }
+    }
+    pieces.0 != pieces.1
+}
+
+impl Encoder {
+    pub fn new(config: EncoderConfig) -> Encoder {
+        Encoder { config }
+    }
+
+    pub fn planes(&self) -> usize {
+        self.config.history * PLANES_PER_POSITION + CONSTANT_PLANES + EXTRA_PLANES
+    }
+
+    /// The length of an encoded position.
+    pub fn len(&self) -> usize {
+        self.planes() * 64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encode the last position of `game`, with the positions before it as history.
+    pub fn encode(&self, game: &[BoardState]) -> Vec<f32> {
+        let mut result = Vec::with_capacity(self.len());
+        self.encode_into(game, &mut result);
+        result
+    }
+
+    /// Like `encode`, but appends to `out`, to fill a batch without copying.
+    pub fn encode_into(&self, game: &[BoardState], out: &mut Vec<f32>) {
+        let start = out.len();
+        out.resize(start + self.len(), 0.0);
+        let planes = &mut out[start..];
+        let current = match game.last() {
+            Some(current) => current,
+            None => return,
+        };
+        let player = current.current_player;
+        let (us, them) = if self.config.flip {
+            (player, player.opponent())
+        } else {
+            (CurrentPlayer::White, CurrentPlayer::Black)
+        };
+        let mirror = self.config.flip && player == CurrentPlayer::Black;
+        let index = |plane: usize, x: u8, y: u8| {
+            let rank = if mirror { 7 - y } else { y };
+            plane * 64 + usize::from(rank) * 8 + usize::from(7 - x)
+        };
+
+        let hashes: Vec<u64> = game.iter().map(zobrist::hash).collect();
+        for step in 0..self.config.history.min(game.len()) {
+            let position = game.len() - 1 - step;
+            let state = &game[position];
+            let base = step * PLANES_PER_POSITION;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let piece = state.get_piece(x, y);
+                    let (kind, owner) = match (piece.kind(), piece.owner()) {
+                        (Some(kind), Some(owner)) => (kind, owner),
+                        _ => continue,
+                    };
+                    let offset = if owner == us { 0 } else { 6 };
+                    planes[index(base + offset + kind_index(kind), x, y)] = 1.0;
+                }
+            }
+            let repetitions = hashes[..position]
+                .iter()
+                .filter(|hash| **hash == hashes[position])
+                .count();
+            for (i, plane) in [base + 12, base + 13].iter().enumerate() {
+                if repetitions > i {
+                    planes[plane * 64..(plane + 1) * 64]
+                        .iter_mut()
+                        .for_each(|v| *v = 1.0);
+                }
+            }
+        }
+
+        let no_progress = game
+            .windows(2)
+            .rev()
+            .take_while(|pair| !is_zeroing(&pair[0], &pair[1]))
+            .count();
+        let own = current.castling_rights(us);
+        let other = current.castling_rights(them);
+        let constants = [
+            (player == CurrentPlayer::White) as u8 as f32,
+            (game.len() - 1) as f32 / 2.0 / 100.0,
+            own[0] as u8 as f32,
+            own[1] as u8 as f32,
+            other[0] as u8 as f32,
+            other[1] as u8 as f32,
+            no_progress as f32 / 100.0,
+        ];
+        let base = self.config.history * PLANES_PER_POSITION;
+        for (i, value) in constants.iter().enumerate() {
+            let plane = base + i;
+            planes[plane * 64..(plane + 1) * 64]
+                .iter_mut()
+                .for_each(|v| *v = *value);
+        }
+
+        if let Some((x, y)) = current.en_passant() {
+            planes[index(base + CONSTANT_PLANES, x, y)] = 1.0;
+        }
+    }
+}
+
+#[test]
+fn test_encode_initial_position() {
+    let encoder = Encoder::default();
+    let planes = encoder.encode(&[BoardState::init()]);
+    assert_eq!(22 * 64, planes.len());
+    // White pawns on rank 2, the black king on e8.
+    assert!(planes[8..16].iter().all(|v| *v == 1.0));
+    assert_eq!(1.0, planes[11 * 64 + 7 * 8 + 4]);
+    assert_eq!(32.0, planes[..12 * 64].iter().sum::<f32>());
+    // White to move, all castling rights.
+    let constants = &planes[14 * 64..];
+    for plane in &[0, 2, 3, 4, 5] {
+        assert_eq!(1.0, constants[plane * 64]);
+    }
+    assert_eq!(0.0, constants[6 * 64]);
+}
+
+#[test]
+fn test_encode_flips_for_black() {
+    let mut game = vec![BoardState::init()];
+    let mut state = BoardState::init();
+    state.make_move("e4").unwrap();
+    game.push(state);
+
+    let encoder = Encoder::default();
+    let planes = encoder.encode(&game);
+    // Black's own pawns are on their second rank, white's e-pawn is on black's fifth.
+    assert!(planes[8..16].iter().all(|v| *v == 1.0));
+    assert_eq!(1.0, planes[6 * 64 + 4 * 8 + 4]);
+    // The en passant square e3 is on black's sixth rank.
+    assert_eq!(1.0, planes[(14 + 7) * 64 + 5 * 8 + 4]);
+    // A pawn move resets the no progress counter.
+    assert_eq!(0.0, planes[(14 + 6) * 64]);
+
+    let encoder = Encoder::new(EncoderConfig {
+        history: 1,
+        flip: false,
+    });
+    let planes = encoder.encode(&game);
+    assert_eq!(1.0, planes[3 * 8 + 4]);
+}
+
+#[test]
+fn test_encode_history_and_repetitions() {
+    let mut game = vec![BoardState::init()];
+    for m in &["Nf3", "Nf6", "Ng1", "Ng8"] {
+        let mut state = game.last().unwrap().clone();
+        state.make_move(m).unwrap();
+        game.push(state);
+    }
+    let encoder = Encoder::new(EncoderConfig {
+        history: 8,
+        flip: true,
+    });
+    let planes = encoder.encode(&game);
+    assert_eq!((8 * 14 + 8) * 64, planes.len());
+    // The current position is a repetition of the initial position.
+    assert_eq!(1.0, planes[12 * 64]);
+    assert_eq!(0.0, planes[13 * 64]);
+    // Only 5 positions have been played, the rest of the history is empty.
+    assert!(planes[5 * 14 * 64..8 * 14 * 64].iter().all(|v| *v == 0.0));
+    // 4 plies without progress.
+    assert_eq!(0.04, planes[(8 * 14 + 6) * 64]);
+}
@@ -2,6 +2,21 @@
 pub extern crate enum_primitive;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate lazy_static;
+extern crate random;
+
+pub mod display;
+pub mod evaluation;
+pub mod features;
+pub mod mcts;
+pub mod movegen;
+pub mod nnue;
+pub mod options;
+pub mod policy;
+pub mod polyglot;
+pub mod syzygy;
+pub mod zobrist;
 
 pub type Result<T> = std::result::Result<T, failure::Error>;
 
@@ -11,14 +26,25 @@ use enum_primitive::FromPrimitive;
 pub struct BoardState {
     pieces: [[Piece; 8]; 8],
     pub current_player: CurrentPlayer,
+    /// The square a pawn skipped over with a double push in the last move.
+    en_passant: Option<(u8, u8)>,
 }
 
-#[derive(Debug, Copy, PartialEq, Eq, Clone)]
+#[derive(Debug, Copy, PartialEq, Eq, Hash, Clone)]
 pub enum CurrentPlayer {
     White,
     Black,
 }
 
+impl CurrentPlayer {
+    pub fn opponent(self) -> CurrentPlayer {
+        match self {
+            CurrentPlayer::White => CurrentPlayer::Black,
+            CurrentPlayer::Black => CurrentPlayer::White,
+        }
+    }
+}
+
 impl BoardState {
     pub fn init() -> BoardState {
         BoardState {
@@ -105,24 +131,32 @@ impl BoardState {
                 ],
             ],
             current_player: CurrentPlayer::White,
+            en_passant: None,
         }
     }
 
-    fn get_position(m: &str) -> (u8, u8) {
-        let mut offset = 0;
-        let bytes = m.as_bytes();
-        while bytes[offset] == b'x' {
-            offset += 1;
+    /// A board without any pieces, with white to move.
+    pub fn empty() -> BoardState {
+        BoardState {
+            pieces: [[Piece::None; 8]; 8],
+            current_player: CurrentPlayer::White,
+            en_passant: None,
         }
-        let x = bytes[offset];
-        let y = bytes[offset + 1];
+    }
+
+    fn get_position(m: &str) -> Result<(u8, u8)> {
+        let bytes = m.trim_start_matches('x').as_bytes();
+        let (x, y) = match bytes {
+            [x, y, ..] => (*x, *y),
+            _ => bail!("Expected a square, got {:?}", m),
+        };
         if x > b'h' || x < b'a' {
-            panic!("X out of bounds: {:?}", x as char);
+            bail!("X out of bounds: {:?}", x as char);
         }
         if y > b'8' || y < b'1' {
-            panic!("Y out of bounds: {:?}", y as char);
+            bail!("Y out of bounds: {:?}", y as char);
         }
-        (7 - (x - b'a'), y - b'1')
+        Ok((7 - (x - b'a'), y - b'1'))
     }
 
     #[inline]
@@ -130,9 +164,77 @@ impl BoardState {
         self.pieces[y as usize][x as usize]
     }
 
-    fn move_piece(&mut self, from: (u8, u8), to: (u8, u8)) {
+    /// The square a pawn can be captured on en passant, if the last move was a double pawn push.
+    #[inline]
+    pub fn en_passant(&self) -> Option<(u8, u8)> {
+        self.en_passant
+    }
+
+    /// King side and queen side castling rights. An unmoved king and rook on their starting
+    /// squares means the right still exists.
+    pub fn castling_rights(&self, player: CurrentPlayer) -> [bool; 2] {
+        let y = match player {
+            CurrentPlayer::White => 0,
+            CurrentPlayer::Black => 7,
+        };
+        if self.get_piece(3, y) != Piece::new(PieceKind::King, player) {
+            return [false, false];
+        }
+        let rook = Piece::new(PieceKind::Rook, player);
+        [self.get_piece(0, y) == rook, self.get_piece(7, y) == rook]
+    }
+
+    /// The same position with the colours swapped: the ranks are mirrored, every piece changes
+    /// owner and the other player is to move. Castling rights and en passant swap with them, so a
+    /// position and its flipped version should get the same evaluation for the player to move.
+    pub fn flipped(&self) -> BoardState {
+        let mut pieces = [[Piece::None; 8]; 8];
+        for (y, row) in self.pieces.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                pieces[7 - y][x] = piece.flipped();
+            }
+        }
+        BoardState {
+            pieces,
+            current_player: self.current_player.opponent(),
+            en_passant: self.en_passant.map(|(x, y)| (x, 7 - y)),
+        }
+    }
+
+    /// The position mirrored from the a-file to the h-file. Castling is not symmetric, so this is
+    /// `None` if either player can still castle.
+    pub fn mirrored(&self) -> Option<BoardState> {
+        let players = [CurrentPlayer::White, CurrentPlayer::Black];
+        if players
+            .iter()
+            .any(|player| self.castling_rights(*player) != [false, false])
+        {
+            return None;
+        }
+        let mut pieces = [[Piece::None; 8]; 8];
+        for (y, row) in self.pieces.iter().enumerate() {
+            for (x, piece) in row.iter().enumerate() {
+                pieces[y][7 - x] = *piece;
+            }
+        }
+        Some(BoardState {
+            pieces,
+            current_player: self.current_player,
+            en_passant: self.en_passant.map(|(x, y)| (7 - x, y)),
+        })
+    }
+
+    fn move_piece(&mut self, from: (u8, u8), to: (u8, u8)) -> Result<()> {
         let mut piece = self.pieces[from.1 as usize][from.0 as usize];
-        assert!(piece != Piece::None);
+        if piece == Piece::None {
+            bail!("There is no piece to move on {:?}", from);
+        }
+        self.en_passant =
+            if piece.kind() == Some(PieceKind::Pawn) && from.1.max(to.1) - from.1.min(to.1) == 2 {
+                Some((from.0, (from.1 + to.1) / 2))
+            } else {
+                None
+            };
         piece.has_moved();
         self.pieces[from.1 as usize][from.0 as usize] = Piece::None;
         self.pieces[to.1 as usize][to.0 as usize] = piece;
@@ -141,6 +243,7 @@ impl BoardState {
             CurrentPlayer::White => CurrentPlayer::Black,
             CurrentPlayer::Black => CurrentPlayer::White,
         };
+        Ok(())
     }
 
     fn set_piece(&mut self, position: (u8, u8), piece: Piece) {
@@ -152,7 +255,7 @@ impl BoardState {
         tile: (u8, u8),
         movements: &[(i8, i8)],
         expected: &[Piece],
-    ) -> (Piece, u8, u8) {
+    ) -> Result<(Piece, u8, u8)> {
         for movement in movements {
             let mut position = tile;
             loop {
@@ -168,7 +271,7 @@ impl BoardState {
                 let piece = self.get_piece(position.0, position.1);
                 for e in expected {
                     if piece == *e {
-                        return (*e, position.0, position.1);
+                        return Ok((*e, position.0, position.1));
                     }
                 }
                 if piece != Piece::None {
@@ -176,34 +279,32 @@ impl BoardState {
                 }
             }
         }
-        panic!("Could not find requested piece");
+        bail!("Could not find {:?} to move to {:?}", expected[0], tile);
     }
 
     fn move_pawn(&mut self, to: &str) -> Result<()> {
-        let (x, y) = BoardState::get_position(to);
+        let (x, y) = BoardState::get_position(to)?;
         match self.current_player {
             CurrentPlayer::White => {
                 // find pawns below this
-                for check_y in (y - 2..y).rev() {
+                for check_y in (y.saturating_sub(2)..y).rev() {
                     if self.get_piece(x, check_y) == Piece::WhitePawn
                         || self.get_piece(x, check_y) == Piece::WhitePawnMoved
                     {
-                        self.move_piece((x, check_y), (x, y));
-                        self.try_promote_pawn((x, y), to);
-                        return Ok(());
+                        self.move_piece((x, check_y), (x, y))?;
+                        return self.try_promote_pawn((x, y), to);
                     }
                 }
                 bail!("Could not find pawn to move to {:?}", to);
             }
             CurrentPlayer::Black => {
                 // find pawns above this
-                for check_y in y + 1..y + 3 {
+                for check_y in y + 1..(y + 3).min(8) {
                     if self.get_piece(x, check_y) == Piece::BlackPawn
                         || self.get_piece(x, check_y) == Piece::BlackPawnMoved
                     {
-                        self.move_piece((x, check_y), (x, y));
-                        self.try_promote_pawn((x, y), to);
-                        return Ok(());
+                        self.move_piece((x, check_y), (x, y))?;
+                        return self.try_promote_pawn((x, y), to);
                     }
                 }
                 bail!("Could not find pawn to move to {:?}", to);
@@ -211,59 +312,53 @@ impl BoardState {
         }
     }
 
-    fn try_promote_pawn(&mut self, target: (u8, u8), command: &str) {
-        if target.1 == 7 || target.1 == 0 {
-            let bytes = command.as_bytes();
-            let mut space_position = bytes.len() - 2;
-            while space_position != 0 {
-                if bytes[space_position] == b'=' {
-                    break;
-                }
-                space_position -= 1;
-            }
-            assert_eq!(b'=', bytes[space_position]);
-            match bytes[space_position + 1] {
-                b'Q' => self.set_piece(
-                    target,
-                    match target.1 {
-                        7 => Piece::WhiteQueen,
-                        0 => Piece::BlackQueen,
-                        _ => unreachable!(),
-                    },
-                ),
-                _ => unimplemented!(),
-            }
+    fn try_promote_pawn(&mut self, target: (u8, u8), command: &str) -> Result<()> {
+        if target.1 != 7 && target.1 != 0 {
+            return Ok(());
         }
+        let kind = match command.rfind('=').map(|i| &command.as_bytes()[i + 1..]) {
+            Some([b'Q', ..]) => PieceKind::Queen,
+            Some([b'R', ..]) => PieceKind::Rook,
+            Some([b'B', ..]) => PieceKind::Bishop,
+            Some([b'N', ..]) => PieceKind::Knight,
+            _ => bail!("Expected a piece to promote to in {:?}", command),
+        };
+        let player = match target.1 {
+            7 => CurrentPlayer::White,
+            _ => CurrentPlayer::Black,
+        };
+        self.set_piece(target, Piece::new(kind, player));
+        Ok(())
     }
 
     fn capture_with_pawn(&mut self, target: &str, column: &str) -> Result<()> {
-        let (x, y) = BoardState::get_position(target);
-        let (source_x, _) = BoardState::get_position(&format!("{}1", column));
+        let (x, y) = BoardState::get_position(target)?;
+        let (source_x, _) = BoardState::get_position(&format!("{}1", column))?;
         let source_y = match self.current_player {
-            CurrentPlayer::White => y - 1,
-            CurrentPlayer::Black => y + 1,
+            CurrentPlayer::White if y > 0 => y - 1,
+            CurrentPlayer::Black if y < 7 => y + 1,
+            _ => bail!("No pawn can capture on {:?}", target),
         };
+        let pawn = self.get_piece(source_x, source_y);
+        if pawn.kind() != Some(PieceKind::Pawn) || pawn.owner() != Some(self.current_player) {
+            bail!("Could not find pawn to capture on {:?}", target);
+        }
         if Piece::None == self.get_piece(x, y) {
-            let target_y = match self.current_player {
-                CurrentPlayer::White => y - 1,
-                CurrentPlayer::Black => y + 1,
-            };
-            let en_passant = self.get_piece(x, target_y);
+            let en_passant = self.get_piece(x, source_y);
             if en_passant != Piece::WhitePawnMoved && en_passant != Piece::BlackPawnMoved {
-                panic!(
+                bail!(
                     "Can not capture piece, expected PawnMove, got {:?}",
                     en_passant
                 );
             }
-            self.set_piece((x, target_y), Piece::None);
+            self.set_piece((x, source_y), Piece::None);
         }
-        self.move_piece((source_x, source_y), (x, y));
-        self.try_promote_pawn((x, y), target);
-        Ok(())
+        self.move_piece((source_x, source_y), (x, y))?;
+        self.try_promote_pawn((x, y), target)
     }
 
     fn bishop_move_to(&mut self, target: &str) -> Result<()> {
-        let (x, y) = BoardState::get_position(target);
+        let (x, y) = BoardState::get_position(target)?;
         let (_, from_x, from_y) = self.find_piece(
             (x, y),
             &[(-1, -1), (1, -1), (1, 1), (-1, 1)],
@@ -272,24 +367,20 @@ impl BoardState {
             } else {
                 Piece::BlackBishop
             }],
-        );
-        self.move_piece((from_x, from_y), (x, y));
-        Ok(())
+        )?;
+        self.move_piece((from_x, from_y), (x, y))
     }
 
     fn knight_move_to(&mut self, target: &str) -> Result<()> {
-        let mut offset = if &target[1..2] == "x" { 1 } else { 0 };
+        let bytes = target.as_bytes();
+        let mut offset = if bytes.get(1) == Some(&b'x') { 1 } else { 0 };
         let mut start_column = None;
-        if target.as_bytes()[0] >= b'a'
-            && target.as_bytes()[0] <= b'h'
-            && target.as_bytes()[1 + offset] >= b'a'
-            && target.as_bytes()[1 + offset] <= b'h'
-        {
-            let start = BoardState::get_position(&format!("{}1", target.as_bytes()[0] as char));
+        if is_file(bytes.first()) && is_file(bytes.get(1 + offset)) {
+            let start = BoardState::get_position(&format!("{}1", bytes[0] as char))?;
             start_column = Some(start.0);
             offset += 1;
         }
-        let (x, y) = BoardState::get_position(&target[offset..]);
+        let (x, y) = BoardState::get_position(&target[offset..])?;
         let min_x = if x > 2 { x - 2 } else { 0 };
         let max_x = if x < 6 { x + 2 } else { 7 };
         let min_y = if y > 2 { y - 2 } else { 0 };
@@ -307,8 +398,7 @@ impl BoardState {
                     let delta_x = (x as i8 - source_x as i8).abs();
                     let delta_y = (y as i8 - source_y as i8).abs();
                     if (delta_x == 2 && delta_y == 1) || (delta_y == 2 && delta_x == 1) {
-                        self.move_piece((source_x, source_y), (x, y));
-                        return Ok(());
+                        return self.move_piece((source_x, source_y), (x, y));
                     }
                 }
             }
@@ -317,7 +407,7 @@ impl BoardState {
     }
 
     fn queen_move_to(&mut self, target: &str) -> Result<()> {
-        let (x, y) = BoardState::get_position(target);
+        let (x, y) = BoardState::get_position(target)?;
         let (_, from_x, from_y) = self.find_piece(
             (x, y),
             &[
@@ -335,9 +425,8 @@ impl BoardState {
             } else {
                 Piece::BlackQueen
             }],
-        );
-        self.move_piece((from_x, from_y), (x, y));
-        Ok(())
+        )?;
+        self.move_piece((from_x, from_y), (x, y))
     }
 
     fn rook_move_to(&mut self, target: &str) -> Result<()> {
@@ -346,40 +435,35 @@ impl BoardState {
         } else {
             &[Piece::BlackRook, Piece::BlackRookMoved]
         };
-        let offset = if &target[1..2] == "x" { 1 } else { 0 };
-        if target.as_bytes()[0] >= b'a'
-            && target.as_bytes()[0] <= b'h'
-            && target.as_bytes()[1 + offset] >= b'a'
-            && target.as_bytes()[1 + offset] <= b'h'
-        {
-            let (source_x, _) = BoardState::get_position(&format!("{}1", &target[..1]));
-            let (x, y) = BoardState::get_position(&target[offset + 1..]);
+        let bytes = target.as_bytes();
+        let offset = if bytes.get(1) == Some(&b'x') { 1 } else { 0 };
+        if is_file(bytes.first()) && is_file(bytes.get(1 + offset)) {
+            let (source_x, _) = BoardState::get_position(&format!("{}1", &target[..1]))?;
+            let (x, y) = BoardState::get_position(&target[offset + 1..])?;
             if source_x == x {
                 for source_y in 0..8 {
                     if source_y != y {
                         let piece = self.get_piece(source_x, source_y);
                         for e in expected {
                             if piece == *e {
-                                self.move_piece((source_x, source_y), (x, y));
-                                return Ok(());
+                                return self.move_piece((source_x, source_y), (x, y));
                             }
                         }
                     }
                 }
-            } else {
-                self.move_piece((source_x, y), (x, y));
-                return Ok(());
+            } else if expected.contains(&self.get_piece(source_x, y)) {
+                return self.move_piece((source_x, y), (x, y));
             }
+            bail!("Could not find rook to move to {:?}", target);
         }
-        let (x, y) = BoardState::get_position(target);
+        let (x, y) = BoardState::get_position(target)?;
         let (_, from_x, from_y) =
-            self.find_piece((x, y), &[(-1, 0), (0, 1), (1, 0), (0, -1)], expected);
-        self.move_piece((from_x, from_y), (x, y));
-        Ok(())
+            self.find_piece((x, y), &[(-1, 0), (0, 1), (1, 0), (0, -1)], expected)?;
+        self.move_piece((from_x, from_y), (x, y))
     }
 
     fn king_move_to(&mut self, target: &str) -> Result<()> {
-        let (x, y) = BoardState::get_position(target);
+        let (x, y) = BoardState::get_position(target)?;
         let expected_piece = if self.current_player == CurrentPlayer::White {
             &[Piece::WhiteKing, Piece::WhiteKingMoved]
         } else {
@@ -397,8 +481,7 @@ impl BoardState {
                 if piece != Piece::None {
                     for expected in expected_piece {
                         if *expected == piece {
-                            self.move_piece((new_x as u8, new_y as u8), (x, y));
-                            return Ok(());
+                            return self.move_piece((new_x as u8, new_y as u8), (x, y));
                         }
                     }
                 }
@@ -408,22 +491,21 @@ impl BoardState {
     }
 
     fn castle_long(&mut self) -> Result<()> {
+        if !self.castling_rights(self.current_player)[1] {
+            bail!("{:?} can not castle long", self.current_player);
+        }
         match self.current_player {
             CurrentPlayer::White => {
-                assert_eq!(Piece::WhiteKing, self.get_piece(3, 0));
-                assert_eq!(Piece::WhiteRook, self.get_piece(7, 0));
-                self.move_piece((3, 0), (5, 0));
-                self.move_piece((7, 0), (4, 0));
+                self.move_piece((3, 0), (5, 0))?;
+                self.move_piece((7, 0), (4, 0))?;
                 self.current_player = match self.current_player {
                     CurrentPlayer::White => CurrentPlayer::Black,
                     CurrentPlayer::Black => CurrentPlayer::White,
                 };
             }
             CurrentPlayer::Black => {
-                assert_eq!(Piece::BlackKing, self.get_piece(3, 7));
-                assert_eq!(Piece::BlackRook, self.get_piece(7, 7));
-                self.move_piece((3, 7), (5, 7));
-                self.move_piece((7, 7), (4, 7));
+                self.move_piece((3, 7), (5, 7))?;
+                self.move_piece((7, 7), (4, 7))?;
                 self.current_player = match self.current_player {
                     CurrentPlayer::White => CurrentPlayer::Black,
                     CurrentPlayer::Black => CurrentPlayer::White,
@@ -433,22 +515,21 @@ impl BoardState {
         Ok(())
     }
     fn castle_short(&mut self) -> Result<()> {
+        if !self.castling_rights(self.current_player)[0] {
+            bail!("{:?} can not castle short", self.current_player);
+        }
         match self.current_player {
             CurrentPlayer::White => {
-                assert_eq!(Piece::WhiteKing, self.get_piece(3, 0));
-                assert_eq!(Piece::WhiteRook, self.get_piece(0, 0));
-                self.move_piece((3, 0), (1, 0));
-                self.move_piece((0, 0), (2, 0));
+                self.move_piece((3, 0), (1, 0))?;
+                self.move_piece((0, 0), (2, 0))?;
                 self.current_player = match self.current_player {
                     CurrentPlayer::White => CurrentPlayer::Black,
                     CurrentPlayer::Black => CurrentPlayer::White,
                 };
             }
             CurrentPlayer::Black => {
-                assert_eq!(Piece::BlackKing, self.get_piece(3, 7));
-                assert_eq!(Piece::BlackRook, self.get_piece(0, 7));
-                self.move_piece((3, 7), (1, 7));
-                self.move_piece((0, 7), (2, 7));
+                self.move_piece((3, 7), (1, 7))?;
+                self.move_piece((0, 7), (2, 7))?;
                 self.current_player = match self.current_player {
                     CurrentPlayer::White => CurrentPlayer::Black,
                     CurrentPlayer::Black => CurrentPlayer::White,
@@ -458,8 +539,20 @@ impl BoardState {
         Ok(())
     }
 
+    /// Play a move in algebraic notation, like `Nf3`, `exd5` or `e8=Q`. A move that can't be
+    /// parsed or has no piece to play it returns an error and leaves the position unchanged. The
+    /// error is an `UnparseableMove` if the move isn't algebraic notation at all.
     pub fn make_move(&mut self, m: &str) -> Result<()> {
-        println!("{:?} {:?}", self.current_player, m);
+        if !is_algebraic(m) {
+            return Err(UnparseableMove(m.to_owned()).into());
+        }
+        let mut next = self.clone();
+        next.play_algebraic(m)?;
+        *self = next;
+        Ok(())
+    }
+
+    fn play_algebraic(&mut self, m: &str) -> Result<()> {
         if m.is_empty() {
             bail!("Empty move");
         }
@@ -470,9 +563,10 @@ impl BoardState {
             b'R' => self.rook_move_to(&m[1..]),
             b'K' => self.king_move_to(&m[1..]),
             _ => {
-                if m == "O-O" {
+                let castle = m.trim_end_matches(&['+', '#'][..]);
+                if castle == "O-O" {
                     self.castle_short()
-                } else if m == "O-O-O" {
+                } else if castle == "O-O-O" {
                     self.castle_long()
                 } else if m.as_bytes().len() >= 4 && m.as_bytes()[1] == b'x' {
                     self.capture_with_pawn(&m[2..], &m[..1])
@@ -483,6 +577,41 @@ impl BoardState {
         }
     }
 
+    /// Apply a move given by its from and to squares. Castling is given as the king moving two
+    /// squares. The move is not checked for legality, only that the current player has a piece
+    /// on the `from` square.
+    pub fn apply_move(&mut self, m: Move) -> Result<()> {
+        let player = self.current_player;
+        let piece = self.get_piece(m.from.0, m.from.1);
+        if piece.owner() != Some(player) {
+            bail!("{:?} can not move {:?} from {:?}", player, piece, m.from);
+        }
+        let distance_x = m.from.0.max(m.to.0) - m.from.0.min(m.to.0);
+        match piece.kind() {
+            Some(PieceKind::King) if distance_x == 2 => {
+                if m.to.0 < m.from.0 {
+                    self.castle_short()
+                } else {
+                    self.castle_long()
+                }
+            }
+            Some(PieceKind::Pawn) => {
+                if distance_x == 1 && self.get_piece(m.to.0, m.to.1) == Piece::None {
+                    // en passant, the captured pawn is next to the moving pawn
+                    self.set_piece((m.to.0, m.from.1), Piece::None);
+                }
+                self.move_piece(m.from, m.to)?;
+                if let Some(kind) = m.promotion {
+                    self.set_piece(m.to, Piece::new(kind, player));
+                }
+                Ok(())
+            }
+            _ => self.move_piece(m.from, m.to),
+        }
+    }
+
+    /// One plane of 64 squares for every `Piece` variant. See `features::Encoder` for an encoding
+    /// with the side to move, castling rights and history.
     pub fn to_piece_vec(&self) -> Vec<f32> {
         let mut result = Vec::with_capacity(Piece::BlackRookMoved as usize * 8 * 8);
         for i in Piece::WhiteKing as u8..=Piece::BlackRookMoved as u8 {
@@ -501,6 +630,142 @@ impl BoardState {
     }
 }
 
+/// Whether a byte of a move is a file, `a` to `h`.
+fn is_file(byte: Option<&u8>) -> bool {
+    byte.is_some_and(|b| (b'a'..=b'h').contains(b))
+}
+
+/// A move from one square to another, in the `(x, y)` coordinates of `BoardState::get_piece`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Move {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    pub promotion: Option<PieceKind>,
+}
+
+impl Move {
+    /// Find the move that was played between two positions, e.g. after calling
+    /// `BoardState::make_move` with a move in algebraic notation.
+    pub fn between(before: &BoardState, after: &BoardState) -> Result<Move> {
+        let player = before.current_player;
+        let (mut from, mut to) = (None, None);
+        let (mut king_from, mut king_to) = (None, None);
+        for y in 0..8 {
+            for x in 0..8 {
+                let old = before.get_piece(x, y);
+                let new = after.get_piece(x, y);
+                if old == new {
+                    continue;
+                }
+                if old.owner() == Some(player) && new == Piece::None {
+                    from = Some((x, y));
+                }
+                if new.owner() == Some(player) {
+                    to = Some((x, y));
+                }
+                if old.owner() == Some(player) && old.kind() == Some(PieceKind::King) {
+                    king_from = Some((x, y));
+                }
+                if new.owner() == Some(player) && new.kind() == Some(PieceKind::King) {
+                    king_to = Some((x, y));
+                }
+            }
+        }
+        // when castling the rook moves as well, but the move is described by the king
+        let (from, to) = match (king_from, king_to, from, to) {
+            (Some(from), Some(to), _, _) | (_, _, Some(from), Some(to)) => (from, to),
+            _ => bail!("Could not find the move between the two positions"),
+        };
+        let moved = before.get_piece(from.0, from.1);
+        let arrived = after.get_piece(to.0, to.1);
+        let promotion = if moved.kind() != arrived.kind() {
+            arrived.kind()
+        } else {
+            None
+        };
+        Ok(Move {
+            from,
+            to,
+            promotion,
+        })
+    }
+}
+
+/// The error of `BoardState::make_move` for a move that is not in algebraic notation, as opposed
+/// to one that can't be played in the position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnparseableMove(pub String);
+
+impl std::fmt::Display for UnparseableMove {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{:?} is not in algebraic notation", self.0)
+    }
+}
+
+impl std::error::Error for UnparseableMove {}
+
+/// Whether a move is in algebraic notation, like `e4`, `Nbxd7+`, `exd8=Q#` or `O-O`. It doesn't
+/// have to be legal.
+fn is_algebraic(m: &str) -> bool {
+    let m = m.trim_end_matches(['+', '#']);
+    if m == "O-O" || m == "O-O-O" {
+        return true;
+    }
+    let (m, promotion) = match m.find('=') {
+        Some(i) => (&m[..i], Some(&m[i + 1..])),
+        None => (m, None),
+    };
+    if let Some(promotion) = promotion {
+        if !["Q", "R", "B", "N"].contains(&promotion) {
+            return false;
+        }
+    }
+    let bytes = m.as_bytes();
+    let (piece, rest) = match bytes.first() {
+        Some(b'K') | Some(b'Q') | Some(b'R') | Some(b'B') | Some(b'N') => (true, &bytes[1..]),
+        _ => (false, bytes),
+    };
+    if piece && promotion.is_some() {
+        return false;
+    }
+    // The target square, after an optional file and rank of the piece and `x` for a capture.
+    let (target, from) = match rest.len() {
+        n if n >= 2 => (&rest[n - 2..], &rest[..n - 2]),
+        _ => return false,
+    };
+    let (from, capture) = match from.strip_suffix(b"x") {
+        Some(from) => (from, true),
+        None => (from, false),
+    };
+    let file = |c: &u8| (b'a'..=b'h').contains(c);
+    let rank = |c: &u8| (b'1'..=b'8').contains(c);
+    file(&target[0])
+        && rank(&target[1])
+        && match from {
+            // A pawn that captures needs its file.
+            [] => piece || !capture,
+            [c] => file(c) || (piece && rank(c)),
+            [f, r] => piece && file(f) && rank(r),
+            _ => false,
+        }
+}
+
+/// Formats the move in the coordinate notation UCI uses, e.g. `e2e4` or `e7e8q`.
+impl std::fmt::Display for Move {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (x, y) in &[self.from, self.to] {
+            write!(fmt, "{}{}", (b'a' + 7 - x) as char, (b'1' + y) as char)?;
+        }
+        match self.promotion {
+            Some(PieceKind::Knight) => write!(fmt, "n"),
+            Some(PieceKind::Bishop) => write!(fmt, "b"),
+            Some(PieceKind::Rook) => write!(fmt, "r"),
+            Some(PieceKind::Queen) => write!(fmt, "q"),
+            _ => Ok(()),
+        }
+    }
+}
+
 enum_from_primitive! {
     #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
     pub enum Piece {
@@ -528,7 +793,73 @@ enum_from_primitive! {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum PieceKind {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
 impl Piece {
+    /// The unmoved piece of the given kind and player.
+    pub fn new(kind: PieceKind, player: CurrentPlayer) -> Piece {
+        match (player, kind) {
+            (CurrentPlayer::White, PieceKind::Pawn) => Piece::WhitePawn,
+            (CurrentPlayer::White, PieceKind::Knight) => Piece::WhiteKnight,
+            (CurrentPlayer::White, PieceKind::Bishop) => Piece::WhiteBishop,
+            (CurrentPlayer::White, PieceKind::Rook) => Piece::WhiteRook,
+            (CurrentPlayer::White, PieceKind::Queen) => Piece::WhiteQueen,
+            (CurrentPlayer::White, PieceKind::King) => Piece::WhiteKing,
+            (CurrentPlayer::Black, PieceKind::Pawn) => Piece::BlackPawn,
+            (CurrentPlayer::Black, PieceKind::Knight) => Piece::BlackKnight,
+            (CurrentPlayer::Black, PieceKind::Bishop) => Piece::BlackBishop,
+            (CurrentPlayer::Black, PieceKind::Rook) => Piece::BlackRook,
+            (CurrentPlayer::Black, PieceKind::Queen) => Piece::BlackQueen,
+            (CurrentPlayer::Black, PieceKind::King) => Piece::BlackKing,
+        }
+    }
+
+    pub fn kind(self) -> Option<PieceKind> {
+        match self {
+            Piece::None => None,
+            Piece::WhitePawn | Piece::WhitePawnMoved | Piece::BlackPawn | Piece::BlackPawnMoved => {
+                Some(PieceKind::Pawn)
+            }
+            Piece::WhiteKnight | Piece::BlackKnight => Some(PieceKind::Knight),
+            Piece::WhiteBishop | Piece::BlackBishop => Some(PieceKind::Bishop),
+            Piece::WhiteRook | Piece::WhiteRookMoved | Piece::BlackRook | Piece::BlackRookMoved => {
+                Some(PieceKind::Rook)
+            }
+            Piece::WhiteQueen | Piece::BlackQueen => Some(PieceKind::Queen),
+            Piece::WhiteKing | Piece::WhiteKingMoved | Piece::BlackKing | Piece::BlackKingMoved => {
+                Some(PieceKind::King)
+            }
+        }
+    }
+
+    pub fn owner(self) -> Option<CurrentPlayer> {
+        if self == Piece::None {
+            None
+        } else if (self as u8) < Piece::BlackKing as u8 {
+            Some(CurrentPlayer::White)
+        } else {
+            Some(CurrentPlayer::Black)
+        }
+    }
+
+    /// The same piece, owned by the other player.
+    pub fn flipped(self) -> Piece {
+        let offset = Piece::BlackKing as u8 - Piece::WhiteKing as u8;
+        match self.owner() {
+            None => Piece::None,
+            Some(CurrentPlayer::White) => Piece::from_u8(self as u8 + offset).unwrap(),
+            Some(CurrentPlayer::Black) => Piece::from_u8(self as u8 - offset).unwrap(),
+        }
+    }
+
     pub fn has_moved(&mut self) {
         match self {
             Piece::WhiteKing => *self = Piece::WhiteKingMoved,
@@ -542,3 +873,53 @@ impl Piece {
         }
     }
 }
+
+#[test]
+fn test_make_move_errors() {
+    let mut state = BoardState::init();
+    for m in &[
+        "", "x", "Q", "Nx", "e", "e9", "i4", "xxx", "e5", "exd3", "hxg3", "Rae4", "Qd4", "Kxé4",
+        "é4", "Ra",
+    ] {
+        let error = state.make_move(m).unwrap_err();
+        assert_eq!(BoardState::init().pieces, state.pieces);
+        assert_eq!(CurrentPlayer::White, state.current_player);
+        // Only the moves that are algebraic notation get to the board.
+        let playable = ["e5", "exd3", "hxg3", "Rae4", "Qd4"].contains(m);
+        let unparseable = error.downcast_ref::<UnparseableMove>();
+        assert_eq!(!playable, unparseable.is_some(), "{:?}", m);
+    }
+}
+
+#[test]
+fn test_is_algebraic() {
+    for m in &[
+        "e4", "exd5", "Nf3", "Nbd7", "R1e2", "Qh4xe1+", "exd8=Q#", "O-O-O", "Kxf7",
+    ] {
+        assert!(is_algebraic(m), "{}", m);
+    }
+    for m in &[
+        "", "Zz9", "e9", "i4", "Nf3=Q", "e8=K", "O-O-O-O", "xe4", "e2e4",
+    ] {
+        assert!(!is_algebraic(m), "{}", m);
+    }
+}
+
+#[test]
+fn test_promotions() {
+    let mut state = BoardState::init();
+    for m in &["a4", "h5", "a5", "h4", "a6", "h3", "axb7", "hxg2"] {
+        state.make_move(m).unwrap();
+    }
+    assert!(state.make_move("bxa8").is_err());
+    state.make_move("bxa8=N").unwrap();
+    state.make_move("gxh1=B+").unwrap();
+    assert_eq!(Piece::WhiteKnight, state.get_piece(7, 7));
+    assert_eq!(Piece::BlackBishop, state.get_piece(0, 0));
+
+    let mut state = BoardState::init();
+    for m in &["e4", "e5", "Nf3", "Nc6", "Bc4", "Nf6", "O-O+"] {
+        state.make_move(m).unwrap();
+    }
+    assert_eq!(Piece::WhiteKingMoved, state.get_piece(1, 0));
+}
@@ -0,0 +1,659 @@
+//! Monte Carlo Tree Search with PUCT, in the style of AlphaZero.
+//!
+//! Every playout walks down the tree picking the child with the highest `Q + U`, where `Q` is the
+//! mean value of the child and `U = c_puct * prior * sqrt(N(parent)) / (1 + N(child))`. The leaf it
+//! ends in is evaluated with an `Evaluate`, its children get the priors of the policy, and the
+//! value is added to every node on the way back up.
+//!
+//! Positions in the tablebases of the engine options are not evaluated, but are leaves with the
+//! result of the tables. When the root is in the tables, only the moves that keep its result are
+//! searched.
+//!
+//! Leaves are evaluated in batches. While a batch is collected, every node on the path to a leaf
+//! gets a virtual loss, so the next playouts of the batch look elsewhere. Values in the tree are
+//! between -1 and 1, from the view of the player that made the move into the node.
+
+use crate::evaluation::StaticEval;
+use crate::nnue::Nnue;
+use crate::options::Options;
+use crate::policy::Policy;
+use crate::syzygy::Wdl;
+use crate::{BoardState, CurrentPlayer, Move, Result};
+use random::Source;
+
+/// The evaluation of a position by a neural network, or anything else that can estimate it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    /// The probability that white wins, counting a draw as half a win.
+    pub value: f32,
+    /// The probability of every legal move. Without it, all moves get the same prior.
+    pub policy: Option<Policy>,
+}
+
+pub trait Evaluate {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<Evaluation>>;
+}
+
+/// The probability that white wins, from a score in centipawns for white.
+fn win_probability(score: i32) -> f32 {
+    1.0 / (1.0 + 10f32.powf(-score as f32 / 400.0))
+}
+
+/// The static evaluation, with its score turned into a probability of winning. It has no policy.
+impl Evaluate for StaticEval {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<Evaluation>> {
+        Ok(states
+            .iter()
+            .map(|state| Evaluation {
+                value: win_probability(self.evaluate(state)),
+                policy: None,
+            })
+            .collect())
+    }
+}
+
+/// The NNUE, in the same way as the static evaluation. The leaves of a batch are on different
+/// lines of the tree, so their accumulators are computed from scratch instead of with an
+/// `AccumulatorStack`.
+impl Evaluate for Nnue {
+    fn evaluate_batch(&mut self, states: &[BoardState]) -> Result<Vec<Evaluation>> {
+        Ok(states
+            .iter()
+            .map(|state| {
+                let score = self.evaluate_state(state);
+                let white = match state.current_player {
+                    CurrentPlayer::White => score,
+                    CurrentPlayer::Black => -score,
+                };
+                Evaluation {
+                    value: win_probability(white),
+                    policy: None,
+                }
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MctsOptions {
+    /// How much the priors count against the values of the children.
+    pub c_puct: f32,
+    /// The alpha of the Dirichlet noise added to the priors at the root, 0 to not add noise.
+    pub dirichlet_alpha: f64,
+    /// The part of the root priors that is replaced by noise.
+    pub dirichlet_fraction: f32,
+    /// The number of leaves that are evaluated at once.
+    pub batch_size: usize,
+    /// The number of losses a playout adds to its path until its leaf is evaluated.
+    pub virtual_loss: u32,
+}
+
+impl Default for MctsOptions {
+    fn default() -> MctsOptions {
+        MctsOptions {
+            c_puct: 1.5,
+            dirichlet_alpha: 0.3,
+            dirichlet_fraction: 0.25,
+            batch_size: 8,
+            virtual_loss: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expansion {
+    No,
+    /// In the batch that is being collected.
+    Pending,
+    Yes,
+    /// The game is over, with the value for the player that moved into the node.
+    Terminal(f32),
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    state: BoardState,
+    /// The move from the parent to this node.
+    m: Option<Move>,
+    prior: f32,
+    visits: u32,
+    virtual_visits: u32,
+    /// The sum of the values of the playouts through this node.
+    value_sum: f32,
+    children: Vec<usize>,
+    expansion: Expansion,
+}
+
+impl Node {
+    fn new(state: BoardState, m: Option<Move>, prior: f32) -> Node {
+        Node {
+            state,
+            m,
+            prior,
+            visits: 0,
+            virtual_visits: 0,
+            value_sum: 0.0,
+            children: Vec::new(),
+            expansion: Expansion::No,
+        }
+    }
+}
+
+/// A search tree, which can be kept between moves with `advance`.
+pub struct Mcts {
+    pub options: MctsOptions,
+    /// All nodes, with the root at index 0.
+    nodes: Vec<Node>,
+    /// Whether the priors of the root have noise added to them.
+    noise: bool,
+    /// The only moves to search at the root, when it is in the tablebases.
+    root_moves: Option<Vec<Move>>,
+}
+
+/// A sample of the gamma distribution with scale 1, with the method of Marsaglia and Tsang.
+fn gamma(alpha: f64, source: &mut impl Source) -> f64 {
+    if alpha < 1.0 {
+        let u = source.read_f64();
+        return gamma(alpha + 1.0, source) * u.powf(1.0 / alpha);
+    }
+    let d = alpha - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        // A normal sample, with the Box-Muller transform.
+        let (u1, u2) = (1.0 - source.read_f64(), source.read_f64());
+        let x = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u = 1.0 - source.read_f64();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+/// A sample of the symmetric Dirichlet distribution with `len` values.
+pub fn dirichlet(alpha: f64, len: usize, source: &mut impl Source) -> Vec<f32> {
+    let samples: Vec<f64> = (0..len).map(|_| gamma(alpha, source)).collect();
+    let total: f64 = samples.iter().sum();
+    if total <= 0.0 {
+        return vec![1.0 / len as f32; len];
+    }
+    samples.iter().map(|s| (s / total) as f32).collect()
+}
+
+/// The value of the position for the player to move, from the probability that white wins.
+fn player_value(state: &BoardState, white_wins: f32) -> f32 {
+    match state.current_player {
+        CurrentPlayer::White => white_wins * 2.0 - 1.0,
+        CurrentPlayer::Black => 1.0 - white_wins * 2.0,
+    }
+}
+
+impl Mcts {
+    pub fn new(state: BoardState, options: MctsOptions) -> Mcts {
+        Mcts {
+            options,
+            nodes: vec![Node::new(state, None, 1.0)],
+            noise: false,
+            root_moves: None,
+        }
+    }
+
+    pub fn root(&self) -> &BoardState {
+        &self.nodes[0].state
+    }
+
+    /// The number of playouts through the root, including those of earlier searches that were
+    /// kept by `advance`.
+    pub fn visits(&self) -> u32 {
+        self.nodes[0].visits
+    }
+
+    /// The mean value of the root for the player to move, between -1 and 1.
+    pub fn value(&self) -> f32 {
+        let root = &self.nodes[0];
+        if root.visits == 0 {
+            return 0.0;
+        }
+        -root.value_sum / root.visits as f32
+    }
+
+    /// Run `playouts` more playouts.
+    pub fn search(
+        &mut self,
+        evaluator: &mut impl Evaluate,
+        playouts: usize,
+        source: &mut impl Source,
+    ) -> Result<()> {
+        self.search_with(evaluator, &mut Options::default(), playouts, source)
+    }
+
+    /// Run `playouts` more playouts, with the tablebases of `engine` if it has them.
+    pub fn search_with(
+        &mut self,
+        evaluator: &mut impl Evaluate,
+        engine: &mut Options,
+        playouts: usize,
+        source: &mut impl Source,
+    ) -> Result<()> {
+        if self.root_moves.is_none() {
+            if let Some(moves) = engine.tablebase_moves(self.root()) {
+                let nodes = &self.nodes;
+                let children = self.nodes[0]
+                    .children
+                    .iter()
+                    .cloned()
+                    .filter(|child| nodes[*child].m.is_some_and(|m| moves.contains(&m)))
+                    .collect();
+                self.nodes[0].children = children;
+                self.root_moves = Some(moves);
+            }
+        }
+
+        let mut done = 0;
+        while done < playouts {
+            let mut batch: Vec<Vec<usize>> = Vec::new();
+            while batch.len() < self.options.batch_size.max(1) && done + batch.len() < playouts {
+                let path = self.select();
+                let leaf = path[path.len() - 1];
+                match self.nodes[leaf].expansion {
+                    Expansion::Terminal(value) => {
+                        self.backup(&path, value, self.options.virtual_loss);
+                        done += 1;
+                    }
+                    // Another playout of the batch is already waiting for this leaf.
+                    Expansion::Pending => {
+                        self.undo_virtual_loss(&path);
+                        break;
+                    }
+                    _ => {
+                        if self.nodes[leaf].state.legal_moves().is_empty() {
+                            let state = &self.nodes[leaf].state;
+                            // Checkmate is a win for the player that moved into the node.
+                            let value = if state.is_in_check(state.current_player) {
+                                1.0
+                            } else {
+                                0.0
+                            };
+                            self.nodes[leaf].expansion = Expansion::Terminal(value);
+                            self.backup(&path, value, self.options.virtual_loss);
+                            done += 1;
+                        } else if let Some(value) = self.tablebase_value(leaf, engine) {
+                            self.nodes[leaf].expansion = Expansion::Terminal(value);
+                            self.backup(&path, value, self.options.virtual_loss);
+                            done += 1;
+                        } else {
+                            self.nodes[leaf].expansion = Expansion::Pending;
+                            batch.push(path);
+                        }
+                    }
+                }
+            }
+            if batch.is_empty() {
+                continue;
+            }
+
+            let states: Vec<BoardState> = batch
+                .iter()
+                .map(|path| self.nodes[path[path.len() - 1]].state.clone())
+                .collect();
+            let evaluations = evaluator.evaluate_batch(&states)?;
+            if evaluations.len() != states.len() {
+                bail!(
+                    "Asked to evaluate {} positions, got {} evaluations",
+                    states.len(),
+                    evaluations.len()
+                );
+            }
+            for (path, evaluation) in batch.iter().zip(evaluations) {
+                let leaf = path[path.len() - 1];
+                self.expand(leaf, evaluation.policy);
+                let value = -player_value(&self.nodes[leaf].state, evaluation.value);
+                self.backup(path, value, self.options.virtual_loss);
+                done += 1;
+            }
+            if !self.noise
+                && self.options.dirichlet_alpha > 0.0
+                && !self.nodes[0].children.is_empty()
+            {
+                self.add_noise(source);
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of a leaf below the root for the player that moved into it, if it is in the
+    /// tablebases. Cursed wins and blessed losses are draws with the 50 move rule.
+    fn tablebase_value(&self, leaf: usize, engine: &mut Options) -> Option<f32> {
+        let tablebases = engine.tablebases()?;
+        let state = &self.nodes[leaf].state;
+        if leaf == 0 || !tablebases.can_probe(state) {
+            return None;
+        }
+        Some(match tablebases.probe_wdl(state).ok()? {
+            Wdl::Win => -1.0,
+            Wdl::Loss => 1.0,
+            _ => 0.0,
+        })
+    }
+
+    /// Walk down from the root to a node that is not expanded yet, adding virtual losses on the
+    /// way.
+    fn select(&mut self) -> Vec<usize> {
+        let mut path = vec![0];
+        let mut node = 0;
+        loop {
+            self.nodes[node].virtual_visits += self.options.virtual_loss;
+            if self.nodes[node].expansion != Expansion::Yes {
+                return path;
+            }
+            let parent = &self.nodes[node];
+            let parent_visits = (parent.visits + parent.virtual_visits) as f32;
+            let explore = self.options.c_puct * parent_visits.sqrt();
+            let mut best = (f32::NEG_INFINITY, 0);
+            for &child in &parent.children {
+                let child_node = &self.nodes[child];
+                let visits = child_node.visits + child_node.virtual_visits;
+                // Virtual visits count as losses.
+                let q = if visits > 0 {
+                    (child_node.value_sum - child_node.virtual_visits as f32) / visits as f32
+                } else {
+                    0.0
+                };
+                let u = explore * child_node.prior / (1 + visits) as f32;
+                if q + u > best.0 {
+                    best = (q + u, child);
+                }
+            }
+            node = best.1;
+            path.push(node);
+        }
+    }
+
+    fn undo_virtual_loss(&mut self, path: &[usize]) {
+        for node in path {
+            self.nodes[*node].virtual_visits -= self.options.virtual_loss;
+        }
+    }
+
+    /// Add the value of a playout to the nodes of its path, and take off its virtual loss. `value`
+    /// is for the player that moved into the leaf, and changes sides at every step up.
+    fn backup(&mut self, path: &[usize], mut value: f32, virtual_loss: u32) {
+        for node in path.iter().rev() {
+            let node = &mut self.nodes[*node];
+            node.virtual_visits -= virtual_loss;
+            node.visits += 1;
+            node.value_sum += value;
+            value = -value;
+        }
+    }
+
+    fn expand(&mut self, leaf: usize, policy: Option<Policy>) {
+        let state = self.nodes[leaf].state.clone();
+        let policy = policy.unwrap_or_else(|| {
+            let moves = state.legal_moves();
+            let prior = 1.0 / moves.len() as f32;
+            moves.into_iter().map(|m| (m, prior)).collect()
+        });
+        for (m, prior) in policy {
+            if leaf == 0
+                && self
+                    .root_moves
+                    .as_ref()
+                    .is_some_and(|moves| !moves.contains(&m))
+            {
+                continue;
+            }
+            let mut child = state.clone();
+            if child.apply_move(m).is_err() {
+                continue;
+            }
+            self.nodes.push(Node::new(child, Some(m), prior));
+            let index = self.nodes.len() - 1;
+            self.nodes[leaf].children.push(index);
+        }
+        self.nodes[leaf].expansion = Expansion::Yes;
+    }
+
+    /// Mix Dirichlet noise into the priors of the children of the root, so self-play tries other
+    /// moves than the network likes best.
+    fn add_noise(&mut self, source: &mut impl Source) {
+        let children = self.nodes[0].children.clone();
+        let noise = dirichlet(self.options.dirichlet_alpha, children.len(), source);
+        let fraction = self.options.dirichlet_fraction;
+        for (child, noise) in children.iter().zip(noise) {
+            let prior = &mut self.nodes[*child].prior;
+            *prior = (1.0 - fraction) * *prior + fraction * noise;
+        }
+        self.noise = true;
+    }
+
+    /// The number of visits of every move of the root.
+    pub fn visit_counts(&self) -> Vec<(Move, u32)> {
+        self.nodes[0]
+            .children
+            .iter()
+            .filter_map(|child| {
+                let node = &self.nodes[*child];
+                node.m.map(|m| (m, node.visits))
+            })
+            .collect()
+    }
+
+    /// The visit counts turned into probabilities, with `visits ^ (1 / temperature)`. A temperature
+    /// of 0 gives all of the probability to the most visited move.
+    pub fn policy(&self, temperature: f32) -> Policy {
+        let counts = self.visit_counts();
+        if temperature <= 0.0 {
+            let best = counts.iter().map(|(_, visits)| *visits).max().unwrap_or(0);
+            let mut found = false;
+            return counts
+                .into_iter()
+                .map(|(m, visits)| {
+                    let pick = visits == best && !found;
+                    found |= pick;
+                    (m, if pick { 1.0 } else { 0.0 })
+                })
+                .collect();
+        }
+        let weights: Vec<f64> = counts
+            .iter()
+            .map(|(_, visits)| f64::from(*visits).powf(1.0 / f64::from(temperature)))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        counts
+            .iter()
+            .zip(weights)
+            .map(|((m, _), weight)| {
+                let probability = if total > 0.0 { weight / total } else { 0.0 };
+                (*m, probability as f32)
+            })
+            .collect()
+    }
+
+    /// Pick a move at random by `policy(temperature)`.
+    pub fn select_move(&self, temperature: f32, source: &mut impl Source) -> Option<Move> {
+        let policy = self.policy(temperature);
+        let mut pick = source.read_f64() as f32;
+        for (m, probability) in &policy {
+            if pick < *probability {
+                return Some(*m);
+            }
+            pick -= probability;
+        }
+        // Rounding can leave a bit of probability at the end.
+        policy
+            .iter()
+            .rev()
+            .find(|(_, probability)| *probability > 0.0)
+            .map(|(m, _)| *m)
+    }
+
+    /// Play a move, keeping the part of the tree below it. Noise is added again to the priors
+    /// of the new root at the next search.
+    pub fn advance(&mut self, m: Move) -> Result<()> {
+        let child = self.nodes[0]
+            .children
+            .iter()
+            .cloned()
+            .find(|child| self.nodes[*child].m == Some(m));
+        let child = match child {
+            Some(child) => child,
+            None => {
+                let mut state = self.nodes[0].state.clone();
+                state.apply_move(m)?;
+                self.nodes = vec![Node::new(state, None, 1.0)];
+                self.noise = false;
+                self.root_moves = None;
+                return Ok(());
+            }
+        };
+
+        // Copy the subtree into a new list, so the rest of the tree is dropped.
+        let mut nodes = Vec::new();
+        let mut stack = vec![(child, None)];
+        while let Some((old, parent)) = stack.pop() {
+            let mut node = std::mem::replace(
+                &mut self.nodes[old],
+                Node::new(BoardState::empty(), None, 0.0),
+            );
+            let children = std::mem::take(&mut node.children);
+            nodes.push(node);
+            let index = nodes.len() - 1;
+            if let Some(parent) = parent {
+                let parent: &mut Node = &mut nodes[parent];
+                parent.children.push(index);
+            }
+            stack.extend(children.into_iter().rev().map(|c| (c, Some(index))));
+        }
+        nodes[0].m = None;
+        nodes[0].prior = 1.0;
+        self.nodes = nodes;
+        self.noise = false;
+        self.root_moves = None;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dirichlet() {
+    let mut source = random::default().seed([1, 1]);
+    let noise = dirichlet(0.3, 20, &mut source);
+    assert_eq!(20, noise.len());
+    assert!(noise.iter().all(|n| *n >= 0.0));
+    assert!((noise.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_finds_mate_in_one() {
+    use crate::Piece;
+    // White to move, Ra8 is mate against the king behind its pawns.
+    let mut state = BoardState::empty();
+    state.set_piece((1, 0), Piece::WhiteKing);
+    state.set_piece((7, 0), Piece::WhiteRook);
+    state.set_piece((0, 7), Piece::BlackKing);
+    state.set_piece((0, 6), Piece::BlackPawn);
+    state.set_piece((1, 6), Piece::BlackPawn);
+    state.set_piece((2, 6), Piece::BlackPawn);
+
+    let mut source = random::default().seed([2, 2]);
+    let mut mcts = Mcts::new(
+        state,
+        MctsOptions {
+            dirichlet_alpha: 0.0,
+            ..MctsOptions::default()
+        },
+    );
+    mcts.search(&mut StaticEval::default(), 400, &mut source)
+        .unwrap();
+    assert_eq!(400, mcts.visits());
+    let best = mcts.select_move(0.0, &mut source).unwrap();
+    assert_eq!("a1a8", best.to_string());
+    assert!(mcts.value() > 0.5);
+
+    // The search below the mate is kept, and the game is over there.
+    let visits = mcts
+        .visit_counts()
+        .iter()
+        .find(|(m, _)| *m == best)
+        .unwrap()
+        .1;
+    mcts.advance(best).unwrap();
+    assert_eq!(visits, mcts.visits());
+    assert!(mcts.root().legal_moves().is_empty());
+}
+
+#[test]
+fn test_policy_temperature() {
+    let mut source = random::default().seed([3, 3]);
+    let mut mcts = Mcts::new(BoardState::init(), MctsOptions::default());
+    mcts.search(&mut StaticEval::default(), 100, &mut source)
+        .unwrap();
+    let counts = mcts.visit_counts();
+    assert_eq!(20, counts.len());
+    assert_eq!(99, counts.iter().map(|(_, visits)| visits).sum::<u32>());
+
+    let policy = mcts.policy(1.0);
+    assert!((policy.iter().map(|(_, p)| p).sum::<f32>() - 1.0).abs() < 1e-4);
+    let greedy = mcts.policy(0.0);
+    assert_eq!(1, greedy.iter().filter(|(_, p)| *p == 1.0).count());
+    let best = counts.iter().map(|(_, visits)| *visits).max().unwrap();
+    let picked = greedy.iter().position(|(_, p)| *p == 1.0).unwrap();
+    assert_eq!(best, counts[picked].1);
+}
+
+#[test]
+fn test_nnue_search() {
+    let mut source = random::default().seed([5, 5]);
+    let mut network = Nnue::random(16, &[8], &mut source);
+    let mut state = BoardState::init();
+    state.make_move("e4").unwrap();
+    // The network scores from the side to move, the evaluation is for white.
+    let evaluations = network
+        .evaluate_batch(&[state.clone(), state.flipped()])
+        .unwrap();
+    assert!((evaluations[0].value + evaluations[1].value - 1.0).abs() < 1e-6);
+
+    let mut mcts = Mcts::new(state, MctsOptions::default());
+    mcts.search(&mut network, 50, &mut source).unwrap();
+    assert_eq!(50, mcts.visits());
+}
+
+#[test]
+fn test_tablebase_leaves() {
+    use crate::Piece;
+    // White can take the last black piece, which leaves the kings alone: a draw by the tables,
+    // even without any table files.
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKingMoved);
+    state.set_piece((3, 1), Piece::BlackRookMoved);
+    state.set_piece((3, 7), Piece::BlackKingMoved);
+    let mut engine = Options::default();
+    engine
+        .set("SyzygyPath", env!("CARGO_MANIFEST_DIR"))
+        .unwrap();
+
+    let mut source = random::default().seed([4, 4]);
+    let mut mcts = Mcts::new(state.clone(), MctsOptions::default());
+    mcts.search_with(&mut StaticEval::default(), &mut engine, 50, &mut source)
+        .unwrap();
+    let capture = |mcts: &Mcts| {
+        mcts.nodes[0]
+            .children
+            .iter()
+            .map(|child| mcts.nodes[*child].clone())
+            .find(|node| node.m.map(|m| m.to_string()) == Some("e1e2".to_owned()))
+            .unwrap()
+    };
+    assert_eq!(Expansion::Terminal(0.0), capture(&mcts).expansion);
+    assert!(capture(&mcts).children.is_empty());
+
+    // Without tablebases the search goes on below it.
+    let mut mcts = Mcts::new(state, MctsOptions::default());
+    mcts.search(&mut StaticEval::default(), 50, &mut source)
+        .unwrap();
+    assert_eq!(Expansion::Yes, capture(&mcts).expansion);
+    assert!(!capture(&mcts).children.is_empty());
+}
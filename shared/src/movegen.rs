@@ -0,0 +1,331 @@
+//! Legal move generation for `BoardState`.
+//!
+//! Moves are generated per piece as pseudo-legal moves, and then applied to a copy of the board to
+//! see if they leave the own king in check. This is not fast, but simple enough to trust.
+
+use crate::{BoardState, CurrentPlayer, Move, Piece, PieceKind};
+
+const KNIGHT_MOVES: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const DIAGONALS: [(i8, i8); 4] = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+const STRAIGHTS: [(i8, i8); 4] = [(-1, 0), (0, 1), (1, 0), (0, -1)];
+const QUEEN_MOVES: [(i8, i8); 8] = [
+    (-1, -1),
+    (1, -1),
+    (1, 1),
+    (-1, 1),
+    (-1, 0),
+    (0, 1),
+    (1, 0),
+    (0, -1),
+];
+const PROMOTIONS: [PieceKind; 4] = [
+    PieceKind::Queen,
+    PieceKind::Rook,
+    PieceKind::Bishop,
+    PieceKind::Knight,
+];
+
+/// The direction pawns of the given player move in.
+#[inline]
+fn pawn_direction(player: CurrentPlayer) -> i8 {
+    match player {
+        CurrentPlayer::White => 1,
+        CurrentPlayer::Black => -1,
+    }
+}
+
+#[inline]
+fn offset(x: u8, y: u8, (dx, dy): (i8, i8)) -> Option<(u8, u8)> {
+    let x = x as i8 + dx;
+    let y = y as i8 + dy;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some((x as u8, y as u8))
+    } else {
+        None
+    }
+}
+
+/// Call `f` for every square the piece on `(x, y)` attacks. For pawns these are the diagonal
+/// squares in front of it, regardless of what is on them.
+pub(crate) fn for_each_attack(state: &BoardState, x: u8, y: u8, mut f: impl FnMut(u8, u8)) {
+    let piece = state.get_piece(x, y);
+    let (directions, sliding): (&[(i8, i8)], bool) = match piece.kind() {
+        Some(PieceKind::Pawn) => {
+            let dy = pawn_direction(piece.owner().unwrap());
+            for dx in &[-1, 1] {
+                if let Some((x, y)) = offset(x, y, (*dx, dy)) {
+                    f(x, y);
+                }
+            }
+            return;
+        }
+        Some(PieceKind::Knight) => (&KNIGHT_MOVES, false),
+        Some(PieceKind::Bishop) => (&DIAGONALS, true),
+        Some(PieceKind::Rook) => (&STRAIGHTS, true),
+        Some(PieceKind::Queen) => (&QUEEN_MOVES, true),
+        Some(PieceKind::King) => (&QUEEN_MOVES, false),
+        None => return,
+    };
+    for direction in directions {
+        let (mut x, mut y) = (x, y);
+        while let Some(next) = offset(x, y, *direction) {
+            x = next.0;
+            y = next.1;
+            f(x, y);
+            if !sliding || state.get_piece(x, y) != Piece::None {
+                break;
+            }
+        }
+    }
+}
+
+impl BoardState {
+    /// Whether any piece of `by` attacks the given square.
+    pub fn is_square_attacked(&self, square: (u8, u8), by: CurrentPlayer) -> bool {
+        for y in 0..8 {
+            for x in 0..8 {
+                if self.get_piece(x, y).owner() != Some(by) {
+                    continue;
+                }
+                let mut attacked = false;
+                for_each_attack(self, x, y, |x, y| attacked |= (x, y) == square);
+                if attacked {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn find_king(&self, player: CurrentPlayer) -> Option<(u8, u8)> {
+        for y in 0..8 {
+            for x in 0..8 {
+                let piece = self.get_piece(x, y);
+                if piece.kind() == Some(PieceKind::King) && piece.owner() == Some(player) {
+                    return Some((x, y));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn is_in_check(&self, player: CurrentPlayer) -> bool {
+        match self.find_king(player) {
+            Some(king) => self.is_square_attacked(king, player.opponent()),
+            None => false,
+        }
+    }
+
+    /// Whether the move takes a piece, including en passant.
+    pub fn is_capture(&self, m: Move) -> bool {
+        if self.get_piece(m.to.0, m.to.1) != Piece::None {
+            return true;
+        }
+        self.get_piece(m.from.0, m.from.1).kind() == Some(PieceKind::Pawn) && m.from.0 != m.to.0
+    }
+
+    /// All moves of the current player that do not leave their own king in check.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let player = self.current_player;
+        let mut moves = self.pseudo_legal_moves();
+        moves.retain(|m| {
+            let mut next = self.clone();
+            next.apply_move(*m).is_ok() && !next.is_in_check(player)
+        });
+        moves
+    }
+
+    fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let player = self.current_player;
+        let mut moves = Vec::with_capacity(64);
+        for y in 0..8 {
+            for x in 0..8 {
+                let piece = self.get_piece(x, y);
+                if piece.owner() != Some(player) {
+                    continue;
+                }
+                if piece.kind() == Some(PieceKind::Pawn) {
+                    self.pawn_moves(x, y, &mut moves);
+                    continue;
+                }
+                for_each_attack(self, x, y, |to_x, to_y| {
+                    if self.get_piece(to_x, to_y).owner() != Some(player) {
+                        moves.push(Move {
+                            from: (x, y),
+                            to: (to_x, to_y),
+                            promotion: None,
+                        });
+                    }
+                });
+            }
+        }
+        self.castling_moves(&mut moves);
+        moves
+    }
+
+    fn pawn_moves(&self, x: u8, y: u8, moves: &mut Vec<Move>) {
+        let player = self.current_player;
+        let dy = pawn_direction(player);
+        let last_rank = match player {
+            CurrentPlayer::White => 7,
+            CurrentPlayer::Black => 0,
+        };
+        let mut push = |to: (u8, u8)| {
+            if to.1 == last_rank {
+                for promotion in &PROMOTIONS {
+                    moves.push(Move {
+                        from: (x, y),
+                        to,
+                        promotion: Some(*promotion),
+                    });
+                }
+            } else {
+                moves.push(Move {
+                    from: (x, y),
+                    to,
+                    promotion: None,
+                });
+            }
+        };
+
+        if let Some(to) = offset(x, y, (0, dy)) {
+            if self.get_piece(to.0, to.1) == Piece::None {
+                push(to);
+                let start_rank = match player {
+                    CurrentPlayer::White => 1,
+                    CurrentPlayer::Black => 6,
+                };
+                if y == start_rank {
+                    let to = (x, to.1.wrapping_add(dy as u8));
+                    if self.get_piece(to.0, to.1) == Piece::None {
+                        push(to);
+                    }
+                }
+            }
+        }
+        for dx in &[-1, 1] {
+            if let Some(to) = offset(x, y, (*dx, dy)) {
+                let target = self.get_piece(to.0, to.1);
+                if target.owner() == Some(player.opponent()) || self.en_passant == Some(to) {
+                    push(to);
+                }
+            }
+        }
+    }
+
+    fn castling_moves(&self, moves: &mut Vec<Move>) {
+        let player = self.current_player;
+        let y = match player {
+            CurrentPlayer::White => 0,
+            CurrentPlayer::Black => 7,
+        };
+        if self.get_piece(3, y) != Piece::new(PieceKind::King, player)
+            || self.is_square_attacked((3, y), player.opponent())
+        {
+            return;
+        }
+        let rook = Piece::new(PieceKind::Rook, player);
+        // (rook x, squares that have to be empty, squares the king passes, king target x)
+        let sides: [(u8, &[u8], &[u8], u8); 2] =
+            [(0, &[1, 2], &[1, 2], 1), (7, &[4, 5, 6], &[4, 5], 5)];
+        for (rook_x, empty, passes, to_x) in &sides {
+            if self.get_piece(*rook_x, y) != rook {
+                continue;
+            }
+            if empty.iter().any(|x| self.get_piece(*x, y) != Piece::None) {
+                continue;
+            }
+            if passes
+                .iter()
+                .any(|x| self.is_square_attacked((*x, y), player.opponent()))
+            {
+                continue;
+            }
+            moves.push(Move {
+                from: (3, y),
+                to: (*to_x, y),
+                promotion: None,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+fn perft(state: &BoardState, depth: u32) -> usize {
+    if depth == 1 {
+        return state.legal_moves().len();
+    }
+    state
+        .legal_moves()
+        .into_iter()
+        .map(|m| {
+            let mut next = state.clone();
+            next.apply_move(m).unwrap();
+            perft(&next, depth - 1)
+        })
+        .sum()
+}
+
+#[test]
+fn test_perft_initial_position() {
+    let state = BoardState::init();
+    assert_eq!(20, perft(&state, 1));
+    assert_eq!(400, perft(&state, 2));
+    assert_eq!(8902, perft(&state, 3));
+}
+
+#[test]
+fn test_special_moves() {
+    let mut state = BoardState::init();
+    for m in &["e4", "a6", "e5", "d5", "Nf3", "h6", "Bc4", "g6"] {
+        state.make_move(m).unwrap();
+    }
+    let moves = state.legal_moves();
+    let castle = Move {
+        from: (3, 0),
+        to: (1, 0),
+        promotion: None,
+    };
+    assert!(moves.contains(&castle));
+    // black just moved g6, so exd6 en passant is gone
+    let en_passant = Move {
+        from: (3, 4),
+        to: (4, 5),
+        promotion: None,
+    };
+    assert!(!moves.contains(&en_passant));
+
+    let mut state = BoardState::init();
+    for m in &["e4", "a6", "e5", "d5"] {
+        state.make_move(m).unwrap();
+    }
+    assert!(state.legal_moves().contains(&en_passant));
+    assert!(state.is_capture(en_passant));
+    state.apply_move(en_passant).unwrap();
+    assert_eq!(Piece::None, state.get_piece(4, 4));
+
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKingMoved);
+    state.set_piece((3, 7), Piece::BlackKingMoved);
+    state.set_piece((0, 6), Piece::WhitePawnMoved);
+    assert_eq!(5 + 4, state.legal_moves().len());
+}
+
+#[test]
+fn test_check() {
+    let mut state = BoardState::init();
+    for m in &["f3", "e5", "g4", "Qh4"] {
+        state.make_move(m).unwrap();
+    }
+    assert!(state.is_in_check(CurrentPlayer::White));
+    assert!(state.legal_moves().is_empty());
+}
@@ -0,0 +1,584 @@
+//! An efficiently updatable neural network (NNUE) evaluation.
+//!
+//! The input features are HalfKP: for both sides, every piece that is not a king is a feature
+//! together with the square of that side's own king, seen from that side (squares are flipped
+//! vertically for black). That is 64 king squares * 10 pieces * 64 squares = 40960 features, of
+//! which only a few are active in a position.
+//!
+//! The first layer (the feature transformer) sums the rows of the active features into an
+//! accumulator per side. A move only changes a few features, so the accumulator is updated with
+//! those instead of computed again, except when the king of that side moves. The accumulators of
+//! the side to move and the other side are clipped to 0..=127 and fed through small dense layers
+//! with `i8` weights, with a clipped ReLU (after shifting right by 6) between them.
+//!
+//! # Weights file
+//!
+//! All numbers are little endian.
+//!
+//! - Magic `b"CNUE"`, version (`u32`, currently 1).
+//! - The accumulator size `L1` (`u32`) and the number of dense layers (`u32`).
+//! - For every dense layer its number of inputs and outputs (`u32`). The first layer has `2 * L1`
+//!   inputs, every layer has as many inputs as the one before it has outputs, and the last layer
+//!   has a single output.
+//! - The feature transformer: `L1` biases (`i16`), then `40960 * L1` weights (`i16`), row `f` holds
+//!   the weights of feature `f`.
+//! - For every dense layer: `outputs` biases (`i32`), then `outputs * inputs` weights (`i8`), row
+//!   `o` holds the weights of output `o`.
+//!
+//! The output of the last layer divided by `OUTPUT_SCALE` is the evaluation in centipawns, from
+//! the view of the player to move.
+
+use crate::{BoardState, CurrentPlayer, Piece, PieceKind, Result};
+use random::Source;
+use std::fs;
+
+const MAGIC: &[u8; 4] = b"CNUE";
+const VERSION: u32 = 1;
+
+/// The number of HalfKP features.
+pub const FEATURES: usize = 64 * 10 * 64;
+
+/// The output of the network divided by this is in centipawns.
+pub const OUTPUT_SCALE: i32 = 16;
+
+/// The clipped ReLU limits activations to 0..=127, so they fit in an `i8` multiplication.
+const ACTIVATION_MAX: i32 = 127;
+/// The dense layers have their weights scaled by `1 << WEIGHT_SHIFT`.
+const WEIGHT_SHIFT: i32 = 6;
+
+#[inline]
+fn square(x: u8, y: u8) -> usize {
+    usize::from(y) * 8 + usize::from(7 - x)
+}
+
+/// The index of a feature from the view of `perspective`, with its king on `king`.
+fn feature(perspective: CurrentPlayer, king: usize, piece: Piece, square: usize) -> Option<usize> {
+    let kind = match piece.kind()? {
+        PieceKind::Pawn => 0,
+        PieceKind::Knight => 1,
+        PieceKind::Bishop => 2,
+        PieceKind::Rook => 3,
+        PieceKind::Queen => 4,
+        PieceKind::King => return None,
+    };
+    let (king, square) = match perspective {
+        CurrentPlayer::White => (king, square),
+        CurrentPlayer::Black => (king ^ 56, square ^ 56),
+    };
+    let theirs = (piece.owner()? != perspective) as usize;
+    Some(king * 640 + (kind * 2 + theirs) * 64 + square)
+}
+
+fn king_square(state: &BoardState, player: CurrentPlayer) -> Option<usize> {
+    state.find_king(player).map(|(x, y)| square(x, y))
+}
+
+fn perspective_index(player: CurrentPlayer) -> usize {
+    match player {
+        CurrentPlayer::White => 0,
+        CurrentPlayer::Black => 1,
+    }
+}
+
+const PERSPECTIVES: [CurrentPlayer; 2] = [CurrentPlayer::White, CurrentPlayer::Black];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenseLayer {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub biases: Vec<i32>,
+    /// `outputs * inputs` weights, row `o` holds the weights of output `o`.
+    pub weights: Vec<i8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nnue {
+    l1: usize,
+    transformer_biases: Vec<i16>,
+    /// `FEATURES * l1` weights, row `f` holds the weights of feature `f`.
+    transformer_weights: Vec<i16>,
+    layers: Vec<DenseLayer>,
+}
+
+/// The first layer of the network for both sides, white first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accumulator {
+    values: [Vec<i16>; 2],
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.offset + len > self.bytes.len() {
+            bail!("Unexpected end of file at byte {}", self.offset);
+        }
+        let result = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(result)
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
+
+    fn i16s(&mut self, count: usize) -> Result<Vec<i16>> {
+        Ok(self
+            .take(count * 2)?
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect())
+    }
+
+    fn i32s(&mut self, count: usize) -> Result<Vec<i32>> {
+        Ok(self
+            .take(count * 4)?
+            .chunks(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    fn i8s(&mut self, count: usize) -> Result<Vec<i8>> {
+        Ok(self.take(count)?.iter().map(|b| *b as i8).collect())
+    }
+}
+
+impl Nnue {
+    /// A network with random weights and the given layer sizes after the accumulator, which is
+    /// only useful for tests and as a start for training.
+    pub fn random(l1: usize, layer_sizes: &[usize], source: &mut impl Source) -> Nnue {
+        let mut i16s = |count: usize, range: u64| -> Vec<i16> {
+            (0..count)
+                .map(|_| (source.read_u64() % (2 * range + 1)) as i16 - range as i16)
+                .collect()
+        };
+        let transformer_biases = i16s(l1, 32);
+        let transformer_weights = i16s(FEATURES * l1, 32);
+        let mut inputs = 2 * l1;
+        let mut layers = Vec::new();
+        for outputs in layer_sizes.iter().cloned().chain(Some(1)) {
+            layers.push(DenseLayer {
+                inputs,
+                outputs,
+                biases: (0..outputs)
+                    .map(|_| (source.read_u64() % 512) as i32 - 256)
+                    .collect(),
+                weights: (0..inputs * outputs)
+                    .map(|_| (source.read_u64() % 65) as i8 - 32)
+                    .collect(),
+            });
+            inputs = outputs;
+        }
+        Nnue {
+            l1,
+            transformer_biases,
+            transformer_weights,
+            layers,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Nnue> {
+        let bytes = fs::read(path)?;
+        Nnue::from_bytes(&bytes).map_err(|e| format_err!("Could not read {:?}: {}", path, e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Nnue> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            bail!("Not an NNUE file");
+        }
+        let version = reader.u32()?;
+        if version != VERSION as usize {
+            bail!("Unsupported version {}", version);
+        }
+        let l1 = reader.u32()?;
+        let count = reader.u32()?;
+        let mut sizes = Vec::with_capacity(count);
+        let mut expected = 2 * l1;
+        for i in 0..count {
+            let (inputs, outputs) = (reader.u32()?, reader.u32()?);
+            if inputs != expected {
+                bail!("Layer {} has {} inputs, expected {}", i, inputs, expected);
+            }
+            sizes.push((inputs, outputs));
+            expected = outputs;
+        }
+        if expected != 1 || count == 0 {
+            bail!("The last layer should have a single output");
+        }
+
+        let transformer_biases = reader.i16s(l1)?;
+        let transformer_weights = reader.i16s(FEATURES * l1)?;
+        let mut layers = Vec::with_capacity(count);
+        for (inputs, outputs) in sizes {
+            layers.push(DenseLayer {
+                inputs,
+                outputs,
+                biases: reader.i32s(outputs)?,
+                weights: reader.i8s(inputs * outputs)?,
+            });
+        }
+        if reader.offset != bytes.len() {
+            bail!(
+                "{} bytes left at the end of the file",
+                bytes.len() - reader.offset
+            );
+        }
+        Ok(Nnue {
+            l1,
+            transformer_biases,
+            transformer_weights,
+            layers,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.l1 as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.layers.len() as u32).to_le_bytes());
+        for layer in &self.layers {
+            bytes.extend_from_slice(&(layer.inputs as u32).to_le_bytes());
+            bytes.extend_from_slice(&(layer.outputs as u32).to_le_bytes());
+        }
+        for value in self
+            .transformer_biases
+            .iter()
+            .chain(&self.transformer_weights)
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for layer in &self.layers {
+            for bias in &layer.biases {
+                bytes.extend_from_slice(&bias.to_le_bytes());
+            }
+            bytes.extend(layer.weights.iter().map(|w| *w as u8));
+        }
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    fn row(&self, feature: usize) -> &[i16] {
+        &self.transformer_weights[feature * self.l1..(feature + 1) * self.l1]
+    }
+
+    fn refresh_side(&self, state: &BoardState, perspective: CurrentPlayer) -> Vec<i16> {
+        let mut values = self.transformer_biases.clone();
+        let king = match king_square(state, perspective) {
+            Some(king) => king,
+            None => return values,
+        };
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Some(f) = feature(perspective, king, state.get_piece(x, y), square(x, y)) {
+                    simd::add(&mut values, self.row(f));
+                }
+            }
+        }
+        values
+    }
+
+    /// Compute the accumulator of a position from scratch.
+    pub fn refresh(&self, state: &BoardState) -> Accumulator {
+        Accumulator {
+            values: [
+                self.refresh_side(state, CurrentPlayer::White),
+                self.refresh_side(state, CurrentPlayer::Black),
+            ],
+        }
+    }
+
+    /// The accumulator of `after`, from the accumulator of `before`. Only the squares that
+    /// changed are looked at, so this works for any move, including castling and en passant.
+    pub fn update(
+        &self,
+        accumulator: &Accumulator,
+        before: &BoardState,
+        after: &BoardState,
+    ) -> Accumulator {
+        let mut result = accumulator.clone();
+        for perspective in &PERSPECTIVES {
+            let king = king_square(after, *perspective);
+            let values = &mut result.values[perspective_index(*perspective)];
+            // Every feature depends on the king, so when it moves everything changes.
+            if king != king_square(before, *perspective) {
+                *values = self.refresh_side(after, *perspective);
+                continue;
+            }
+            let king = match king {
+                Some(king) => king,
+                None => continue,
+            };
+            for y in 0..8 {
+                for x in 0..8 {
+                    let (old, new) = (before.get_piece(x, y), after.get_piece(x, y));
+                    if old.kind() == new.kind() && old.owner() == new.owner() {
+                        continue;
+                    }
+                    if let Some(f) = feature(*perspective, king, old, square(x, y)) {
+                        simd::sub(values, self.row(f));
+                    }
+                    if let Some(f) = feature(*perspective, king, new, square(x, y)) {
+                        simd::add(values, self.row(f));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// The evaluation in centipawns from the view of the player to move.
+    pub fn evaluate(&self, accumulator: &Accumulator, player: CurrentPlayer) -> i32 {
+        let us = &accumulator.values[perspective_index(player)];
+        let them = &accumulator.values[perspective_index(player.opponent())];
+        let mut input: Vec<u8> = us
+            .iter()
+            .chain(them)
+            .map(|v| i32::from(*v).clamp(0, ACTIVATION_MAX) as u8)
+            .collect();
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let outputs = (0..layer.outputs).map(|o| {
+                let row = &layer.weights[o * layer.inputs..(o + 1) * layer.inputs];
+                layer.biases[o] + simd::dot(&input, row)
+            });
+            if i + 1 == self.layers.len() {
+                return outputs.sum::<i32>() / OUTPUT_SCALE;
+            }
+            input = outputs
+                .map(|v| (v >> WEIGHT_SHIFT).clamp(0, ACTIVATION_MAX) as u8)
+                .collect();
+        }
+        0
+    }
+
+    /// Evaluate a position without an accumulator from before.
+    pub fn evaluate_state(&self, state: &BoardState) -> i32 {
+        self.evaluate(&self.refresh(state), state.current_player)
+    }
+}
+
+/// The accumulators along the line the search is in. Making a move pushes the updated
+/// accumulator, unmaking it pops it again.
+#[derive(Debug)]
+pub struct AccumulatorStack<'a> {
+    network: &'a Nnue,
+    stack: Vec<Accumulator>,
+}
+
+impl<'a> AccumulatorStack<'a> {
+    pub fn new(network: &'a Nnue, root: &BoardState) -> AccumulatorStack<'a> {
+        AccumulatorStack {
+            network,
+            stack: vec![network.refresh(root)],
+        }
+    }
+
+    pub fn make(&mut self, before: &BoardState, after: &BoardState) {
+        let next = self.network.update(self.current(), before, after);
+        self.stack.push(next);
+    }
+
+    pub fn unmake(&mut self) {
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    pub fn current(&self) -> &Accumulator {
+        self.stack.last().unwrap()
+    }
+
+    pub fn evaluate(&self, player: CurrentPlayer) -> i32 {
+        self.network.evaluate(self.current(), player)
+    }
+}
+
+/// The inner loops of the network, with AVX2 when the CPU has it.
+mod simd {
+    pub fn add(values: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return unsafe { avx2::add(values, row) };
+            }
+        }
+        scalar::add(values, row)
+    }
+
+    pub fn sub(values: &mut [i16], row: &[i16]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return unsafe { avx2::sub(values, row) };
+            }
+        }
+        scalar::sub(values, row)
+    }
+
+    /// The dot product of activations (at most 127) and weights.
+    pub fn dot(input: &[u8], weights: &[i8]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return unsafe { avx2::dot(input, weights) };
+            }
+        }
+        scalar::dot(input, weights)
+    }
+
+    pub mod scalar {
+        pub fn add(values: &mut [i16], row: &[i16]) {
+            for (v, r) in values.iter_mut().zip(row) {
+                *v = v.wrapping_add(*r);
+            }
+        }
+
+        pub fn sub(values: &mut [i16], row: &[i16]) {
+            for (v, r) in values.iter_mut().zip(row) {
+                *v = v.wrapping_sub(*r);
+            }
+        }
+
+        pub fn dot(input: &[u8], weights: &[i8]) -> i32 {
+            input
+                .iter()
+                .zip(weights)
+                .map(|(i, w)| i32::from(*i) * i32::from(*w))
+                .sum()
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    mod avx2 {
+        use std::arch::x86_64::*;
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn add(values: &mut [i16], row: &[i16]) {
+            debug_assert!(row.len() >= values.len());
+            let chunks = values.len() / 16;
+            for i in 0..chunks {
+                let v = values.as_mut_ptr().add(i * 16) as *mut __m256i;
+                let r = row.as_ptr().add(i * 16) as *const __m256i;
+                _mm256_storeu_si256(
+                    v,
+                    _mm256_add_epi16(_mm256_loadu_si256(v), _mm256_loadu_si256(r)),
+                );
+            }
+            super::scalar::add(&mut values[chunks * 16..], &row[chunks * 16..]);
+        }
+
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn sub(values: &mut [i16], row: &[i16]) {
+            debug_assert!(row.len() >= values.len());
+            let chunks = values.len() / 16;
+            for i in 0..chunks {
+                let v = values.as_mut_ptr().add(i * 16) as *mut __m256i;
+                let r = row.as_ptr().add(i * 16) as *const __m256i;
+                _mm256_storeu_si256(
+                    v,
+                    _mm256_sub_epi16(_mm256_loadu_si256(v), _mm256_loadu_si256(r)),
+                );
+            }
+            super::scalar::sub(&mut values[chunks * 16..], &row[chunks * 16..]);
+        }
+
+        /// `maddubs` multiplies the unsigned activations with the signed weights and adds pairs to
+        /// `i16`. With activations of at most 127 that can't saturate.
+        #[target_feature(enable = "avx2")]
+        pub unsafe fn dot(input: &[u8], weights: &[i8]) -> i32 {
+            let len = input.len().min(weights.len());
+            let chunks = len / 32;
+            let ones = _mm256_set1_epi16(1);
+            let mut sum = _mm256_setzero_si256();
+            for i in 0..chunks {
+                let a = _mm256_loadu_si256(input.as_ptr().add(i * 32) as *const __m256i);
+                let b = _mm256_loadu_si256(weights.as_ptr().add(i * 32) as *const __m256i);
+                let products = _mm256_madd_epi16(_mm256_maddubs_epi16(a, b), ones);
+                sum = _mm256_add_epi32(sum, products);
+            }
+            let mut lanes = [0i32; 8];
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+            lanes.iter().sum::<i32>()
+                + super::scalar::dot(&input[chunks * 32..len], &weights[chunks * 32..len])
+        }
+    }
+}
+
+#[test]
+fn test_incremental_update_matches_refresh() {
+    let mut source = random::default().seed([7, 8]);
+    let network = Nnue::random(24, &[8], &mut source);
+
+    let mut state = BoardState::init();
+    let mut stack = AccumulatorStack::new(&network, &state);
+    // Castling, en passant, a king move and captures.
+    for m in &[
+        "e4", "d5", "e5", "f5", "exf6", "Nxf6", "Nf3", "e6", "Be2", "Bd6", "O-O", "O-O", "Kh1",
+        "Bxh2",
+    ] {
+        let before = state.clone();
+        state.make_move(m).unwrap();
+        stack.make(&before, &state);
+        assert_eq!(&network.refresh(&state), stack.current(), "after {}", m);
+    }
+    assert_eq!(
+        network.evaluate_state(&state),
+        stack.evaluate(state.current_player)
+    );
+    stack.unmake();
+    assert_ne!(&network.refresh(&state), stack.current());
+}
+
+#[test]
+fn test_simd_matches_scalar() {
+    let mut source = random::default().seed([9, 10]);
+    let input: Vec<u8> = (0..100).map(|_| (source.read_u64() % 128) as u8).collect();
+    let weights: Vec<i8> = (0..100).map(|_| source.read_u64() as i8).collect();
+    assert_eq!(
+        simd::scalar::dot(&input, &weights),
+        simd::dot(&input, &weights)
+    );
+
+    let row: Vec<i16> = (0..40).map(|_| source.read_u64() as i16).collect();
+    let mut values = vec![1000i16; 40];
+    let mut expected = values.clone();
+    simd::add(&mut values, &row);
+    simd::scalar::add(&mut expected, &row);
+    assert_eq!(expected, values);
+    simd::sub(&mut values, &row);
+    assert_eq!(vec![1000i16; 40], values);
+}
+
+#[test]
+fn test_nnue_file_roundtrip() {
+    let mut source = random::default().seed([11, 12]);
+    let network = Nnue::random(8, &[4, 4], &mut source);
+    let bytes = network.to_bytes();
+    assert_eq!(network, Nnue::from_bytes(&bytes).unwrap());
+    assert!(Nnue::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn test_evaluation_is_symmetric_under_flip() {
+    let mut source = random::default().seed([13, 14]);
+    let network = Nnue::random(16, &[8], &mut source);
+    let mut state = BoardState::init();
+    for m in &["d4", "Nf6", "c4", "e6", "Nc3", "Bb4", "Qc2", "O-O"] {
+        state.make_move(m).unwrap();
+        // Both perspectives see the same position from their side.
+        assert_eq!(
+            network.evaluate_state(&state),
+            network.evaluate_state(&state.flipped())
+        );
+    }
+}
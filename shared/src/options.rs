@@ -0,0 +1,104 @@
+//! Engine options, set by name in the same way as UCI's `setoption name <name> value <value>`.
+
+use crate::evaluation::{StaticEval, Weights};
+use crate::polyglot::Book;
+use crate::syzygy::Tablebases;
+use crate::{BoardState, Move, Result};
+use random::Source;
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Play moves from the opening book while the position is in it.
+    pub own_book: bool,
+    /// Pick the book move with the highest weight, instead of a random move by weight.
+    pub best_book_move: bool,
+    book: Option<Book>,
+    tablebases: Option<Tablebases>,
+    /// The weights of the static evaluation, from `EvalFile`.
+    weights: Option<Weights>,
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!("Option {:?} expects true or false, got {:?}", name, value),
+    }
+}
+
+impl Options {
+    /// Option names are case insensitive.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        match name.to_ascii_lowercase().as_str() {
+            "ownbook" => self.own_book = parse_bool(name, value)?,
+            "bestbookmove" => self.best_book_move = parse_bool(name, value)?,
+            "bookfile" => self.book = Some(Book::open(value)?),
+            "evalfile" => {
+                self.weights = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Weights::load(path)?),
+                }
+            }
+            "syzygypath" => {
+                // UCI uses `<empty>` to turn the tablebases off.
+                self.tablebases = match value {
+                    "" | "<empty>" => None,
+                    path => Some(Tablebases::open(path)?),
+                }
+            }
+            _ => bail!("Unknown option {:?}", name),
+        }
+        Ok(())
+    }
+
+    /// The static evaluation with the weights of `EvalFile`, or the default weights.
+    pub fn static_eval(&self) -> StaticEval {
+        StaticEval::new(self.weights.clone().unwrap_or_default())
+    }
+
+    pub fn book(&self) -> Option<&Book> {
+        self.book.as_ref()
+    }
+
+    /// The move to play from the opening book, if `OwnBook` is enabled and the position is in it.
+    pub fn book_move(&self, state: &BoardState, source: &mut impl Source) -> Option<Move> {
+        let book = match (self.own_book, self.book.as_ref()) {
+            (true, Some(book)) => book,
+            _ => return None,
+        };
+        if self.best_book_move {
+            book.best_move(state)
+        } else {
+            book.weighted_move(state, source)
+        }
+    }
+
+    pub fn tablebases(&mut self) -> Option<&mut Tablebases> {
+        self.tablebases.as_mut()
+    }
+
+    /// The moves that keep the tablebase result of the position, if `SyzygyPath` is set and the
+    /// position is in the tables. The search at the root only needs to look at these.
+    pub fn tablebase_moves(&mut self, state: &BoardState) -> Option<Vec<Move>> {
+        let tablebases = self.tablebases.as_mut()?;
+        if !tablebases.can_probe(state) {
+            return None;
+        }
+        tablebases.root_moves(state).ok()
+    }
+}
+
+#[test]
+fn test_eval_file() {
+    use crate::evaluation::Term;
+    let path = std::env::temp_dir().join(format!("weights-{}.txt", std::process::id()));
+    std::fs::write(&path, "QueenValue 950\n").unwrap();
+    let mut options = Options::default();
+    assert_eq!(900, options.static_eval().weights.get(Term::QueenValue));
+    options.set("EvalFile", path.to_str().unwrap()).unwrap();
+    assert_eq!(950, options.static_eval().weights.get(Term::QueenValue));
+    options.set("evalfile", "<empty>").unwrap();
+    assert_eq!(900, options.static_eval().weights.get(Term::QueenValue));
+    assert!(options.set("EvalFile", "missing.txt").is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,260 @@
+//! A fixed numbering of moves, for the policy output of neural networks.
+//!
+//! Moves are numbered like in AlphaZero, with 73 planes of 64 squares. The index of a move is
+//! `plane * 64 + from`, with the `from` square numbered `rank * 8 + file` (a1 = 0). Moves are seen
+//! from the player to move, so for black the ranks are mirrored, like in `features::Encoder`.
+//!
+//! | plane | moves                                                                          |
+//! |-------|--------------------------------------------------------------------------------|
+//! | 0-55  | `direction * 7 + distance - 1`, for the directions N, NE, E, SE, S, SW, W, NW  |
+//! | 56-63 | knight moves                                                                   |
+//! | 64-72 | `64 + direction * 3 + piece`, underpromotions to a knight, bishop or rook when |
+//! |       | capturing to the left, moving straight ahead or capturing to the right         |
+//!
+//! Promotions to a queen use the planes of the other moves, so a pawn moving to the last rank on
+//! those planes is always a queen promotion. Not every index is a possible move.
+
+use crate::{BoardState, CurrentPlayer, Move, PieceKind};
+
+/// The number of move indices.
+pub const POLICY_SIZE: usize = 73 * 64;
+
+/// The probability of every legal move of a position.
+pub type Policy = Vec<(Move, f32)>;
+
+/// Directions as `(file, rank)` steps, from the view of the player to move.
+const DIRECTIONS: [(i8, i8); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+const KNIGHT_MOVES: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const UNDERPROMOTIONS: [PieceKind; 3] = [PieceKind::Knight, PieceKind::Bishop, PieceKind::Rook];
+
+/// The `(file, rank)` of a square from the view of `player`.
+fn view((x, y): (u8, u8), player: CurrentPlayer) -> (i8, i8) {
+    let rank = match player {
+        CurrentPlayer::White => y,
+        CurrentPlayer::Black => 7 - y,
+    };
+    (7 - x as i8, rank as i8)
+}
+
+/// The inverse of `view`.
+fn square((file, rank): (i8, i8), player: CurrentPlayer) -> Option<(u8, u8)> {
+    if !(0..8).contains(&file) || !(0..8).contains(&rank) {
+        return None;
+    }
+    let y = match player {
+        CurrentPlayer::White => rank,
+        CurrentPlayer::Black => 7 - rank,
+    };
+    Some((7 - file as u8, y as u8))
+}
+
+/// The index of a move by `player`, or `None` if no piece can move like that.
+pub fn move_index(player: CurrentPlayer, m: Move) -> Option<usize> {
+    let from = view(m.from, player);
+    let to = view(m.to, player);
+    let delta = (to.0 - from.0, to.1 - from.1);
+    let plane = match m.promotion {
+        Some(PieceKind::Queen) | None => {
+            if let Some(knight) = KNIGHT_MOVES.iter().position(|d| *d == delta) {
+                56 + knight
+            } else {
+                if delta.0 != 0 && delta.1 != 0 && delta.0.abs() != delta.1.abs() {
+                    return None;
+                }
+                let distance = delta.0.abs().max(delta.1.abs());
+                let step = (delta.0.signum(), delta.1.signum());
+                let direction = DIRECTIONS.iter().position(|d| *d == step)?;
+                direction * 7 + distance as usize - 1
+            }
+        }
+        Some(kind) => {
+            if delta.1 != 1 || delta.0.abs() > 1 {
+                return None;
+            }
+            let piece = UNDERPROMOTIONS.iter().position(|k| *k == kind)?;
+            64 + (delta.0 + 1) as usize * 3 + piece
+        }
+    };
+    Some(plane * 64 + from.1 as usize * 8 + from.0 as usize)
+}
+
+/// The move with the given index in the position, or `None` if it leaves the board. The move is
+/// not checked for legality.
+pub fn index_move(state: &BoardState, index: usize) -> Option<Move> {
+    if index >= POLICY_SIZE {
+        return None;
+    }
+    let player = state.current_player;
+    let (plane, from) = (index / 64, index % 64);
+    let from = ((from % 8) as i8, (from / 8) as i8);
+    let (delta, underpromotion) = match plane {
+        0..=55 => {
+            let (dx, dy) = DIRECTIONS[plane / 7];
+            let distance = (plane % 7) as i8 + 1;
+            ((dx * distance, dy * distance), None)
+        }
+        56..=63 => (KNIGHT_MOVES[plane - 56], None),
+        _ => {
+            let plane = plane - 64;
+            ((plane as i8 / 3 - 1, 1), Some(UNDERPROMOTIONS[plane % 3]))
+        }
+    };
+    let to = (from.0 + delta.0, from.1 + delta.1);
+    let from = square(from, player)?;
+    let pawn = state.get_piece(from.0, from.1).kind() == Some(PieceKind::Pawn);
+    let promotion = match underpromotion {
+        Some(kind) => Some(kind),
+        None if pawn && to.1 == 7 => Some(PieceKind::Queen),
+        None => None,
+    };
+    Some(Move {
+        from,
+        to: square(to, player)?,
+        promotion,
+    })
+}
+
+/// The index of the same move in `BoardState::mirrored`, with the files mirrored. The index of a
+/// move in `BoardState::flipped` is the same as in the original position, as moves are numbered
+/// from the view of the player to move.
+pub fn mirror_index(index: usize) -> usize {
+    let (plane, from) = (index / 64, index % 64);
+    let mirror = |steps: &[(i8, i8)], i: usize| {
+        let (dx, dy) = steps[i];
+        steps.iter().position(|s| *s == (-dx, dy)).unwrap()
+    };
+    let plane = match plane {
+        0..=55 => mirror(&DIRECTIONS, plane / 7) * 7 + plane % 7,
+        56..=63 => 56 + mirror(&KNIGHT_MOVES, plane - 56),
+        _ => 64 + (2 - (plane - 64) / 3) * 3 + (plane - 64) % 3,
+    };
+    plane * 64 + from / 8 * 8 + 7 - from % 8
+}
+
+/// The legal moves of the position with their indices.
+pub fn legal_move_indices(state: &BoardState) -> Vec<(Move, usize)> {
+    let player = state.current_player;
+    state
+        .legal_moves()
+        .into_iter()
+        .filter_map(|m| move_index(player, m).map(|index| (m, index)))
+        .collect()
+}
+
+/// The probability of every legal move, from the `POLICY_SIZE` logits of a network. Illegal moves
+/// are left out before the softmax, so the probabilities of the legal moves add up to 1.
+pub fn masked_softmax(state: &BoardState, logits: &[f32]) -> Policy {
+    let moves = legal_move_indices(state);
+    let max = moves
+        .iter()
+        .map(|(_, index)| logits[*index])
+        .fold(f32::NEG_INFINITY, f32::max);
+    let mut result: Policy = moves
+        .into_iter()
+        .map(|(m, index)| (m, (logits[index] - max).exp()))
+        .collect();
+    let total: f32 = result.iter().map(|(_, p)| p).sum();
+    result.iter_mut().for_each(|(_, p)| *p /= total);
+    result
+}
+
+#[test]
+fn test_move_indices() {
+    use crate::Piece;
+    let mut state = BoardState::init();
+    let e2e4 = Move {
+        from: (3, 1),
+        to: (3, 3),
+        promotion: None,
+    };
+    // North, distance 2, from e2.
+    assert_eq!(Some(64 + 12), move_index(CurrentPlayer::White, e2e4));
+    state.make_move("e4").unwrap();
+    // e7e5 is the same move from black's view.
+    let e7e5 = Move {
+        from: (3, 6),
+        to: (3, 4),
+        promotion: None,
+    };
+    assert_eq!(Some(64 + 12), move_index(CurrentPlayer::Black, e7e5));
+    assert_eq!(Some(e7e5), index_move(&state, 64 + 12));
+
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKing);
+    state.set_piece((3, 7), Piece::BlackKing);
+    state.set_piece((1, 6), Piece::WhitePawn);
+    state.set_piece((0, 7), Piece::BlackRook);
+    let mut indices = Vec::new();
+    for (m, index) in legal_move_indices(&state) {
+        assert_eq!(Some(m), index_move(&state, index), "{}", m);
+        indices.push(index);
+    }
+    // 5 king moves, 4 promotions moving ahead and 4 capturing the rook.
+    assert_eq!(13, indices.len());
+    indices.sort();
+    indices.dedup();
+    assert_eq!(13, indices.len());
+}
+
+#[test]
+fn test_masked_softmax() {
+    let state = BoardState::init();
+    let mut logits = vec![0.0; POLICY_SIZE];
+    // A large logit for an illegal move doesn't take any probability.
+    logits[0] = 100.0;
+    logits[64 + 12] = 2.0;
+    let policy = masked_softmax(&state, &logits);
+    assert_eq!(20, policy.len());
+    let total: f32 = policy.iter().map(|(_, p)| p).sum();
+    assert!((total - 1.0).abs() < 1e-5);
+    let best = policy
+        .iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .unwrap();
+    assert_eq!("e2e4", best.0.to_string());
+}
+
+#[test]
+fn test_indices_under_flip_and_mirror() {
+    let mut state = BoardState::init();
+    for m in &["e4", "d5", "exd5", "Nf6", "Bb5+"] {
+        state.make_move(m).unwrap();
+    }
+    let indices = |state: &BoardState| {
+        let mut indices: Vec<usize> = legal_move_indices(state).iter().map(|m| m.1).collect();
+        indices.sort();
+        indices
+    };
+    // The same moves, seen from the other side of the board.
+    assert_eq!(indices(&state), indices(&state.flipped()));
+
+    // Without castling rights, mirrored moves are the moves of the mirrored position.
+    assert!(state.mirrored().is_none());
+    let mut state = BoardState::init();
+    for m in &["e4", "d5", "exd5", "Qxd5", "Ke2", "Kd7"] {
+        state.make_move(m).unwrap();
+    }
+    let mirrored = state.mirrored().unwrap();
+    let mut expected: Vec<usize> = indices(&state).into_iter().map(mirror_index).collect();
+    expected.sort();
+    assert_eq!(expected, indices(&mirrored));
+    assert_eq!(37, mirror_index(mirror_index(37)));
+}
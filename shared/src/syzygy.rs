@@ -0,0 +1,1507 @@
+//! Probing of Syzygy endgame tablebases.
+//!
+//! A table covers one material combination, e.g. `KRvK`, and comes in two files: `KRvK.rtbw` holds
+//! the win/draw/loss result (WDL) of every position, `KRvK.rtbz` the distance to the next zeroing
+//! move (a capture or pawn move) with best play (DTZ). The tables are compressed, and indexed by the
+//! placement of the pieces with the symmetries of the board taken out.
+//!
+//! The tables do not contain positions where a capture is possible, those are resolved by a small
+//! search over the captures. Positions with castling rights are not in the tables at all.
+//!
+//! This follows the layout of the files as read by the probing code of Stockfish and Fathom.
+
+use crate::{BoardState, CurrentPlayer, Move, Piece, PieceKind, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod generate;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/// The largest tables that exist.
+const MAX_PIECES: usize = 7;
+
+/// The piece letters in the order they are written in a table name.
+const PIECE_ORDER: [(PieceKind, char); 6] = [
+    (PieceKind::King, 'K'),
+    (PieceKind::Queen, 'Q'),
+    (PieceKind::Rook, 'R'),
+    (PieceKind::Bishop, 'B'),
+    (PieceKind::Knight, 'N'),
+    (PieceKind::Pawn, 'P'),
+];
+
+// Flags of a table, stored per file and side.
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+/// The result of a position for the player to move. The cursed and blessed results are a win and
+/// a loss that can't be forced within the 50 move rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_i32(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    /// A score for the search, from the view of the player to move. Wins and losses are scored
+    /// just below a mate, so a found mate is still preferred.
+    pub fn score(self) -> i32 {
+        match self {
+            Wdl::Loss => -TB_WIN_SCORE,
+            Wdl::Win => TB_WIN_SCORE,
+            _ => 0,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_i32(-(self as i32))
+    }
+}
+
+/// The score of a tablebase win, see `Wdl::score`.
+pub const TB_WIN_SCORE: i32 = 20_000;
+
+/// A piece as stored in the tables: 1 to 6 for the white pawn to king, 9 to 14 for black.
+fn piece_code(piece: Piece) -> u8 {
+    let kind = match piece.kind() {
+        Some(PieceKind::Pawn) => 1,
+        Some(PieceKind::Knight) => 2,
+        Some(PieceKind::Bishop) => 3,
+        Some(PieceKind::Rook) => 4,
+        Some(PieceKind::Queen) => 5,
+        Some(PieceKind::King) => 6,
+        None => return 0,
+    };
+    match piece.owner() {
+        Some(CurrentPlayer::Black) => kind + 8,
+        _ => kind,
+    }
+}
+
+fn piece_from_code(code: u8) -> Option<Piece> {
+    let kind = match code & 7 {
+        1 => PieceKind::Pawn,
+        2 => PieceKind::Knight,
+        3 => PieceKind::Bishop,
+        4 => PieceKind::Rook,
+        5 => PieceKind::Queen,
+        6 => PieceKind::King,
+        _ => return None,
+    };
+    let player = if code & 8 == 0 {
+        CurrentPlayer::White
+    } else {
+        CurrentPlayer::Black
+    };
+    let mut piece = Piece::new(kind, player);
+    // Pieces in a tablebase position can't castle anymore.
+    piece.has_moved();
+    Some(piece)
+}
+
+/// Tablebase squares count from a1 = 0 to h8 = 63, `BoardState` counts its files from the h-file.
+#[inline]
+fn square(x: u8, y: u8) -> usize {
+    usize::from(y) * 8 + usize::from(7 - x)
+}
+
+#[inline]
+fn coordinates(square: usize) -> (u8, u8) {
+    (7 - (square % 8) as u8, (square / 8) as u8)
+}
+
+#[inline]
+fn file_of(square: usize) -> usize {
+    square & 7
+}
+
+#[inline]
+fn rank_of(square: usize) -> usize {
+    square >> 3
+}
+
+/// Which side of the a1-h8 diagonal a square is on: negative below, 0 on it, positive above.
+#[inline]
+fn off_diagonal(square: usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+#[inline]
+fn flip_diagonal(square: usize) -> usize {
+    ((square >> 3) | (square << 3)) & 63
+}
+
+/// A position as the tables see it: a list of pieces with their square, and the side to move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    /// Piece codes, 1 to 6 for the white pawn to king and 9 to 14 for black, on squares a1 = 0 to
+    /// h8 = 63.
+    pub pieces: Vec<(u8, usize)>,
+    pub white_to_move: bool,
+}
+
+impl Position {
+    pub fn from_state(state: &BoardState) -> Position {
+        let mut pieces = Vec::new();
+        for y in 0..8 {
+            for x in 0..8 {
+                let piece = state.get_piece(x, y);
+                if piece != Piece::None {
+                    pieces.push((piece_code(piece), square(x, y)));
+                }
+            }
+        }
+        pieces.sort_by_key(|(_, square)| *square);
+        Position {
+            pieces,
+            white_to_move: state.current_player == CurrentPlayer::White,
+        }
+    }
+
+    /// The board with these pieces. All kings, rooks and pawns count as moved, as there is no
+    /// castling in the tables and pawns off their start square can't double push anyway.
+    pub fn to_state(&self) -> Result<BoardState> {
+        let mut state = BoardState::empty();
+        for (code, square) in &self.pieces {
+            let piece = match piece_from_code(*code) {
+                Some(piece) if *square < 64 => piece,
+                _ => bail!("Invalid piece {} on square {}", code, square),
+            };
+            state.set_piece(coordinates(*square), piece);
+        }
+        if !self.white_to_move {
+            state.current_player = CurrentPlayer::Black;
+        }
+        Ok(state)
+    }
+}
+
+/// The material of one side as written in a table name, e.g. `KRP`.
+fn side_name(state: &BoardState, player: CurrentPlayer) -> String {
+    let mut name = String::new();
+    for (kind, letter) in &PIECE_ORDER {
+        let piece = Piece::new(*kind, player);
+        for y in 0..8 {
+            for x in 0..8 {
+                let other = state.get_piece(x, y);
+                if other.kind() == piece.kind() && other.owner() == piece.owner() {
+                    name.push(*letter);
+                }
+            }
+        }
+    }
+    name
+}
+
+/// The name of the table with the material of the position, with white first, e.g. `KRPvKR`.
+pub fn material_name(state: &BoardState) -> String {
+    format!(
+        "{}v{}",
+        side_name(state, CurrentPlayer::White),
+        side_name(state, CurrentPlayer::Black)
+    )
+}
+
+lazy_static! {
+    static ref INDEX: IndexTables = IndexTables::new();
+}
+
+/// The tables used to turn a placement of pieces into an index in a tablebase.
+struct IndexTables {
+    /// The squares below the a1-h8 diagonal, numbered 0 to 27.
+    map_b1h1h7: [u64; 64],
+    /// The triangle a1-d1-d4, with the squares below the diagonal first.
+    map_a1d1d4: [u64; 64],
+    /// Both kings without pawns, 462 legal placements.
+    map_kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; 7],
+    /// The squares a pawn can be on, numbered from 47 on a2 down, two files at a time from the
+    /// edges to the center.
+    map_pawns: [u64; 64],
+    /// The index of the leading pawn, by number of leading pawns and square.
+    lead_pawn_idx: [[u64; 64]; 6],
+    /// The number of placements of the leading pawns, by number of leading pawns and file.
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+impl IndexTables {
+    fn new() -> IndexTables {
+        let mut tables = IndexTables {
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 7],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        let mut code = 0;
+        for s in 0..64 {
+            if off_diagonal(s) < 0 {
+                tables.map_b1h1h7[s] = code;
+                code += 1;
+            }
+        }
+
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for s in 0..28 {
+            if file_of(s) > 3 {
+                continue;
+            }
+            if off_diagonal(s) < 0 {
+                tables.map_a1d1d4[s] = code;
+                code += 1;
+            } else if off_diagonal(s) == 0 {
+                diagonal.push(s);
+            }
+        }
+        for s in diagonal {
+            tables.map_a1d1d4[s] = code;
+            code += 1;
+        }
+
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                // Squares outside the triangle also map to 0, only b1 is the real one.
+                if tables.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let adjacent = (file_of(s1) as i32 - file_of(s2) as i32).abs() <= 1
+                        && (rank_of(s1) as i32 - rank_of(s2) as i32).abs() <= 1;
+                    if adjacent || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        tables.map_kk[idx as usize][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            tables.map_kk[idx as usize][s2] = code;
+            code += 1;
+        }
+
+        tables.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..7.min(n + 1) {
+                tables.binomial[k][n] = if k > 0 {
+                    tables.binomial[k - 1][n - 1]
+                } else {
+                    0
+                } + if k < n { tables.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        for lead_pawns in 1..6 {
+            let mut available = 48;
+            for f in 0..4 {
+                let mut idx = 0;
+                for r in 1..7 {
+                    let s = 8 * r + f;
+                    if lead_pawns == 1 {
+                        available -= 1;
+                        tables.map_pawns[s] = available;
+                        available -= 1;
+                        tables.map_pawns[s ^ 7] = available;
+                    }
+                    tables.lead_pawn_idx[lead_pawns][s] = idx;
+                    idx += tables.binomial[lead_pawns - 1][tables.map_pawns[s] as usize];
+                }
+                tables.lead_pawns_size[lead_pawns][f] = idx;
+            }
+        }
+        tables
+    }
+}
+
+/// The compression and indexing data for one file (for tables with pawns) and side to move.
+#[derive(Debug, Default, Clone)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    /// The length of each group of pieces that is indexed together, terminated by a 0.
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    max_sym_len: usize,
+    min_sym_len: usize,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    btree: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    block_length: usize,
+    block_length_size: usize,
+    data: usize,
+    /// Offsets into the DTZ value map, by WDL result.
+    map_idx: [usize; 4],
+}
+
+/// One tablebase file, read into memory.
+struct Table {
+    bytes: Vec<u8>,
+    dtz: bool,
+    symmetric: bool,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    piece_count: usize,
+    /// The pawns of the leading color, and of the other color.
+    pawn_count: [usize; 2],
+    /// Indexed by side to move, then file.
+    pairs: Vec<Vec<PairsData>>,
+    map: usize,
+}
+
+impl Table {
+    fn open(path: &Path, name: &str, dtz: bool) -> Result<Table> {
+        let bytes = fs::read(path)?;
+        Table::from_bytes(bytes, name, dtz)
+            .map_err(|e| format_err!("Could not read {:?}: {}", path, e))
+    }
+
+    fn from_bytes(bytes: Vec<u8>, name: &str, dtz: bool) -> Result<Table> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.len() < 5 || bytes[..4] != magic || bytes.len() % 64 != 16 {
+            bail!("Not a tablebase file");
+        }
+
+        let mut sides = name.split('v');
+        let (white, black) = match (sides.next(), sides.next()) {
+            (Some(white), Some(black)) => (white, black),
+            _ => bail!("Invalid table name {:?}", name),
+        };
+        let count = |side: &str, letter: char| side.chars().filter(|c| *c == letter).count();
+        let white_pawns = count(white, 'P');
+        let black_pawns = count(black, 'P');
+        let mut has_unique_pieces = false;
+        for (_, letter) in &PIECE_ORDER[1..] {
+            has_unique_pieces |= count(white, *letter) == 1 || count(black, *letter) == 1;
+        }
+        // The leading color is the one with pawns, or the fewest pawns if both have them.
+        let pawn_count = if black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns) {
+            [white_pawns, black_pawns]
+        } else {
+            [black_pawns, white_pawns]
+        };
+
+        let mut table = Table {
+            bytes,
+            dtz,
+            symmetric: white == black,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            piece_count: white.len() + black.len(),
+            pawn_count,
+            pairs: Vec::new(),
+            map: 0,
+        };
+        if table.piece_count > MAX_PIECES {
+            bail!("Table {:?} has too many pieces", name);
+        }
+        table.read()?;
+        Ok(table)
+    }
+
+    fn byte(&self, offset: usize) -> Result<u8> {
+        match self.bytes.get(offset) {
+            Some(byte) => Ok(*byte),
+            None => bail!("Unexpected end of file"),
+        }
+    }
+
+    fn u16_le(&self, offset: usize) -> Result<u16> {
+        Ok(u16::from(self.byte(offset)?) | u16::from(self.byte(offset + 1)?) << 8)
+    }
+
+    fn u32_le(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from(self.u16_le(offset)?) | u32::from(self.u16_le(offset + 2)?) << 16)
+    }
+
+    /// Read the headers of the table. The data itself stays in `bytes`.
+    fn read(&mut self) -> Result<()> {
+        let flags = self.byte(4)?;
+        let split = flags & 1 != 0;
+        let has_pawns = flags & 2 != 0;
+        let sides = if !self.dtz && !self.symmetric { 2 } else { 1 };
+        if has_pawns != self.has_pawns || split == self.symmetric {
+            bail!("Table flags don't match the material");
+        }
+
+        let files = if self.has_pawns { 4 } else { 1 };
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        self.pairs = vec![vec![PairsData::default(); files]; sides];
+
+        let mut p = 5;
+        for f in 0..files {
+            let first = self.byte(p)?;
+            let second = if pp { self.byte(p + 1)? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            p += 1 + pp as usize;
+
+            for k in 0..self.piece_count {
+                let byte = self.byte(p)?;
+                for i in 0..sides {
+                    self.pairs[i][f].pieces[k] = if i == 1 { byte >> 4 } else { byte & 0xF };
+                }
+                p += 1;
+            }
+            for (i, order) in order.iter().enumerate().take(sides) {
+                let mut pairs = self.pairs[i][f].clone();
+                self.set_groups(&mut pairs, *order, f);
+                self.pairs[i][f] = pairs;
+            }
+        }
+        p += p & 1;
+
+        for f in 0..files {
+            for i in 0..sides {
+                let mut pairs = self.pairs[i][f].clone();
+                p = self.set_sizes(&mut pairs, p)?;
+                self.pairs[i][f] = pairs;
+            }
+        }
+
+        if self.dtz {
+            p = self.set_dtz_map(p, files)?;
+        }
+
+        for f in 0..files {
+            for i in 0..sides {
+                self.pairs[i][f].sparse_index = p;
+                p += self.pairs[i][f].sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                self.pairs[i][f].block_length = p;
+                p += self.pairs[i][f].block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                p = (p + 0x3F) & !0x3F;
+                self.pairs[i][f].data = p;
+                p += self.pairs[i][f].num_blocks * self.pairs[i][f].block_size;
+            }
+        }
+        if p > self.bytes.len() {
+            bail!("Unexpected end of file");
+        }
+        Ok(())
+    }
+
+    /// Split the pieces in groups that are indexed together, and compute the factor of each group
+    /// in the final index. `order` says where the leading group and the other pawns go in the
+    /// order of factors.
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], f: usize) {
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns {
+            0
+        } else if self.has_unique_pieces {
+            3
+        } else {
+            2
+        };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let pp = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if pp { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    INDEX.lead_pawns_size[d.group_len[0]][f]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= INDEX.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= INDEX.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    /// Read the sizes of the compressed data and the Huffman code, returning the offset after it.
+    fn set_sizes(&self, d: &mut PairsData, mut p: usize) -> Result<usize> {
+        d.flags = self.byte(p)?;
+        p += 1;
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            d.min_sym_len = usize::from(self.byte(p)?);
+            return Ok(p + 1);
+        }
+
+        let groups = d.group_len.iter().take_while(|len| **len != 0).count();
+        let tb_size = d.group_idx[groups];
+
+        d.block_size = 1 << self.byte(p)?;
+        d.span = 1 << self.byte(p + 1)?;
+        d.sparse_index_size = tb_size.div_ceil(d.span) as usize;
+        let padding = usize::from(self.byte(p + 2)?);
+        d.num_blocks = self.u32_le(p + 3)? as usize;
+        d.block_length_size = d.num_blocks + padding;
+        d.max_sym_len = usize::from(self.byte(p + 7)?);
+        d.min_sym_len = usize::from(self.byte(p + 8)?);
+        p += 9;
+        d.lowest_sym = p;
+        if d.max_sym_len < d.min_sym_len || d.min_sym_len == 0 {
+            bail!("Invalid symbol lengths");
+        }
+
+        // The canonical Huffman code: base64[l] is the first code of length `min_sym_len + l`,
+        // left aligned in 64 bits.
+        let lengths = d.max_sym_len - d.min_sym_len + 1;
+        d.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = u64::from(self.u16_le(p + 2 * i)?);
+            let next_lowest = u64::from(self.u16_le(p + 2 * (i + 1))?);
+            d.base64[i] = (d.base64[i + 1]
+                .wrapping_add(lowest)
+                .wrapping_sub(next_lowest))
+                / 2;
+        }
+        for (i, base) in d.base64.iter_mut().enumerate() {
+            *base = base
+                .checked_shl((64 - i - d.min_sym_len) as u32)
+                .unwrap_or(0);
+        }
+        p += lengths * 2;
+
+        let symbols = usize::from(self.u16_le(p)?);
+        p += 2;
+        d.btree = p;
+        if p + symbols * 3 > self.bytes.len() {
+            bail!("Unexpected end of file");
+        }
+        d.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.set_symlen(d, sym, &mut visited)?;
+            }
+        }
+        Ok(p + symbols * 3 + (symbols & 1))
+    }
+
+    /// The two symbols a symbol stands for, `None` for a leaf.
+    fn btree_pair(&self, d: &PairsData, sym: usize) -> Option<(usize, usize)> {
+        let b = &self.bytes[d.btree + 3 * sym..d.btree + 3 * sym + 3];
+        let right = usize::from(b[2]) << 4 | usize::from(b[1]) >> 4;
+        if right == 0xFFF {
+            None
+        } else {
+            Some((usize::from(b[1] & 0xF) << 8 | usize::from(b[0]), right))
+        }
+    }
+
+    /// The number of values a symbol expands to, minus one.
+    fn set_symlen(&self, d: &mut PairsData, sym: usize, visited: &mut [bool]) -> Result<()> {
+        visited[sym] = true;
+        if let Some((left, right)) = self.btree_pair(d, sym) {
+            if left >= visited.len() || right >= visited.len() {
+                bail!("Invalid symbol {}", sym);
+            }
+            for child in &[left, right] {
+                if !visited[*child] {
+                    self.set_symlen(d, *child, visited)?;
+                }
+            }
+            d.symlen[sym] = d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    fn set_dtz_map(&mut self, mut p: usize, files: usize) -> Result<usize> {
+        self.map = p;
+        for f in 0..files {
+            let flags = self.pairs[0][f].flags;
+            if flags & FLAG_MAPPED == 0 {
+                continue;
+            }
+            if flags & FLAG_WIDE != 0 {
+                p += p & 1;
+                for i in 0..4 {
+                    self.pairs[0][f].map_idx[i] = (p - self.map) / 2 + 1;
+                    p += 2 * usize::from(self.u16_le(p)?) + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    self.pairs[0][f].map_idx[i] = p - self.map + 1;
+                    p += usize::from(self.byte(p)?) + 1;
+                }
+            }
+        }
+        Ok(p + (p & 1))
+    }
+
+    fn pairs(&self, stm: usize, f: usize) -> &PairsData {
+        let side = &self.pairs[stm % self.pairs.len()];
+        &side[if self.has_pawns { f } else { 0 }]
+    }
+
+    /// Find the value at `idx`: look up the block it is in with the sparse index, and walk the
+    /// Huffman coded symbols in that block until the one containing `idx`.
+    fn decompress_pairs(&self, d: &PairsData, idx: u64) -> Result<usize> {
+        if d.flags & FLAG_SINGLE_VALUE != 0 {
+            return Ok(d.min_sym_len);
+        }
+        let bytes = &self.bytes;
+
+        let k = (idx / d.span) as usize;
+        let entry = d.sparse_index + 6 * k;
+        let mut block = self.u32_le(entry)? as usize;
+        let mut offset = i64::from(self.u16_le(entry + 4)?);
+        offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+
+        let block_length = |block: usize| -> Result<i64> {
+            Ok(i64::from(self.u16_le(d.block_length + 2 * block)?) + 1)
+        };
+        while offset < 0 {
+            block = block
+                .checked_sub(1)
+                .ok_or_else(|| format_err!("Invalid index {}", idx))?;
+            offset += block_length(block)?;
+        }
+        loop {
+            let length = block_length(block)?;
+            if offset < length {
+                break;
+            }
+            offset -= length;
+            block += 1;
+        }
+
+        let start = d.data + block * d.block_size;
+        if start + d.block_size > bytes.len() {
+            bail!("Invalid block {}", block);
+        }
+        let read_u32 = |at: usize| -> u64 {
+            bytes
+                .get(at..at + 4)
+                .map(|b| u64::from(u32::from_be_bytes([b[0], b[1], b[2], b[3]])))
+                .unwrap_or(0)
+        };
+        let mut at = start;
+        let mut buf64 = read_u32(at) << 32 | read_u32(at + 4);
+        at += 8;
+        let mut buf64_size = 64;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < d.base64[len] {
+                len += 1;
+                if len >= d.base64.len() {
+                    bail!("Invalid code in block {}", block);
+                }
+            }
+            let code = (buf64 - d.base64[len]) >> (64 - len - d.min_sym_len);
+            sym = usize::from(self.u16_le(d.lowest_sym + 2 * len)?) + code as usize;
+            if sym >= d.symlen.len() {
+                bail!("Invalid symbol in block {}", block);
+            }
+            let run = i64::from(d.symlen[sym]) + 1;
+            if offset < run {
+                break;
+            }
+            offset -= run;
+
+            len += d.min_sym_len;
+            buf64 = buf64.checked_shl(len as u32).unwrap_or(0);
+            buf64_size -= len;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                buf64 |= read_u32(at) << (64 - buf64_size);
+                at += 4;
+            }
+        }
+
+        // Descend the tree of the symbol to the value at the offset.
+        while d.symlen[sym] != 0 {
+            let (left, right) = match self.btree_pair(d, sym) {
+                Some(pair) => pair,
+                None => break,
+            };
+            let left_run = i64::from(d.symlen[left]) + 1;
+            if offset < left_run {
+                sym = left;
+            } else {
+                offset -= left_run;
+                sym = right;
+            }
+        }
+        Ok(usize::from(self.bytes[d.btree + 3 * sym])
+            | usize::from(self.bytes[d.btree + 3 * sym + 1] & 0xF) << 8)
+    }
+
+    /// Turn the value from the table into a WDL result (for WDL tables) or a DTZ (for DTZ tables,
+    /// where `wdl` is the result of the position).
+    fn map_score(&self, f: usize, value: usize, wdl: Wdl) -> Result<i32> {
+        if !self.dtz {
+            return Ok(value as i32 - 2);
+        }
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let d = self.pairs(0, f);
+        let mut value = value as i32;
+        if d.flags & FLAG_MAPPED != 0 {
+            let idx = d.map_idx[WDL_MAP[(wdl as i32 + 2) as usize]];
+            value = if d.flags & FLAG_WIDE != 0 {
+                i32::from(self.u16_le(self.map + 2 * (idx + value as usize))?)
+            } else {
+                i32::from(self.byte(self.map + idx + value as usize)?)
+            };
+        }
+        // DTZ is stored in moves unless the table says it is in plies, cursed and blessed results
+        // are always stored in moves.
+        if (wdl == Wdl::Win && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == Wdl::Loss && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == Wdl::CursedWin
+            || wdl == Wdl::BlessedLoss
+        {
+            value *= 2;
+        }
+        Ok(value + 1)
+    }
+
+    /// The side to move and file of the table the position is stored in, and its index there.
+    /// `black_stronger` is whether the colors are swapped, see `Tablebases::table`.
+    fn index(&self, position: &Position, black_stronger: bool) -> Result<(usize, usize, u64)> {
+        // The tables are stored with the stronger side as white. With symmetric material, they
+        // only store white to move.
+        let flip_color = if self.symmetric {
+            !position.white_to_move
+        } else {
+            black_stronger
+        };
+        let flip_color_code = if flip_color { 8 } else { 0 };
+        let flip_squares = if flip_color { 56 } else { 0 };
+        let stm = (!position.white_to_move ^ flip_color) as usize;
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = 0;
+        let mut tb_file = 0;
+
+        if self.has_pawns {
+            // The leading pawns are those of the color of the first piece in the table.
+            let lead = self.pairs[0][0].pieces[0] ^ flip_color_code;
+            for (code, square) in &position.pieces {
+                if *code == lead {
+                    squares[size] = square ^ flip_squares;
+                    pieces[size] = code ^ flip_color_code;
+                    size += 1;
+                }
+            }
+            lead_pawns = size;
+            let max = (0..lead_pawns)
+                .max_by_key(|i| INDEX.map_pawns[squares[*i]])
+                .unwrap();
+            squares.swap(0, max);
+            tb_file = file_of(squares[0]);
+            if tb_file > 3 {
+                tb_file = file_of(squares[0] ^ 7);
+            }
+        }
+
+        let d = self.pairs(stm, tb_file);
+
+        let lead = if self.has_pawns {
+            self.pairs[0][0].pieces[0] ^ flip_color_code
+        } else {
+            0
+        };
+        for (code, square) in &position.pieces {
+            if self.has_pawns && *code == lead {
+                continue;
+            }
+            squares[size] = square ^ flip_squares;
+            pieces[size] = code ^ flip_color_code;
+            size += 1;
+        }
+        if size != self.piece_count {
+            bail!("Position doesn't match the table material");
+        }
+
+        // Put the pieces in the order of the table.
+        for i in lead_pawns..size - 1 {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Use the symmetries of the board: the leading piece is moved to the a-d files, and
+        // without pawns to the a1-d1-d4 triangle.
+        if file_of(squares[0]) > 3 {
+            for square in squares.iter_mut().take(size) {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = INDEX.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|s| INDEX.map_pawns[*s]);
+            for (i, square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += INDEX.binomial[i][INDEX.map_pawns[*square] as usize];
+            }
+        } else {
+            if rank_of(squares[0]) > 3 {
+                for square in squares.iter_mut().take(size) {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                if off_diagonal(squares[i]) == 0 {
+                    continue;
+                }
+                if off_diagonal(squares[i]) > 0 {
+                    for square in squares.iter_mut().take(size).skip(i) {
+                        *square = flip_diagonal(*square);
+                    }
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as u64;
+                let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+                idx = if off_diagonal(s0) != 0 {
+                    (INDEX.map_a1d1d4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + rank_of(s0) as u64 * 28 + INDEX.map_b1h1h7[s1]) * 62 + s2 as u64
+                        - adjust2
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank_of(s0) as u64 * 7 * 28
+                        + (rank_of(s1) as u64 - adjust1) * 28
+                        + INDEX.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank_of(s0) as u64 * 7 * 6
+                        + (rank_of(s1) as u64 - adjust1) * 6
+                        + (rank_of(s2) as u64 - adjust2)
+                };
+            } else {
+                idx = INDEX.map_kk[INDEX.map_a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        // The other groups are indexed as combinations of the squares left over.
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[group_start..group_start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[group_start + i];
+                let adjust = squares[..group_start]
+                    .iter()
+                    .filter(|s| **s < square)
+                    .count();
+                let free = square - adjust - if remaining_pawns { 8 } else { 0 };
+                n += INDEX.binomial[i + 1][free];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start += len;
+            next += 1;
+        }
+        Ok((stm, tb_file, idx))
+    }
+}
+
+/// The outcome of looking up a position in a table.
+enum Probe {
+    Value(i32),
+    /// The DTZ table only has the other side to move in this position.
+    ChangeStm,
+}
+
+/// The Syzygy tables in one or more directories. Tables are read the first time a position with
+/// their material is probed.
+pub struct Tablebases {
+    directories: Vec<PathBuf>,
+    max_pieces: usize,
+    wdl: HashMap<String, Option<Table>>,
+    dtz: HashMap<String, Option<Table>>,
+}
+
+impl std::fmt::Debug for Tablebases {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.debug_struct("Tablebases")
+            .field("directories", &self.directories)
+            .field("max_pieces", &self.max_pieces)
+            .finish()
+    }
+}
+
+/// Castling is not in the tables.
+fn has_castling_rights(state: &BoardState) -> bool {
+    [CurrentPlayer::White, CurrentPlayer::Black]
+        .iter()
+        .any(|player| state.castling_rights(*player) != [false, false])
+}
+
+fn piece_count(state: &BoardState) -> usize {
+    let mut count = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            if state.get_piece(x, y) != Piece::None {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+fn is_zeroing(state: &BoardState, m: Move) -> bool {
+    state.is_capture(m) || state.get_piece(m.from.0, m.from.1).kind() == Some(PieceKind::Pawn)
+}
+
+fn after(state: &BoardState, m: Move) -> Result<BoardState> {
+    let mut next = state.clone();
+    next.apply_move(m)?;
+    Ok(next)
+}
+
+/// The DTZ of a position where the best move zeroes, from its WDL result.
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+impl Tablebases {
+    /// Use the tables in `path`, which can hold multiple directories separated by `:` (or `;` on
+    /// Windows), like UCI's `SyzygyPath`.
+    pub fn open(path: &str) -> Result<Tablebases> {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut tablebases = Tablebases {
+            directories: Vec::new(),
+            max_pieces: 0,
+            wdl: HashMap::new(),
+            dtz: HashMap::new(),
+        };
+        for directory in path.split(separator).filter(|d| !d.is_empty()) {
+            for entry in fs::read_dir(directory)? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                if let Some(name) = path.file_stem().and_then(|n| n.to_str()) {
+                    tablebases.max_pieces = tablebases.max_pieces.max(name.len() - 1);
+                }
+            }
+            tablebases.directories.push(PathBuf::from(directory));
+        }
+        Ok(tablebases)
+    }
+
+    /// The most pieces (including kings) of any table that was found.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether the position could be in the tables: few enough pieces and no castling rights.
+    pub fn can_probe(&self, state: &BoardState) -> bool {
+        let count = piece_count(state);
+        count == 2 || (count <= self.max_pieces && !has_castling_rights(state))
+    }
+
+    fn find(&self, name: &str, extension: &str) -> Option<PathBuf> {
+        self.directories
+            .iter()
+            .map(|directory| directory.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file())
+    }
+
+    /// The table with the material of the position, and whether colors are swapped in it.
+    fn table(&mut self, state: &BoardState, dtz: bool) -> Result<(&Table, bool)> {
+        let white = side_name(state, CurrentPlayer::White);
+        let black = side_name(state, CurrentPlayer::Black);
+        let mut found = None;
+        for (name, black_stronger) in &[
+            (format!("{}v{}", white, black), false),
+            (format!("{}v{}", black, white), true),
+        ] {
+            let (tables, extension) = if dtz {
+                (&self.dtz, "rtbz")
+            } else {
+                (&self.wdl, "rtbw")
+            };
+            if !tables.contains_key(name) {
+                let table = match self.find(name, extension) {
+                    Some(path) => Some(Table::open(&path, name, dtz)?),
+                    None => None,
+                };
+                let tables = if dtz { &mut self.dtz } else { &mut self.wdl };
+                tables.insert(name.clone(), table);
+            }
+            let tables = if dtz { &self.dtz } else { &self.wdl };
+            if let Some(Some(_)) = tables.get(name) {
+                found = Some((name.clone(), *black_stronger));
+                break;
+            }
+        }
+        let (name, black_stronger) = match found {
+            Some(found) => found,
+            None => bail!(
+                "No {} table for {}v{}",
+                if dtz { "DTZ" } else { "WDL" },
+                white,
+                black
+            ),
+        };
+        let tables = if dtz { &self.dtz } else { &self.wdl };
+        Ok((tables[&name].as_ref().unwrap(), black_stronger))
+    }
+
+    /// Look up the position in its table. The position must not have a capture available for the
+    /// WDL value to be right, as the tables don't store those.
+    fn probe_table(&mut self, state: &BoardState, wdl: Wdl, dtz: bool) -> Result<Probe> {
+        let position = Position::from_state(state);
+        let (table, black_stronger) = self.table(state, dtz)?;
+        let (stm, tb_file, idx) = table.index(&position, black_stronger)?;
+        let d = table.pairs(stm, tb_file);
+        // Symmetric tables without pawns are the same for both sides to move.
+        let both_sides = table.symmetric && !table.has_pawns;
+        if dtz && !both_sides && (d.flags & FLAG_STM) as usize != stm {
+            return Ok(Probe::ChangeStm);
+        }
+        let value = table.decompress_pairs(d, idx)?;
+        Ok(Probe::Value(table.map_score(tb_file, value, wdl)?))
+    }
+
+    /// The best result over the captures, or over all zeroing moves with `check_zeroing`, and
+    /// whether that was better than the result in the table. Captures are not in the tables, so
+    /// they have to be searched.
+    fn search(&mut self, state: &BoardState, check_zeroing: bool) -> Result<(Wdl, bool)> {
+        let moves = state.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut move_count = 0;
+        for m in &moves {
+            if !state.is_capture(*m) && (!check_zeroing || !is_zeroing(state, *m)) {
+                continue;
+            }
+            move_count += 1;
+            let value = -self.search(&after(state, *m)?, false)?.0;
+            if value > best {
+                best = value;
+                if value >= Wdl::Win {
+                    // A winning zeroing move is the best there is.
+                    return Ok((value, true));
+                }
+            }
+        }
+
+        // If every move was searched, the table is not needed.
+        let no_more_moves = move_count > 0 && move_count == moves.len();
+        let value = if no_more_moves {
+            best
+        } else if piece_count(state) == 2 {
+            Wdl::Draw
+        } else {
+            match self.probe_table(state, Wdl::Draw, false)? {
+                Probe::Value(value) => Wdl::from_i32(value),
+                Probe::ChangeStm => unreachable!(),
+            }
+        };
+
+        if best >= value {
+            let zeroing = best > Wdl::Draw || no_more_moves;
+            Ok((best, zeroing))
+        } else {
+            Ok((value, false))
+        }
+    }
+
+    /// The result of the position for the player to move.
+    pub fn probe_wdl(&mut self, state: &BoardState) -> Result<Wdl> {
+        if piece_count(state) == 2 {
+            return Ok(Wdl::Draw);
+        }
+        self.check(state)?;
+        Ok(self.search(state, false)?.0)
+    }
+
+    /// The number of plies to the next zeroing move with best play: positive if the player to move
+    /// wins, negative if they lose, 0 for a draw. Cursed wins and blessed losses are more than 100
+    /// plies away. The value can be one ply off, as some tables store moves instead of plies.
+    pub fn probe_dtz(&mut self, state: &BoardState) -> Result<i32> {
+        if piece_count(state) == 2 {
+            return Ok(0);
+        }
+        self.check(state)?;
+        let (wdl, zeroing) = self.search(state, true)?;
+        if wdl == Wdl::Draw {
+            return Ok(0);
+        }
+        if zeroing {
+            return Ok(dtz_before_zeroing(wdl));
+        }
+
+        let sign = if wdl > Wdl::Draw { 1 } else { -1 };
+        if let Probe::Value(dtz) = self.probe_table(state, wdl, true)? {
+            let cursed = wdl == Wdl::CursedWin || wdl == Wdl::BlessedLoss;
+            return Ok((dtz + if cursed { 100 } else { 0 }) * sign);
+        }
+
+        // The table only has the other side to move, so search one ply.
+        let mut min_dtz = None;
+        for m in state.legal_moves() {
+            let zeroing = is_zeroing(state, m);
+            let next = after(state, m)?;
+            // For a zeroing move the DTZ is that of the move itself, so only the result of the
+            // position after it matters.
+            let mut dtz = if zeroing {
+                dtz_before_zeroing(-self.search(&next, false)?.0)
+            } else {
+                -self.probe_dtz(&next)?
+            };
+            if dtz == 1 && next.is_in_check(next.current_player) && next.legal_moves().is_empty() {
+                min_dtz = Some(1);
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            // Skip draws, and when winning only look at the winning moves.
+            if dtz.signum() == sign && min_dtz.is_none_or(|min| dtz < min) {
+                min_dtz = Some(dtz);
+            }
+        }
+        // Without legal moves the position is mate.
+        Ok(min_dtz.unwrap_or(-1))
+    }
+
+    fn check(&self, state: &BoardState) -> Result<()> {
+        if has_castling_rights(state) {
+            bail!("Positions with castling rights are not in the tablebases");
+        }
+        if piece_count(state) > self.max_pieces {
+            bail!("No tablebases with {} pieces", piece_count(state));
+        }
+        Ok(())
+    }
+
+    /// The moves that keep the best result for the player to move. With the DTZ tables available,
+    /// only the moves that win fastest (or lose slowest) are kept, otherwise all moves with the
+    /// best WDL result. To be used at the root, so the search only looks at these moves.
+    pub fn root_moves(&mut self, state: &BoardState) -> Result<Vec<Move>> {
+        self.check(state)?;
+        let moves = state.legal_moves();
+        let ranks = match self.rank_by_dtz(state, &moves) {
+            Ok(ranks) => ranks,
+            Err(_) => self.rank_by_wdl(state, &moves)?,
+        };
+        let best = ranks.iter().max().cloned().unwrap_or(0);
+        Ok(moves
+            .into_iter()
+            .zip(ranks)
+            .filter(|(_, rank)| *rank == best)
+            .map(|(m, _)| m)
+            .collect())
+    }
+
+    fn rank_by_wdl(&mut self, state: &BoardState, moves: &[Move]) -> Result<Vec<i32>> {
+        moves
+            .iter()
+            .map(|m| Ok(-(self.probe_wdl(&after(state, *m)?)? as i32)))
+            .collect()
+    }
+
+    fn rank_by_dtz(&mut self, state: &BoardState, moves: &[Move]) -> Result<Vec<i32>> {
+        const MAX_DTZ: i32 = 1 << 18;
+        let mut ranks = Vec::with_capacity(moves.len());
+        for m in moves {
+            let next = after(state, *m)?;
+            let dtz = if is_zeroing(state, *m) {
+                dtz_before_zeroing(-self.probe_wdl(&next)?)
+            } else {
+                let dtz = -self.probe_dtz(&next)?;
+                dtz + dtz.signum()
+            };
+            // Mate in one isn't zeroing, but it is the quickest win there is.
+            let dtz = if next.is_in_check(next.current_player) && next.legal_moves().is_empty() {
+                1
+            } else {
+                dtz
+            };
+            ranks.push(match dtz {
+                dtz if dtz > 0 => MAX_DTZ - dtz,
+                dtz if dtz < 0 => -MAX_DTZ - dtz,
+                _ => 0,
+            });
+        }
+        Ok(ranks)
+    }
+}
+
+#[test]
+fn test_index_tables() {
+    // 462 placements of two kings without pawns, 28 squares below the diagonal.
+    let max_kk = INDEX.map_kk.iter().flat_map(|row| row.iter()).max();
+    assert_eq!(Some(&461), max_kk);
+    assert_eq!(Some(&27), INDEX.map_b1h1h7.iter().max());
+    assert_eq!(0, INDEX.map_a1d1d4[1]);
+    assert_eq!(9, INDEX.map_a1d1d4[27]);
+
+    assert_eq!(47, INDEX.map_pawns[8]);
+    assert_eq!(46, INDEX.map_pawns[15]);
+    assert_eq!(6, INDEX.lead_pawns_size[1][0]);
+    assert_eq!(7_028_847, INDEX.binomial[5][63]);
+}
+
+#[test]
+fn test_position_conversion() {
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKingMoved);
+    state.set_piece((0, 6), Piece::WhitePawnMoved);
+    state.set_piece((3, 7), Piece::BlackKingMoved);
+    state.current_player = CurrentPlayer::Black;
+
+    let position = Position::from_state(&state);
+    // e1 = 4, h7 = 55, e8 = 60
+    assert_eq!(vec![(6, 4), (1, 55), (14, 60)], position.pieces);
+    assert!(!position.white_to_move);
+    assert_eq!(
+        position,
+        Position::from_state(&position.to_state().unwrap())
+    );
+    assert_eq!("KPvK", material_name(&state));
+}
+
+#[test]
+fn test_missing_tables() {
+    let mut tablebases = Tablebases::open(env!("CARGO_MANIFEST_DIR")).unwrap();
+    assert_eq!(0, tablebases.max_pieces());
+
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKingMoved);
+    state.set_piece((3, 7), Piece::BlackKingMoved);
+    assert_eq!(Wdl::Draw, tablebases.probe_wdl(&state).unwrap());
+
+    state.set_piece((0, 0), Piece::WhiteRookMoved);
+    assert!(tablebases.probe_wdl(&state).is_err());
+    assert!(!tablebases.can_probe(&BoardState::init()));
+}
+
+#[test]
+fn test_castling_rights() {
+    assert!(has_castling_rights(&BoardState::init()));
+    // A king that never moved has no rights once its rooks are gone.
+    let mut state = BoardState::empty();
+    state.set_piece((3, 0), Piece::WhiteKing);
+    state.set_piece((3, 7), Piece::BlackKing);
+    state.set_piece((0, 6), Piece::WhiteRookMoved);
+    assert!(!has_castling_rights(&state));
+    state.set_piece((7, 7), Piece::BlackRook);
+    assert!(has_castling_rights(&state));
+}
+
+#[cfg(test)]
+fn fixture_state(pieces: &[((u8, u8), Piece)], player: CurrentPlayer) -> BoardState {
+    let mut state = BoardState::empty();
+    for (coordinates, piece) in pieces {
+        state.set_piece(*coordinates, *piece);
+    }
+    state.current_player = player;
+    state
+}
+
+/// The tables in `fixtures/syzygy` are solved and written by `generate`.
+#[test]
+fn test_probe_fixtures() {
+    use self::CurrentPlayer::{Black, White};
+    use self::Piece::{BlackKingMoved, BlackPawnMoved, BlackRookMoved};
+    use self::Piece::{WhiteKingMoved, WhitePawnMoved, WhiteQueen, WhiteRookMoved};
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/syzygy");
+    let mut tablebases = Tablebases::open(path).unwrap();
+    assert_eq!(3, tablebases.max_pieces());
+    let mut probe = |pieces: &[((u8, u8), Piece)], player| {
+        let state = fixture_state(pieces, player);
+        let wdl = tablebases.probe_wdl(&state).unwrap();
+        (wdl, tablebases.probe_dtz(&state).unwrap())
+    };
+
+    // White Ke1, Qd1 against black Ke8.
+    let pieces = [
+        ((3, 0), WhiteKingMoved),
+        ((4, 0), WhiteQueen),
+        ((3, 7), BlackKingMoved),
+    ];
+    let (wdl, dtz) = probe(&pieces, White);
+    assert_eq!(Wdl::Win, wdl);
+    assert!((2..=19).contains(&dtz));
+    let (wdl, dtz) = probe(&pieces, Black);
+    assert_eq!(Wdl::Loss, wdl);
+    assert!((-19..=-2).contains(&dtz));
+
+    // Kb6, Qc7 against Ka8 mates with Qb7 or Qc8, and is stalemate with black to move.
+    let pieces = [
+        ((6, 5), WhiteKingMoved),
+        ((5, 6), WhiteQueen),
+        ((7, 7), BlackKingMoved),
+    ];
+    assert_eq!((Wdl::Win, 1), probe(&pieces, White));
+    assert_eq!((Wdl::Draw, 0), probe(&pieces, Black));
+
+    // Kb6, Rh8 against Ka8 is mate, and Rh1 mates in one.
+    let mut pieces = [
+        ((6, 5), WhiteKingMoved),
+        ((0, 7), WhiteRookMoved),
+        ((7, 7), BlackKingMoved),
+    ];
+    assert_eq!((Wdl::Loss, -1), probe(&pieces, Black));
+    pieces[1].0 = (0, 0);
+    assert_eq!((Wdl::Win, 1), probe(&pieces, White));
+    let (wdl, dtz) = probe(&pieces, Black);
+    assert_eq!(Wdl::Loss, wdl);
+    assert!(dtz < -1);
+    // The same with the colors swapped: Kb3, Rh8 against Ka1, black to move.
+    let pieces = [
+        ((6, 2), BlackKingMoved),
+        ((0, 7), BlackRookMoved),
+        ((7, 0), WhiteKingMoved),
+    ];
+    assert_eq!((Wdl::Win, 1), probe(&pieces, Black));
+
+    // Black to move takes the rook: Ke1, Rd7 against Ke8.
+    let state = fixture_state(
+        &[
+            ((3, 0), WhiteKingMoved),
+            ((4, 6), WhiteRookMoved),
+            ((3, 7), BlackKingMoved),
+        ],
+        Black,
+    );
+    assert_eq!(Wdl::Draw, tablebases.probe_wdl(&state).unwrap());
+    let moves = tablebases.root_moves(&state).unwrap();
+    assert_eq!(1, moves.len());
+    assert_eq!("e8d7", moves[0].to_string());
+
+    // A pawn on h7 with the black king far away queens.
+    let state = fixture_state(
+        &[
+            ((3, 0), WhiteKingMoved),
+            ((0, 6), WhitePawnMoved),
+            ((7, 7), BlackKingMoved),
+        ],
+        White,
+    );
+    assert_eq!(Wdl::Win, tablebases.probe_wdl(&state).unwrap());
+    let moves = tablebases.root_moves(&state).unwrap();
+    assert!(moves.iter().all(|m| m.to_string().starts_with("h7h8")));
+
+    let mut probe = |pieces: &[((u8, u8), Piece)], player| {
+        let state = fixture_state(pieces, player);
+        tablebases.probe_wdl(&state).unwrap()
+    };
+    // With the king on the sixth rank in front of the pawn, Ke6, Pe5 against Ke8 wins whoever
+    // moves. Black Ke3, Pe4 against Ke1 is the same.
+    let pieces = [
+        ((3, 5), WhiteKingMoved),
+        ((3, 4), WhitePawnMoved),
+        ((3, 7), BlackKingMoved),
+    ];
+    assert_eq!(Wdl::Win, probe(&pieces, White));
+    assert_eq!(Wdl::Loss, probe(&pieces, Black));
+    let pieces = [
+        ((3, 2), BlackKingMoved),
+        ((3, 3), BlackPawnMoved),
+        ((3, 0), WhiteKingMoved),
+    ];
+    assert_eq!(Wdl::Win, probe(&pieces, Black));
+    assert_eq!(Wdl::Loss, probe(&pieces, White));
+    // Ke5, Pe6 against Ke7 holds, and so does Kh8 against a pawn on the h-file.
+    let pieces = [
+        ((3, 4), WhiteKingMoved),
+        ((3, 5), WhitePawnMoved),
+        ((3, 6), BlackKingMoved),
+    ];
+    assert_eq!(Wdl::Draw, probe(&pieces, White));
+    assert_eq!(Wdl::Draw, probe(&pieces, Black));
+    let pieces = [
+        ((7, 0), WhiteKingMoved),
+        ((0, 4), WhitePawnMoved),
+        ((0, 7), BlackKingMoved),
+    ];
+    assert_eq!(Wdl::Draw, probe(&pieces, White));
+    assert_eq!(Wdl::Draw, probe(&pieces, Black));
+}
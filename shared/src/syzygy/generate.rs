@@ -0,0 +1,731 @@
+//! Writes the small tables in `fixtures/syzygy` that `test_probe_fixtures` reads.
+//!
+//! The tables are solved by retrograde analysis over `BoardState::legal_moves`, and written in the
+//! format `Table` reads: every position goes to the index `Table::index` gives it, and the values
+//! are compressed with pairs and a canonical Huffman code like the real tables. Positions that
+//! can't happen, and DTZ values of drawn positions, repeat the value before them. Run
+//! `cargo test --release -p shared generate_fixtures -- --ignored` to write them again.
+
+use super::{
+    after, is_zeroing, material_name, piece_code, Position, Table, Tablebases, Wdl, DTZ_MAGIC,
+    FLAG_LOSS_PLIES, FLAG_SINGLE_VALUE, FLAG_WIN_PLIES, WDL_MAGIC,
+};
+use crate::{Piece, Result};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// The tables to write, with their pieces in the order they are indexed in. Tables with pawns
+/// start with the leading pawn. `KBvK` and `KNvK` are there for the underpromotions in `KPvK`.
+const TABLES: [(&str, [Piece; 3]); 5] = [
+    (
+        "KBvK",
+        [
+            Piece::WhiteKingMoved,
+            Piece::WhiteBishop,
+            Piece::BlackKingMoved,
+        ],
+    ),
+    (
+        "KNvK",
+        [
+            Piece::WhiteKingMoved,
+            Piece::WhiteKnight,
+            Piece::BlackKingMoved,
+        ],
+    ),
+    (
+        "KQvK",
+        [
+            Piece::WhiteKingMoved,
+            Piece::WhiteQueen,
+            Piece::BlackKingMoved,
+        ],
+    ),
+    (
+        "KRvK",
+        [
+            Piece::WhiteKingMoved,
+            Piece::WhiteRookMoved,
+            Piece::BlackKingMoved,
+        ],
+    ),
+    (
+        "KPvK",
+        [
+            Piece::WhitePawnMoved,
+            Piece::WhiteKingMoved,
+            Piece::BlackKingMoved,
+        ],
+    ),
+];
+
+const BLOCK_SIZE_LOG2: u8 = 6;
+const SPAN_LOG2: u8 = 9;
+/// At most this many values go in a block, so the offsets of the sparse index fit in 16 bits.
+const MAX_BLOCK_VALUES: usize = 1 << 15;
+const MAX_PAIRS: usize = 255;
+/// Values are read 32 bits at a time, a code has to fit.
+const MAX_CODE_LEN: usize = 32;
+
+/// Where a move leads: a position of the same table, and whether the move zeroes, or the result
+/// of a position in another table for the player to move there.
+#[derive(Debug, Clone, Copy)]
+enum Next {
+    Position(usize, bool),
+    Result(Wdl),
+}
+
+/// The result and DTZ of every position of a table, by the squares of its pieces in table order
+/// and the side to move.
+struct Solution {
+    codes: Vec<u8>,
+    /// `None` for positions that can't happen.
+    wdl: Vec<Option<Wdl>>,
+    /// In plies, see `Tablebases::probe_dtz`. 0 for draws.
+    dtz: Vec<i32>,
+}
+
+fn key(codes: &[u8], position: &Position) -> usize {
+    let mut key = if position.white_to_move { 0 } else { 1 };
+    for code in codes {
+        let square = position
+            .pieces
+            .iter()
+            .find(|(c, _)| c == code)
+            .map_or(0, |(_, square)| *square);
+        key = key * 64 + square;
+    }
+    key
+}
+
+/// The position with a key, or `None` if pieces overlap or a pawn is on the first or last rank.
+fn position(codes: &[u8], mut key: usize) -> Option<Position> {
+    let mut pieces = vec![(0, 0); codes.len()];
+    for (i, code) in codes.iter().enumerate().rev() {
+        let square = key % 64;
+        key /= 64;
+        if *code & 7 == 1 && !(8..56).contains(&square) {
+            return None;
+        }
+        pieces[i] = (*code, square);
+    }
+    pieces.sort_by_key(|(_, square)| *square);
+    if pieces.windows(2).any(|w| w[0].1 == w[1].1) {
+        return None;
+    }
+    Some(Position {
+        pieces,
+        white_to_move: key == 0,
+    })
+}
+
+fn solve(name: &str, codes: &[u8], solved: &HashMap<String, Solution>) -> Result<Solution> {
+    let size = 2 << (6 * codes.len());
+    let mut moves: Vec<Vec<Next>> = vec![Vec::new(); size];
+    let mut wdl = vec![None; size];
+    let mut valid = Vec::new();
+
+    for key in 0..size {
+        let state = match position(codes, key) {
+            Some(position) => position.to_state()?,
+            None => continue,
+        };
+        let player = state.current_player;
+        if state.is_in_check(player.opponent()) {
+            continue;
+        }
+        valid.push(key);
+        for m in state.legal_moves() {
+            let next = after(&state, m)?;
+            let position = Position::from_state(&next);
+            let other = material_name(&next);
+            moves[key].push(if other == name {
+                Next::Position(self::key(codes, &position), is_zeroing(&state, m))
+            } else if position.pieces.len() == 2 {
+                Next::Result(Wdl::Draw)
+            } else {
+                let other = match solved.get(&other) {
+                    Some(other) => other,
+                    None => bail!("{} needs {} to be solved first", name, other),
+                };
+                match other.wdl[self::key(&other.codes, &position)] {
+                    Some(result) => Next::Result(result),
+                    None => bail!("{} leads to an invalid {} position", name, other.codes[0]),
+                }
+            });
+        }
+        if moves[key].is_empty() {
+            wdl[key] = Some(if state.is_in_check(player) {
+                Wdl::Loss
+            } else {
+                Wdl::Draw
+            });
+        }
+    }
+
+    // A position is won if a move leads to a lost position, and lost if every move leads to a
+    // won one. What is left after that is drawn.
+    loop {
+        let mut changed = false;
+        for key in &valid {
+            if wdl[*key].is_some() {
+                continue;
+            }
+            let results: Vec<Option<Wdl>> = moves[*key]
+                .iter()
+                .map(|next| match next {
+                    Next::Position(next, _) => wdl[*next],
+                    Next::Result(result) => Some(*result),
+                })
+                .collect();
+            if results.contains(&Some(Wdl::Loss)) {
+                wdl[*key] = Some(Wdl::Win);
+                changed = true;
+            } else if results.iter().all(|result| *result == Some(Wdl::Win)) {
+                wdl[*key] = Some(Wdl::Loss);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    for key in &valid {
+        wdl[*key].get_or_insert(Wdl::Draw);
+    }
+
+    let dtz = solve_dtz(&valid, &moves, &wdl)?;
+    Ok(Solution {
+        codes: codes.to_vec(),
+        wdl,
+        dtz,
+    })
+}
+
+/// The DTZ of the won and lost positions, one ply further at a time. A zeroing move or a mate
+/// wins in 1, a mated position is -1, and otherwise the winner takes the shortest way to a
+/// zeroing move and the loser the longest.
+fn solve_dtz(valid: &[usize], moves: &[Vec<Next>], wdl: &[Option<Wdl>]) -> Result<Vec<i32>> {
+    let mut dtz = vec![0i32; wdl.len()];
+    let mated = |key: usize| wdl[key] == Some(Wdl::Loss) && moves[key].is_empty();
+    for key in valid {
+        if mated(*key) {
+            dtz[*key] = -1;
+        }
+    }
+    for key in valid {
+        if wdl[*key] != Some(Wdl::Win) {
+            continue;
+        }
+        let wins_at_once = moves[*key].iter().any(|next| match next {
+            Next::Position(next, zeroing) => {
+                (*zeroing && wdl[*next] == Some(Wdl::Loss)) || mated(*next)
+            }
+            Next::Result(result) => *result == Wdl::Loss,
+        });
+        if wins_at_once {
+            dtz[*key] = 1;
+        }
+    }
+
+    let mut plies = 1;
+    loop {
+        let mut changed = false;
+        for key in valid {
+            if wdl[*key] != Some(Wdl::Loss) || dtz[*key] != 0 {
+                continue;
+            }
+            let mut longest = Some(0);
+            for next in &moves[*key] {
+                let length = match next {
+                    Next::Position(next, false) if dtz[*next] == 0 => None,
+                    Next::Position(next, false) => Some(dtz[*next] + 1),
+                    _ => Some(1),
+                };
+                longest = match (longest, length) {
+                    (Some(longest), Some(length)) => Some(longest.max(length)),
+                    _ => None,
+                };
+            }
+            if let Some(longest) = longest {
+                dtz[*key] = -longest;
+                changed = true;
+            }
+        }
+        for key in valid {
+            if wdl[*key] != Some(Wdl::Win) || dtz[*key] != 0 {
+                continue;
+            }
+            let next_ply = moves[*key].iter().any(|next| match next {
+                Next::Position(next, false) => {
+                    wdl[*next] == Some(Wdl::Loss) && dtz[*next] == -plies
+                }
+                _ => false,
+            });
+            if next_ply {
+                dtz[*key] = plies + 1;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        plies += 1;
+    }
+
+    for key in valid {
+        let decided = wdl[*key] != Some(Wdl::Draw);
+        if decided && dtz[*key] == 0 {
+            bail!("No DTZ for position {}", key);
+        }
+        if dtz[*key].abs() > 100 {
+            bail!("Position {} can't be won within the 50 move rule", key);
+        }
+    }
+    Ok(dtz)
+}
+
+/// The values of one side to move and file of a table, as they are written.
+struct Compressed {
+    flags: u8,
+    /// The value of every position, if they are all the same.
+    single: Option<u8>,
+    min_len: usize,
+    /// The first symbol of every code length, from `min_len` up.
+    lowest: Vec<u16>,
+    btree: Vec<[u8; 3]>,
+    block_lengths: Vec<u16>,
+    blocks: Vec<u8>,
+    /// The block and offset in it of the middle of every span.
+    sparse: Vec<(u32, u16)>,
+}
+
+impl Compressed {
+    fn single(flags: u8, value: u8) -> Compressed {
+        Compressed {
+            flags: flags | FLAG_SINGLE_VALUE,
+            single: Some(value),
+            min_len: 0,
+            lowest: Vec::new(),
+            btree: Vec::new(),
+            block_lengths: Vec::new(),
+            blocks: Vec::new(),
+            sparse: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.blocks.len() + self.btree.len() * 3 + self.sparse.len() * 6
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Symbol {
+    Value(u16),
+    Pair(usize, usize),
+}
+
+/// The length of the Huffman code of every symbol with a frequency, at most `MAX_CODE_LEN`.
+fn code_lengths(frequencies: &[usize]) -> Vec<usize> {
+    let mut frequencies = frequencies.to_vec();
+    loop {
+        let mut heap = BinaryHeap::new();
+        let mut parent = vec![usize::MAX; frequencies.len()];
+        for (symbol, frequency) in frequencies.iter().enumerate() {
+            if *frequency > 0 {
+                heap.push(Reverse((*frequency, symbol)));
+            }
+        }
+        while heap.len() > 1 {
+            let Reverse((a, left)) = heap.pop().unwrap();
+            let Reverse((b, right)) = heap.pop().unwrap();
+            let node = parent.len();
+            parent.push(usize::MAX);
+            parent[left] = node;
+            parent[right] = node;
+            heap.push(Reverse((a + b, node)));
+        }
+        let lengths: Vec<usize> = (0..frequencies.len())
+            .map(|symbol| {
+                if frequencies[symbol] == 0 {
+                    return 0;
+                }
+                let (mut node, mut len) = (symbol, 0);
+                while parent[node] != usize::MAX {
+                    node = parent[node];
+                    len += 1;
+                }
+                len
+            })
+            .collect();
+        if lengths.iter().all(|len| *len <= MAX_CODE_LEN) {
+            return lengths;
+        }
+        // Flatten the frequencies until the longest code fits.
+        for frequency in frequencies.iter_mut().filter(|f| **f > 0) {
+            *frequency = *frequency / 2 + 1;
+        }
+    }
+}
+
+/// Compress the values: replace the most frequent pairs of symbols by a new symbol while that
+/// pays off, and write the symbols with a canonical Huffman code in blocks.
+fn compress(values: &[Option<u16>], flags: u8) -> Compressed {
+    let first = values.iter().flatten().next().cloned().unwrap_or(0);
+    let mut last = first;
+    let values: Vec<u16> = values
+        .iter()
+        .map(|value| {
+            last = value.unwrap_or(last);
+            last
+        })
+        .collect();
+    if values.iter().all(|value| *value == first) {
+        return Compressed::single(flags, first as u8);
+    }
+
+    let mut distinct = values.clone();
+    distinct.sort();
+    distinct.dedup();
+    let mut symbols: Vec<Symbol> = distinct.iter().map(|v| Symbol::Value(*v)).collect();
+    let mut runs = vec![1; symbols.len()];
+    let mut sequence: Vec<usize> = values
+        .iter()
+        .map(|value| distinct.binary_search(value).unwrap())
+        .collect();
+
+    while symbols.len() - distinct.len() < MAX_PAIRS {
+        let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+        for pair in sequence.windows(2) {
+            if runs[pair[0]] + runs[pair[1]] <= 256 {
+                *counts.entry((pair[0], pair[1])).or_insert(0) += 1;
+            }
+        }
+        let best = counts
+            .into_iter()
+            .max_by_key(|(pair, count)| (*count, Reverse(*pair)));
+        let (left, right) = match best {
+            Some((pair, count)) if count >= 8 => pair,
+            _ => break,
+        };
+        let symbol = symbols.len();
+        symbols.push(Symbol::Pair(left, right));
+        runs.push(runs[left] + runs[right]);
+        let mut replaced = Vec::with_capacity(sequence.len());
+        let mut i = 0;
+        while i < sequence.len() {
+            if i + 1 < sequence.len() && sequence[i] == left && sequence[i + 1] == right {
+                replaced.push(symbol);
+                i += 2;
+            } else {
+                replaced.push(sequence[i]);
+                i += 1;
+            }
+        }
+        sequence = replaced;
+    }
+
+    let mut frequencies = vec![0; symbols.len()];
+    for symbol in &sequence {
+        frequencies[*symbol] += 1;
+    }
+    // A code needs two symbols, give one that is never written a code too.
+    if frequencies.iter().filter(|f| **f > 0).count() < 2 {
+        let unused = frequencies.iter().position(|f| *f == 0).unwrap();
+        frequencies[unused] = 1;
+    }
+    let lengths = code_lengths(&frequencies);
+
+    // Symbols are numbered from the longest codes to the shortest, then those without a code.
+    let mut order: Vec<usize> = (0..symbols.len()).collect();
+    order.sort_by_key(|symbol| (lengths[*symbol] == 0, Reverse(lengths[*symbol]), *symbol));
+    let mut number = vec![0; symbols.len()];
+    for (i, symbol) in order.iter().enumerate() {
+        number[*symbol] = i;
+    }
+    let min_len = lengths.iter().filter(|l| **l > 0).min().cloned().unwrap();
+    let max_len = lengths.iter().cloned().max().unwrap();
+    let mut count = vec![0; max_len + 2];
+    for len in lengths.iter().filter(|l| **l > 0) {
+        count[*len] += 1;
+    }
+    // `lowest` and `base` of each length, counting down from the longest codes.
+    let mut lowest = vec![0; max_len + 2];
+    let mut base = vec![0u64; max_len + 2];
+    for len in (min_len..max_len).rev() {
+        lowest[len] = lowest[len + 1] + count[len + 1];
+        assert_eq!(0, (base[len + 1] + count[len + 1] as u64) % 2);
+        base[len] = (base[len + 1] + count[len + 1] as u64) / 2;
+    }
+    assert_eq!(1 << min_len, base[min_len] + count[min_len] as u64);
+
+    let btree = order
+        .iter()
+        .map(|symbol| match symbols[*symbol] {
+            Symbol::Value(value) => [value as u8, (value >> 8) as u8 | 0xF0, 0xFF],
+            Symbol::Pair(left, right) => {
+                let (left, right) = (number[left], number[right]);
+                [
+                    left as u8,
+                    (left >> 8) as u8 | ((right & 0xF) << 4) as u8,
+                    (right >> 4) as u8,
+                ]
+            }
+        })
+        .collect();
+
+    let block_bits = 8 << BLOCK_SIZE_LOG2;
+    let mut blocks = Vec::new();
+    let mut block_lengths = Vec::new();
+    let mut block_starts = Vec::new();
+    let (mut bits, mut block_values, mut start) = (Vec::new(), 0, 0);
+    let mut flush = |bits: &mut Vec<bool>, block_values: &mut usize, start: &mut usize| {
+        let mut block = vec![0u8; 1 << BLOCK_SIZE_LOG2];
+        for (i, bit) in bits.iter().enumerate() {
+            if *bit {
+                block[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        blocks.extend(block);
+        block_lengths.push((*block_values - 1) as u16);
+        block_starts.push(*start);
+        *start += *block_values;
+        bits.clear();
+        *block_values = 0;
+    };
+    for symbol in &sequence {
+        let len = lengths[*symbol];
+        if bits.len() + len > block_bits || block_values + runs[*symbol] > MAX_BLOCK_VALUES {
+            flush(&mut bits, &mut block_values, &mut start);
+        }
+        let code = base[len] + (number[*symbol] - lowest[len]) as u64;
+        bits.extend((0..len).rev().map(|i| code >> i & 1 == 1));
+        block_values += runs[*symbol];
+    }
+    flush(&mut bits, &mut block_values, &mut start);
+
+    let span = 1 << SPAN_LOG2;
+    let sparse = (0..values.len().div_ceil(span))
+        .map(|k| {
+            let middle = k * span + span / 2;
+            let block = match block_starts.binary_search(&middle) {
+                Ok(block) => block,
+                Err(next) => next - 1,
+            };
+            (block as u32, (middle - block_starts[block]) as u16)
+        })
+        .collect();
+
+    Compressed {
+        flags,
+        single: None,
+        min_len,
+        lowest: (min_len..=max_len).map(|len| lowest[len] as u16).collect(),
+        btree,
+        block_lengths,
+        blocks,
+        sparse,
+    }
+}
+
+/// A table file, with `parts` by file and side to move.
+fn write_table(codes: &[u8], dtz: bool, parts: &[Vec<Compressed>]) -> Vec<u8> {
+    let pawns = codes[0] & 7 == 1;
+    let mut bytes = Vec::new();
+    bytes.extend(if dtz { DTZ_MAGIC } else { WDL_MAGIC });
+    bytes.push(1 | if pawns { 2 } else { 0 });
+    for _ in parts {
+        bytes.push(0);
+        bytes.extend(codes.iter().map(|code| code | code << 4));
+    }
+    let pad = |bytes: &mut Vec<u8>, to: usize| {
+        while !bytes.len().is_multiple_of(to) {
+            bytes.push(0);
+        }
+    };
+    pad(&mut bytes, 2);
+
+    for part in parts.iter().flatten() {
+        bytes.push(part.flags);
+        if let Some(value) = part.single {
+            bytes.push(value);
+            continue;
+        }
+        bytes.push(BLOCK_SIZE_LOG2);
+        bytes.push(SPAN_LOG2);
+        bytes.push(0);
+        bytes.extend(&(part.block_lengths.len() as u32).to_le_bytes());
+        bytes.push((part.min_len + part.lowest.len() - 1) as u8);
+        bytes.push(part.min_len as u8);
+        for lowest in &part.lowest {
+            bytes.extend(&lowest.to_le_bytes());
+        }
+        bytes.extend(&(part.btree.len() as u16).to_le_bytes());
+        for entry in &part.btree {
+            bytes.extend(entry);
+        }
+        pad(&mut bytes, 2);
+    }
+    if dtz {
+        pad(&mut bytes, 2);
+    }
+    for part in parts.iter().flatten() {
+        for (block, offset) in &part.sparse {
+            bytes.extend(&block.to_le_bytes());
+            bytes.extend(&offset.to_le_bytes());
+        }
+    }
+    for part in parts.iter().flatten() {
+        for length in &part.block_lengths {
+            bytes.extend(&length.to_le_bytes());
+        }
+    }
+    for part in parts.iter().flatten() {
+        pad(&mut bytes, 64);
+        bytes.extend(&part.blocks);
+    }
+    pad(&mut bytes, 64);
+    bytes.extend(&[0; 16]);
+    bytes
+}
+
+/// The values of every side to move and file, at the index the table gives the positions.
+fn table_values(
+    name: &str,
+    solution: &Solution,
+    dtz: bool,
+    value: impl Fn(usize) -> Option<u16>,
+) -> Result<Vec<Vec<Vec<Option<u16>>>>> {
+    let files = if solution.codes[0] & 7 == 1 { 4 } else { 1 };
+    let sides = if dtz { 1 } else { 2 };
+    let stub = write_table(
+        &solution.codes,
+        dtz,
+        &(0..files)
+            .map(|_| (0..sides).map(|_| Compressed::single(0, 0)).collect())
+            .collect::<Vec<_>>(),
+    );
+    let table = Table::from_bytes(stub, name, dtz)?;
+
+    let mut values = vec![vec![Vec::new(); 2]; files];
+    for (key, wdl) in solution.wdl.iter().enumerate() {
+        let value = match (wdl, value(key)) {
+            (Some(_), Some(value)) => value,
+            _ => continue,
+        };
+        let (stm, file, idx) = table.index(&position(&solution.codes, key).unwrap(), false)?;
+        let d = table.pairs(stm, file);
+        let size = d.group_idx[d.group_len.iter().take_while(|len| **len != 0).count()] as usize;
+        let values = &mut values[file][stm];
+        values.resize(size, None);
+        if idx as usize >= size {
+            bail!("Index {} of {} is past the end of {}", idx, key, name);
+        }
+        match values[idx as usize] {
+            Some(other) if other != value => {
+                bail!("Positions with index {} in {} differ", idx, name)
+            }
+            _ => values[idx as usize] = Some(value),
+        }
+    }
+    Ok(values)
+}
+
+fn write_fixtures(directory: &Path) -> Result<()> {
+    fs::create_dir_all(directory)?;
+    let mut solved = HashMap::new();
+    for (name, pieces) in &TABLES {
+        let codes: Vec<u8> = pieces.iter().map(|piece| piece_code(*piece)).collect();
+        let solution = solve(name, &codes, &solved)?;
+
+        let wdl = table_values(name, &solution, false, |key| {
+            solution.wdl[key].map(|wdl| (wdl as i32 + 2) as u16)
+        })?;
+        let parts: Vec<Vec<Compressed>> = wdl
+            .iter()
+            .map(|sides| sides.iter().map(|values| compress(values, 0)).collect())
+            .collect();
+        fs::write(
+            directory.join(format!("{}.rtbw", name)),
+            write_table(&codes, false, &parts),
+        )?;
+
+        // DTZ tables only have one side to move, the one that compresses best.
+        let dtz = table_values(name, &solution, true, |key| match solution.dtz[key] {
+            0 => None,
+            dtz => Some(dtz.unsigned_abs() as u16 - 1),
+        })?;
+        let flags = FLAG_WIN_PLIES | FLAG_LOSS_PLIES;
+        let sides: Vec<Vec<Compressed>> = (0..2)
+            .map(|stm| {
+                dtz.iter()
+                    .map(|sides| compress(&sides[stm], flags | stm as u8))
+                    .collect()
+            })
+            .collect();
+        let size = |side: &Vec<Compressed>| side.iter().map(Compressed::len).sum::<usize>();
+        let side = if size(&sides[1]) < size(&sides[0]) {
+            1
+        } else {
+            0
+        };
+        let parts: Vec<Vec<Compressed>> = sides
+            .into_iter()
+            .nth(side)
+            .unwrap()
+            .into_iter()
+            .map(|part| vec![part])
+            .collect();
+        fs::write(
+            directory.join(format!("{}.rtbz", name)),
+            write_table(&codes, true, &parts),
+        )?;
+
+        let longest = solution.dtz.iter().max().cloned().unwrap_or(0);
+        println!("{}: longest win {} plies", name, longest);
+        solved.insert(name.to_string(), solution);
+    }
+    check_fixtures(directory, &solved)
+}
+
+/// Probe every position, and the same position with the colors swapped, in the written tables.
+fn check_fixtures(directory: &Path, solved: &HashMap<String, Solution>) -> Result<()> {
+    let mut tablebases = Tablebases::open(&directory.to_string_lossy())?;
+    for (name, solution) in solved {
+        for (key, wdl) in solution.wdl.iter().enumerate() {
+            let wdl = match wdl {
+                Some(wdl) => *wdl,
+                None => continue,
+            };
+            let position = position(&solution.codes, key).unwrap();
+            let mirrored = Position {
+                pieces: (position.pieces.iter())
+                    .map(|(code, square)| (code ^ 8, square ^ 56))
+                    .collect(),
+                white_to_move: !position.white_to_move,
+            };
+            for state in &[position.to_state()?, mirrored.to_state()?] {
+                let probed = (tablebases.probe_wdl(state)?, tablebases.probe_dtz(state)?);
+                if probed != (wdl, solution.dtz[key]) {
+                    bail!(
+                        "{} position {} is {:?} with DTZ {}, probed {:?}",
+                        name,
+                        key,
+                        wdl,
+                        solution.dtz[key],
+                        probed
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn generate_fixtures() {
+    write_fixtures(&Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/syzygy")).unwrap();
+}
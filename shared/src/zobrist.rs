@@ -0,0 +1,87 @@
+//! Zobrist keys for `BoardState`.
+//!
+//! The keys are generated from a fixed seed so hashes are stable between runs, which lets us
+//! persist them (e.g. in tuning data) without worrying about the table changing.
+
+use crate::{BoardState, CurrentPlayer, Piece, PieceKind};
+
+const PIECE_COUNT: usize = Piece::BlackRookMoved as usize + 1;
+
+lazy_static! {
+    static ref PIECE_KEYS: Vec<[u64; 64]> = {
+        let mut seed = 0x5eed_c4e5_5eed_c4e5;
+        let mut keys = Vec::with_capacity(PIECE_COUNT);
+        for _ in 0..PIECE_COUNT {
+            let mut squares = [0u64; 64];
+            for key in squares.iter_mut() {
+                *key = splitmix64(&mut seed);
+            }
+            keys.push(squares);
+        }
+        keys
+    };
+    static ref BLACK_TO_MOVE_KEY: u64 = splitmix64(&mut 0xb1ac_c0de);
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The key of a single piece on a single square. `PawnMoved`, `KingMoved` and `RookMoved` hash
+/// differently from their unmoved counterparts, as they have different castling/double push rights.
+#[inline]
+pub fn piece_key(piece: Piece, x: u8, y: u8) -> u64 {
+    PIECE_KEYS[piece as usize][y as usize * 8 + x as usize]
+}
+
+/// Full position key, including the side to move.
+pub fn hash(state: &BoardState) -> u64 {
+    let mut key = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = state.get_piece(x, y);
+            if piece != Piece::None {
+                key ^= piece_key(piece, x, y);
+            }
+        }
+    }
+    if state.current_player == CurrentPlayer::Black {
+        key ^= *BLACK_TO_MOVE_KEY;
+    }
+    key
+}
+
+/// Key of only the pawns on the board. Moved and unmoved pawns hash the same, so positions with
+/// identical pawn structures share a key regardless of how they came to be.
+pub fn pawn_hash(state: &BoardState) -> u64 {
+    let mut key = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = state.get_piece(x, y);
+            if piece.kind() == Some(PieceKind::Pawn) {
+                let piece = match piece.owner() {
+                    Some(CurrentPlayer::White) => Piece::WhitePawn,
+                    _ => Piece::BlackPawn,
+                };
+                key ^= piece_key(piece, x, y);
+            }
+        }
+    }
+    key
+}
+
+#[test]
+fn test_pawn_hash_ignores_pieces() {
+    let mut state = BoardState::init();
+    let key = pawn_hash(&state);
+    state.set_piece((1, 0), Piece::None);
+    assert_eq!(key, pawn_hash(&state));
+    assert_ne!(hash(&BoardState::init()), hash(&state));
+
+    state.set_piece((0, 1), Piece::None);
+    assert_ne!(key, pawn_hash(&state));
+}
@@ -0,0 +1,292 @@
+//! Texel tuning of the static evaluation weights.
+//!
+//! Replays every game in `games.csv`, samples the quiet positions with the result of the game as
+//! label, and fits the weights so `sigmoid(evaluation)` predicts that result as well as possible.
+//!
+//! Usage: `tuner [games.csv] [weights.txt] [iterations]`
+
+extern crate csv;
+#[macro_use]
+extern crate failure;
+extern crate shared;
+
+use shared::evaluation::{StaticEval, Term, Trace, Weights, TERM_COUNT};
+use shared::{BoardState, Result};
+
+const COLUMN_WINNER: usize = 6;
+const COLUMN_MOVES: usize = 12;
+
+/// The first moves of a game are mostly opening theory, and say little about the evaluation.
+const SKIP_PLIES: usize = 8;
+
+const LEARNING_RATE: f64 = 1.0;
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+
+struct Sample {
+    trace: Trace,
+    /// 1.0 if white won, 0.5 for a draw and 0.0 if black won.
+    result: f64,
+}
+
+fn main() {
+    let input_file = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("games.csv"));
+    let output_file = std::env::args()
+        .nth(2)
+        .unwrap_or_else(|| String::from("weights.txt"));
+    let iterations = std::env::args()
+        .nth(3)
+        .map(|i| i.parse().expect("Iterations is not a number"))
+        .unwrap_or(1000);
+
+    let samples = load_samples(&input_file).expect("Could not load games");
+    println!("Loaded {} positions", samples.len());
+    if samples.is_empty() {
+        return;
+    }
+
+    let weights = Weights::default();
+    let k = find_scaling(&samples, &weights);
+    println!("Scaling constant K = {:.3}", k);
+
+    let weights = tune(&samples, &weights, k, iterations);
+    weights.save(&output_file).expect("Could not write weights");
+    println!("Written weights to {:?}", output_file);
+}
+
+fn load_samples(input_file: &str) -> Result<Vec<Sample>> {
+    let mut parser = csv::Reader::from_path(input_file)?;
+    let mut eval = StaticEval::default();
+    let mut samples = Vec::new();
+    let mut skipped = 0;
+
+    for record in parser.records() {
+        let record = record?;
+        let game_id = record.get(0).unwrap_or_default();
+        let result = match record.get(COLUMN_WINNER) {
+            Some("white") => 1.0,
+            Some("black") => 0.0,
+            Some("draw") => 0.5,
+            winner => {
+                println!("Game {:?}: unknown winner {:?}", game_id, winner);
+                skipped += 1;
+                continue;
+            }
+        };
+        let moves = record
+            .get(COLUMN_MOVES)
+            .ok_or_else(|| format_err!("Game {:?} has no moves", game_id))?;
+        if let Err(e) = replay(moves, result, &mut eval, &mut samples) {
+            println!("Game {:?}: {}", game_id, e);
+            skipped += 1;
+        }
+    }
+
+    if skipped > 0 {
+        println!("Skipped {} games", skipped);
+    }
+    Ok(samples)
+}
+
+/// Positions where the last move was a capture, check or promotion, or where the player to move
+/// can capture, are likely to be in the middle of a tactical sequence, which a static evaluation
+/// can't be expected to predict.
+fn is_quiet(state: &BoardState, m: &str) -> bool {
+    !m.contains(&['x', '+', '#', '='][..])
+        && !state.legal_moves().into_iter().any(|reply| state.is_capture(reply))
+}
+
+fn replay(
+    moves: &str,
+    result: f64,
+    eval: &mut StaticEval,
+    samples: &mut Vec<Sample>,
+) -> Result<()> {
+    let mut state = BoardState::init();
+    for (ply, m) in moves.split(' ').enumerate() {
+        if let Err(e) = state.make_move(m) {
+            bail!("Could not apply move {} ({:?}): {}", ply + 1, m, e);
+        }
+        if ply + 1 >= SKIP_PLIES && is_quiet(&state, m) {
+            samples.push(Sample {
+                trace: eval.trace(&state),
+                result,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+fn score(trace: &Trace, weights: &[f64]) -> f64 {
+    trace
+        .counts
+        .iter()
+        .zip(weights)
+        .map(|(count, weight)| f64::from(*count) * weight)
+        .sum()
+}
+
+fn mean_error(samples: &[Sample], weights: &[f64], k: f64) -> f64 {
+    let total: f64 = samples
+        .iter()
+        .map(|sample| (sample.result - sigmoid(score(&sample.trace, weights), k)).powi(2))
+        .sum();
+    total / samples.len() as f64
+}
+
+fn to_floats(weights: &Weights) -> Vec<f64> {
+    Term::all()
+        .map(|term| f64::from(weights.get(term)))
+        .collect()
+}
+
+/// Find the `K` that best maps the current weights to the game results, so the tuning changes the
+/// relative values of the weights instead of scaling all of them.
+fn find_scaling(samples: &[Sample], weights: &Weights) -> f64 {
+    let weights = to_floats(weights);
+    let mut best = (f64::MAX, 1.0);
+    let mut step = 0.1;
+    let mut start = 0.1;
+    for _ in 0..3 {
+        for i in 0..=20 {
+            let k = start + step * f64::from(i);
+            let error = mean_error(samples, &weights, k);
+            if error < best.0 {
+                best = (error, k);
+            }
+        }
+        start = (best.1 - step).max(step / 10.0);
+        step /= 10.0;
+    }
+    best.1
+}
+
+/// The gradient of `mean_error` for every weight.
+fn gradient(samples: &[Sample], weights: &[f64], k: f64) -> [f64; TERM_COUNT] {
+    let scale = k * 10f64.ln() / 400.0;
+    let mut gradient = [0f64; TERM_COUNT];
+    for sample in samples {
+        let s = sigmoid(score(&sample.trace, weights), k);
+        let factor = 2.0 * (s - sample.result) * s * (1.0 - s) * scale;
+        for (g, count) in gradient.iter_mut().zip(sample.trace.counts.iter()) {
+            *g += factor * f64::from(*count);
+        }
+    }
+    for g in gradient.iter_mut() {
+        *g /= samples.len() as f64;
+    }
+    gradient
+}
+
+/// Minimize the mean squared error between the game results and the sigmoid of the evaluation
+/// with Adam.
+fn tune(samples: &[Sample], weights: &Weights, k: f64, iterations: usize) -> Weights {
+    let mut weights = to_floats(weights);
+    let mut m = [0f64; TERM_COUNT];
+    let mut v = [0f64; TERM_COUNT];
+
+    for iteration in 1..=iterations {
+        let gradient = gradient(samples, &weights, k);
+        for i in 0..TERM_COUNT {
+            let g = gradient[i];
+            m[i] = BETA1 * m[i] + (1.0 - BETA1) * g;
+            v[i] = BETA2 * v[i] + (1.0 - BETA2) * g * g;
+            let m_hat = m[i] / (1.0 - BETA1.powi(iteration as i32));
+            let v_hat = v[i] / (1.0 - BETA2.powi(iteration as i32));
+            weights[i] -= LEARNING_RATE * m_hat / (v_hat.sqrt() + 1e-8);
+        }
+
+        if iteration % 50 == 0 || iteration == iterations {
+            println!(
+                "Iteration {}: error {:.6}",
+                iteration,
+                mean_error(samples, &weights, k)
+            );
+        }
+    }
+
+    let mut result = Weights::default();
+    for (term, weight) in Term::all().zip(weights) {
+        result.set(term, weight.round() as i32);
+    }
+    result
+}
+
+#[cfg(test)]
+fn samples() -> Vec<Sample> {
+    let sample = |counts: &[(Term, i32)], result| {
+        let mut trace = Trace::default();
+        for (term, count) in counts {
+            trace.counts[*term as usize] = *count;
+        }
+        Sample { trace, result }
+    };
+    vec![
+        sample(&[(Term::PawnValue, 1)], 1.0),
+        sample(&[(Term::PawnValue, 2), (Term::DoubledPawn, 1)], 0.5),
+        sample(&[(Term::KnightValue, -1), (Term::PawnValue, 1)], 0.0),
+        sample(&[(Term::QueenValue, 1), (Term::RookValue, -1)], 1.0),
+        sample(&[], 0.5),
+    ]
+}
+
+#[test]
+fn test_gradient() {
+    let samples = samples();
+    let weights = to_floats(&Weights::default());
+    let k = 1.2;
+    let gradient = gradient(&samples, &weights, k);
+    for term in Term::all() {
+        let i = term as usize;
+        let step = 0.01;
+        let (mut above, mut below) = (weights.clone(), weights.clone());
+        above[i] += step;
+        below[i] -= step;
+        let expected =
+            (mean_error(&samples, &above, k) - mean_error(&samples, &below, k)) / (2.0 * step);
+        assert!(
+            (gradient[i] - expected).abs() < 1e-9,
+            "{:?}: {} != {}",
+            term,
+            gradient[i],
+            expected
+        );
+    }
+    assert_eq!(0.0, gradient[Term::PawnStorm as usize]);
+}
+
+#[test]
+fn test_tune() {
+    let samples = samples();
+    let weights = Weights::default();
+    let k = find_scaling(&samples, &weights);
+    let before = mean_error(&samples, &to_floats(&weights), k);
+    let tuned = tune(&samples, &weights, k, 200);
+    assert!(mean_error(&samples, &to_floats(&tuned), k) < before);
+    // A pawn up was a win once and a draw once, but two pawns up with a doubled pawn a draw.
+    assert!(tuned.get(Term::DoubledPawn) < weights.get(Term::DoubledPawn));
+    // Terms that are in none of the samples keep their weight.
+    assert_eq!(weights.get(Term::PawnStorm), tuned.get(Term::PawnStorm));
+}
+
+#[test]
+fn test_replay() {
+    let mut eval = StaticEval::default();
+    let mut samples = Vec::new();
+    let moves = "e4 e5 Nf3 Nc6 Bc4 Bc5 c3 Nf6 d4 exd4 cxd4 Bb4+";
+    replay(moves, 0.5, &mut eval, &mut samples).unwrap();
+    // From the 8th ply on there is always a capture or check, Bxf7+ after Nf6 and exd4 after d4.
+    assert!(samples.is_empty());
+    let moves = "Nf3 Nf6 g3 g6 Bg2 Bg7 O-O O-O d3 d6 e4 Nxe4";
+    replay(moves, 0.5, &mut eval, &mut samples).unwrap();
+    // The positions after O-O, d3 and d6. After e4 black can take the pawn.
+    assert_eq!(3, samples.len());
+    assert!(samples.iter().all(|sample| sample.result == 0.5));
+    assert!(replay("e4 e5 Ke3", 1.0, &mut eval, &mut samples).is_err());
+}
@@ -0,0 +1,113 @@
+//! Animated GIFs of whole games, with a frame for every position.
+
+use color_quant::NeuQuant;
+use gif::{Encoder, Frame, Repeat, SetParameter};
+use image::{Rgb, RgbImage};
+use shared::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    /// How long every position is shown, in milliseconds.
+    pub delay: u32,
+    /// How long the final position is shown before the animation starts over, in milliseconds.
+    pub final_delay: u32,
+    /// Whether the animation starts with the initial position, before the first move.
+    pub start_frame: bool,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> AnimationOptions {
+        AnimationOptions {
+            delay: 1000,
+            final_delay: 3000,
+            start_frame: true,
+        }
+    }
+}
+
+/// GIF delays are in hundredths of a second.
+fn centiseconds(milliseconds: u32) -> u16 {
+    (milliseconds / 10).min(u32::from(u16::MAX)) as u16
+}
+
+/// A palette of at most 256 colours for all frames, and the index of every colour in them. The
+/// sprites alone have about as many colours as a GIF palette can hold, so if there are more they
+/// are reduced with NeuQuant.
+fn palette(frames: &[RgbImage]) -> (Vec<u8>, HashMap<Rgb<u8>, u8>) {
+    let mut colours = Vec::new();
+    let mut indices = HashMap::new();
+    for frame in frames {
+        for pixel in frame.pixels() {
+            if !indices.contains_key(pixel) {
+                indices.insert(*pixel, 0);
+                colours.push(*pixel);
+            }
+        }
+    }
+    if colours.len() <= 256 {
+        let mut palette = Vec::with_capacity(colours.len() * 3);
+        for (i, colour) in colours.iter().enumerate() {
+            palette.extend_from_slice(&colour.data);
+            indices.insert(*colour, i as u8);
+        }
+        return (palette, indices);
+    }
+    let rgba: Vec<u8> = colours
+        .iter()
+        .flat_map(|c| vec![c.data[0], c.data[1], c.data[2], 255])
+        .collect();
+    let quant = NeuQuant::new(1, 256, &rgba);
+    for (colour, pixel) in colours.iter().zip(rgba.chunks(4)) {
+        indices.insert(*colour, quant.index_of(pixel) as u8);
+    }
+    (quant.color_map_rgb(), indices)
+}
+
+/// Write the positions of a game as a looping GIF. `frames` starts with the initial position,
+/// which is left out unless `options.start_frame` is set.
+pub fn write_gif(frames: &[RgbImage], options: &AnimationOptions, out: &Path) -> Result<()> {
+    let frames = if options.start_frame || frames.len() == 1 {
+        frames
+    } else {
+        &frames[1..]
+    };
+    let (width, height) = match frames.first() {
+        Some(frame) => frame.dimensions(),
+        None => bail!("A game needs at least one frame"),
+    };
+    let (palette, indices) = palette(frames);
+    let mut encoder = Encoder::new(File::create(out)?, width as u16, height as u16, &palette)?;
+    encoder.set(Repeat::Infinite)?;
+    for (i, image) in frames.iter().enumerate() {
+        let pixels: Vec<u8> = image.pixels().map(|p| indices[p]).collect();
+        let mut frame = Frame::from_indexed_pixels(width as u16, height as u16, &pixels, None);
+        frame.delay = if i + 1 == frames.len() {
+            centiseconds(options.final_delay)
+        } else {
+            centiseconds(options.delay)
+        };
+        encoder.write_frame(&frame)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_palette() {
+    let mut image = RgbImage::new(20, 20);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        *pixel = Rgb {
+            data: [x as u8, y as u8, 0],
+        };
+    }
+    // 400 colours don't fit, so they are quantized.
+    let (colours, indices) = palette(&[image]);
+    assert_eq!(256 * 3, colours.len());
+    assert_eq!(400, indices.len());
+
+    let (colours, indices) = palette(&[RgbImage::new(2, 2)]);
+    assert_eq!(vec![0, 0, 0], colours);
+    assert_eq!(Some(&0), indices.get(&Rgb { data: [0, 0, 0] }));
+}
@@ -0,0 +1,244 @@
+//! What is drawn on top of a position: the last move, a king in check, and arrows and circles.
+//!
+//! Arrows and circles use the colours of the `[%cal]` and `[%csl]` commands in PGN comments, e.g.
+//! `[%cal Ge2e4,Rd7d5]` for a green arrow from e2 to e4 and a red one from d7 to d5, and
+//! `[%csl Yd4]` for a yellow circle around d4.
+
+use shared::{BoardState, Move, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colour {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl Colour {
+    pub fn rgb(self) -> [u8; 3] {
+        match self {
+            Colour::Green => [21, 120, 27],
+            Colour::Red => [136, 32, 32],
+            Colour::Yellow => [230, 143, 0],
+            Colour::Blue => [0, 48, 136],
+        }
+    }
+
+    pub fn hex(self) -> String {
+        let [r, g, b] = self.rgb();
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// Squares are `(x, y)` in `BoardState` coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arrow {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    pub colour: Colour,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Circle {
+    pub square: (u8, u8),
+    pub colour: Colour,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Annotations {
+    /// The move that led to the position, with its squares highlighted.
+    pub last_move: Option<Move>,
+    /// The square of the king of the player to move, if it is in check.
+    pub check: Option<(u8, u8)>,
+    pub arrows: Vec<Arrow>,
+    pub circles: Vec<Circle>,
+}
+
+impl Annotations {
+    /// The last move and check of a position, given the position before it.
+    pub fn new(before: Option<&BoardState>, state: &BoardState) -> Annotations {
+        let player = state.current_player;
+        Annotations {
+            last_move: before.and_then(|before| Move::between(before, state).ok()),
+            check: if state.is_in_check(player) {
+                state.find_king(player)
+            } else {
+                None
+            },
+            ..Annotations::default()
+        }
+    }
+
+    /// Add the arrows and circles of the `[%cal]` and `[%csl]` commands in a PGN comment.
+    pub fn add_comment(&mut self, comment: &str) -> Result<()> {
+        for command in comment.split('[').skip(1) {
+            let command = match command.find(']') {
+                Some(end) => &command[..end],
+                None => bail!("Unterminated command in {:?}", comment),
+            };
+            let mut parts = command.trim().splitn(2, ' ');
+            let (name, marks) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+            // Other commands, like `[%clk 0:03:00]`, are not drawn.
+            if name != "%cal" && name != "%csl" {
+                continue;
+            }
+            for mark in marks.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+                let colour = match mark.as_bytes()[0] {
+                    b'G' => Colour::Green,
+                    b'R' => Colour::Red,
+                    b'Y' => Colour::Yellow,
+                    b'B' => Colour::Blue,
+                    _ => bail!("Unknown colour in {:?}", mark),
+                };
+                match (name, mark.len()) {
+                    ("%cal", 5) => self.arrows.push(Arrow {
+                        from: square(&mark[1..3])?,
+                        to: square(&mark[3..5])?,
+                        colour,
+                    }),
+                    ("%csl", 3) => self.circles.push(Circle {
+                        square: square(&mark[1..3])?,
+                        colour,
+                    }),
+                    _ => bail!("Invalid mark {:?}", mark),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `(x, y)` of a square like `e4`.
+pub fn square(name: &str) -> Result<(u8, u8)> {
+    match name.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Ok((7 - (file - b'a'), rank - b'1')),
+        _ => bail!("Invalid square {:?}", name),
+    }
+}
+
+/// The outline of an arrow between the centres of two squares on the screen, in pixels. The
+/// head takes up the last part of the arrow, and the shaft is narrower.
+pub struct ArrowShape {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub shaft_width: f32,
+    pub head_width: f32,
+    pub head_length: f32,
+}
+
+impl ArrowShape {
+    pub fn new(start: (f32, f32), end: (f32, f32), square_size: u32) -> ArrowShape {
+        let square = square_size as f32;
+        ArrowShape {
+            start,
+            end,
+            shaft_width: square * 0.16,
+            head_width: square * 0.45,
+            head_length: square * 0.4,
+        }
+    }
+
+    /// The length of the arrow and the unit vector along it.
+    fn direction(&self) -> (f32, (f32, f32)) {
+        let (dx, dy) = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let length = (dx * dx + dy * dy).sqrt().max(1.0);
+        (length, (dx / length, dy / length))
+    }
+
+    /// Whether a point on the screen is inside the arrow.
+    pub fn contains(&self, (x, y): (f32, f32)) -> bool {
+        let (length, (ux, uy)) = self.direction();
+        let (px, py) = (x - self.start.0, y - self.start.1);
+        // The distance along the arrow, and to either side of it.
+        let along = px * ux + py * uy;
+        let across = (px * uy - py * ux).abs();
+        let head = (length - self.head_length).max(0.0);
+        if along < 0.0 || along > length {
+            false
+        } else if along < head {
+            across <= self.shaft_width / 2.0
+        } else {
+            across <= self.head_width / 2.0 * (length - along) / self.head_length
+        }
+    }
+
+    /// The corners of the outline, going around from the start of the shaft.
+    pub fn polygon(&self) -> Vec<(f32, f32)> {
+        let (length, (ux, uy)) = self.direction();
+        let head = (length - self.head_length).max(0.0);
+        // A point at a distance along the arrow, and to its side.
+        let point = |along: f32, side: f32| {
+            (
+                self.start.0 + ux * along - uy * side,
+                self.start.1 + uy * along + ux * side,
+            )
+        };
+        vec![
+            point(0.0, self.shaft_width / 2.0),
+            point(head, self.shaft_width / 2.0),
+            point(head, self.head_width / 2.0),
+            point(length, 0.0),
+            point(head, -self.head_width / 2.0),
+            point(head, -self.shaft_width / 2.0),
+            point(0.0, -self.shaft_width / 2.0),
+        ]
+    }
+}
+
+#[test]
+fn test_comment_marks() {
+    let mut annotations = Annotations::default();
+    annotations
+        .add_comment("Best is [%cal Ge2e4,Rd7d5] and [%csl Yd4][%clk 0:03:00]")
+        .unwrap();
+    assert_eq!(
+        vec![
+            Arrow {
+                from: (3, 1),
+                to: (3, 3),
+                colour: Colour::Green,
+            },
+            Arrow {
+                from: (4, 6),
+                to: (4, 4),
+                colour: Colour::Red,
+            },
+        ],
+        annotations.arrows
+    );
+    assert_eq!(
+        vec![Circle {
+            square: (4, 3),
+            colour: Colour::Yellow,
+        }],
+        annotations.circles
+    );
+    assert!(Annotations::default().add_comment("[%cal Xe2e4]").is_err());
+    assert!(Annotations::default().add_comment("[%csl Ge9]").is_err());
+}
+
+#[test]
+fn test_last_move_and_check() {
+    let mut state = BoardState::init();
+    for m in &["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6"] {
+        state.make_move(m).unwrap();
+    }
+    let before = state.clone();
+    state.make_move("Qxf7#").unwrap();
+    let annotations = Annotations::new(Some(&before), &state);
+    assert_eq!(square("h5").unwrap(), annotations.last_move.unwrap().from);
+    assert_eq!(square("f7").unwrap(), annotations.last_move.unwrap().to);
+    assert_eq!(Some(square("e8").unwrap()), annotations.check);
+    assert_eq!(None, Annotations::new(None, &before).check);
+}
+
+#[test]
+fn test_arrow_shape() {
+    let arrow = ArrowShape::new((25.0, 175.0), (25.0, 75.0), 50);
+    assert!(arrow.contains((25.0, 150.0)));
+    assert!(!arrow.contains((35.0, 150.0)));
+    // The head is wider than the shaft.
+    assert!(arrow.contains((32.0, 90.0)));
+    assert!(!arrow.contains((25.0, 70.0)));
+    assert_eq!(7, arrow.polygon().len());
+}
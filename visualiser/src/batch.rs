@@ -0,0 +1,198 @@
+//! Drawing many games at once, on a number of threads.
+//!
+//! A game whose output is already in `board_states` is skipped, so a run that was stopped goes on
+//! where it left off, and `board_states` has to be deleted to draw every game again. Every file is
+//! written under a temporary name and renamed when it is complete, and the positions of a game in
+//! order, so once the file of the last position is there the game is done.
+
+use crate::annotations::Annotations;
+use crate::render::Renderer;
+use crate::report::{ErrorKind, GameError, Report};
+use crate::{animation, annotate, html, replay, svg, Format, Options};
+use image::png::PNGEncoder;
+use image::{ColorType, RgbImage};
+use shared::BoardState;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// A game to draw, from a row of the input.
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub id: String,
+    pub moves: String,
+    /// The other columns, by name.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Game {
+    pub fn moves(&self) -> Vec<&str> {
+        self.moves.split(' ').collect()
+    }
+
+    /// The file that is written last for a game.
+    fn last_output(&self, format: Format) -> PathBuf {
+        let plies = self.moves().len();
+        PathBuf::from(match format {
+            Format::Png => format!("board_states/{}/{}.png", self.id, plies),
+            Format::Svg => format!("board_states/{}/{}.svg", self.id, plies),
+            Format::Gif => format!("board_states/{}.gif", self.id),
+            Format::Html => format!("board_states/{}.html", self.id),
+        })
+    }
+}
+
+enum Outcome {
+    Generated,
+    AlreadyDone,
+    Failed(GameError),
+}
+
+/// The path a file is written to before it is complete.
+fn partial(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> shared::Result<()> {
+    fs::write(partial(path), contents)?;
+    fs::rename(partial(path), path)?;
+    Ok(())
+}
+
+/// Draw a game, with `images` the images of earlier games to draw over.
+fn render_game(
+    game: &Game,
+    options: &Options,
+    renderer: &Renderer,
+    images: &mut Vec<RgbImage>,
+) -> Result<(), GameError> {
+    let states = replay(&game.id, &game.moves)?;
+    let annotations = annotate(&states, options);
+    write_game(game, options, renderer, images, &states, &annotations)
+        .map_err(|e| GameError::new(ErrorKind::RenderFailure, &game.id, e.to_string()))
+}
+
+fn write_game(
+    game: &Game,
+    options: &Options,
+    renderer: &Renderer,
+    images: &mut Vec<RgbImage>,
+    states: &[BoardState],
+    annotations: &[Annotations],
+) -> shared::Result<()> {
+    let directory = format!("board_states/{}", game.id);
+    match options.format {
+        Format::Png => {
+            fs::create_dir_all(&directory)?;
+            if images.is_empty() {
+                images.push(RgbImage::new(0, 0));
+            }
+            let image = &mut images[0];
+            let mut png = Vec::new();
+            for (ply, state) in states.iter().enumerate() {
+                renderer.render(state, &annotations[ply], image);
+                png.clear();
+                let (width, height) = image.dimensions();
+                PNGEncoder::new(&mut png).encode(image, width, height, ColorType::RGB(8))?;
+                write_file(Path::new(&format!("{}/{}.png", directory, ply)), &png)?;
+            }
+        }
+        Format::Svg => {
+            fs::create_dir_all(&directory)?;
+            for (ply, state) in states.iter().enumerate() {
+                let svg = svg::render(state, renderer, &annotations[ply]);
+                write_file(
+                    Path::new(&format!("{}/{}.svg", directory, ply)),
+                    svg.as_bytes(),
+                )?;
+            }
+        }
+        Format::Gif => {
+            while images.len() < states.len() {
+                images.push(RgbImage::new(0, 0));
+            }
+            for (ply, state) in states.iter().enumerate() {
+                renderer.render(state, &annotations[ply], &mut images[ply]);
+            }
+            let path = game.last_output(Format::Gif);
+            let frames = &images[..states.len()];
+            animation::write_gif(frames, &options.animation, &partial(&path))?;
+            fs::rename(partial(&path), path)?;
+        }
+        Format::Html => {
+            let page = html::render(
+                &game.id,
+                &game.metadata,
+                &game.moves(),
+                states,
+                annotations,
+                renderer,
+            );
+            write_file(&game.last_output(Format::Html), page.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw the games on `options.jobs` threads, printing the progress as they finish and adding them
+/// to the report.
+pub fn render_all(games: &[Game], options: &Options, renderer: &Renderer, report: &mut Report) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..options.jobs {
+            let (next, sender) = (&next, sender.clone());
+            scope.spawn(move || {
+                let mut images = Vec::new();
+                while let Some(game) = games.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let outcome = if game.last_output(options.format).exists() {
+                        Outcome::AlreadyDone
+                    } else {
+                        match render_game(game, options, renderer, &mut images) {
+                            Ok(()) => Outcome::Generated,
+                            Err(e) => Outcome::Failed(e),
+                        }
+                    };
+                    if sender.send((game, outcome)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (done, (game, outcome)) in receiver.iter().enumerate() {
+            let progress = format!("[{}/{}]", done + 1, games.len());
+            match outcome {
+                Outcome::Generated => {
+                    report.generated += 1;
+                    println!("{} Done generating game {:?}", progress, game.id);
+                }
+                Outcome::AlreadyDone => {
+                    report.already_done += 1;
+                    println!("{} Game {:?} was already done", progress, game.id);
+                }
+                Outcome::Failed(e) => {
+                    println!("{} Skipping game {:?}: {}", progress, game.id, e);
+                    report.add(e);
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn test_write_file() {
+    let directory = std::env::temp_dir().join(format!("visualiser-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("0.svg");
+    assert_eq!(directory.join("0.svg.partial"), partial(&path));
+    write_file(&path, b"<svg/>").unwrap();
+    assert_eq!("<svg/>", fs::read_to_string(&path).unwrap());
+    assert!(!partial(&path).exists());
+    fs::remove_dir_all(&directory).unwrap();
+}
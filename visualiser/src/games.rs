@@ -0,0 +1,287 @@
+//! Finding the games to draw in a CSV file, by the names of its columns.
+//!
+//! The columns default to the names in the Lichess games dataset, and `--column FIELD=NAME` maps a
+//! field to another column. Only the id and the moves have to be there, the other columns only
+//! if a filter uses them.
+
+use csv::StringRecord;
+use shared::Result;
+use std::collections::HashMap;
+
+/// The fields of a game, and the default names of their columns.
+pub const FIELDS: [(&str, &str); 12] = [
+    ("id", "id"),
+    ("moves", "moves"),
+    ("white", "white_id"),
+    ("black", "black_id"),
+    ("white_rating", "white_rating"),
+    ("black_rating", "black_rating"),
+    ("result", "winner"),
+    ("status", "victory_status"),
+    ("turns", "turns"),
+    ("time_control", "increment_code"),
+    ("eco", "opening_eco"),
+    ("opening", "opening_name"),
+];
+
+/// Where the fields are in the rows of a file.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    columns: HashMap<&'static str, usize>,
+}
+
+impl Schema {
+    /// Find the columns of the fields in the header, using `names` instead of the default names
+    /// where it has them.
+    pub fn new(header: &StringRecord, names: &HashMap<String, String>) -> Result<Schema> {
+        let mut columns = HashMap::new();
+        for (field, default) in FIELDS.iter() {
+            let name = names.get(*field).map_or(*default, String::as_str);
+            if let Some(column) = header.iter().position(|h| h == name) {
+                columns.insert(*field, column);
+            }
+        }
+        let schema = Schema { columns };
+        schema.require(&["id", "moves"])?;
+        Ok(schema)
+    }
+
+    /// Fail if any of the fields has no column.
+    pub fn require(&self, fields: &[&str]) -> Result<()> {
+        for field in fields {
+            if !self.columns.contains_key(field) {
+                bail!("There is no column for the {} of a game", field);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn column(&self, field: &str) -> Option<usize> {
+        self.columns.get(field).cloned()
+    }
+
+    /// The value of a field in a row, or an empty string if the row is too short.
+    pub fn get<'r>(&self, record: &'r StringRecord, field: &str) -> &'r str {
+        self.column(field)
+            .and_then(|column| record.get(column))
+            .unwrap_or("")
+    }
+}
+
+/// Parse `FIELD=NAME` for `--column`.
+pub fn parse_column(value: &str) -> Result<(String, String)> {
+    let mut parts = value.splitn(2, '=');
+    let (field, name) = match (parts.next(), parts.next()) {
+        (Some(field), Some(name)) if !name.is_empty() => (field, name),
+        _ => bail!("Expected FIELD=NAME for --column, got {:?}", value),
+    };
+    if !FIELDS.iter().any(|(f, _)| *f == field) {
+        let fields: Vec<&str> = FIELDS.iter().map(|(f, _)| *f).collect();
+        bail!(
+            "Unknown field {:?}, expected one of {}",
+            field,
+            fields.join(", ")
+        );
+    }
+    Ok((field.to_owned(), name.to_owned()))
+}
+
+/// Which games to draw. Every filter that is set has to match.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub game_id: Option<String>,
+    /// Either player.
+    pub player: Option<String>,
+    /// The ratings of both players.
+    pub min_rating: Option<u32>,
+    pub max_rating: Option<u32>,
+    /// The start of the ECO code, like `B` or `C20`, or a part of the name of the opening.
+    pub opening: Option<String>,
+    /// `white`, `black` or `draw`.
+    pub result: Option<String>,
+    /// How the game ended, like `mate` or `resign`.
+    pub status: Option<String>,
+    pub min_turns: Option<u32>,
+    pub max_turns: Option<u32>,
+    /// Like `15+2`.
+    pub time_control: Option<String>,
+}
+
+impl Filter {
+    /// The fields the filters need.
+    pub fn fields(&self) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.player.is_some() {
+            fields.extend(&["white", "black"]);
+        }
+        if self.min_rating.is_some() || self.max_rating.is_some() {
+            fields.extend(&["white_rating", "black_rating"]);
+        }
+        if self.opening.is_some() {
+            fields.extend(&["eco", "opening"]);
+        }
+        if self.result.is_some() {
+            fields.push("result");
+        }
+        if self.status.is_some() {
+            fields.push("status");
+        }
+        if self.min_turns.is_some() || self.max_turns.is_some() {
+            fields.push("turns");
+        }
+        if self.time_control.is_some() {
+            fields.push("time_control");
+        }
+        fields
+    }
+
+    pub fn matches(&self, schema: &Schema, record: &StringRecord) -> Result<bool> {
+        let get = |field| schema.get(record, field);
+        let same = |wanted: &Option<String>, field| {
+            wanted
+                .as_ref()
+                .is_none_or(|wanted| wanted.eq_ignore_ascii_case(get(field)))
+        };
+        let number = |field| -> Result<u32> {
+            get(field).parse().map_err(|_| {
+                format_err!("Expected a number for the {}, got {:?}", field, get(field))
+            })
+        };
+        let within = |value: u32, min: Option<u32>, max: Option<u32>| {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        };
+
+        if let Some(game_id) = &self.game_id {
+            if game_id != get("id") {
+                return Ok(false);
+            }
+        }
+        if let Some(player) = &self.player {
+            if !player.eq_ignore_ascii_case(get("white"))
+                && !player.eq_ignore_ascii_case(get("black"))
+            {
+                return Ok(false);
+            }
+        }
+        if self.min_rating.is_some() || self.max_rating.is_some() {
+            for field in &["white_rating", "black_rating"] {
+                if !within(number(field)?, self.min_rating, self.max_rating) {
+                    return Ok(false);
+                }
+            }
+        }
+        if let Some(opening) = &self.opening {
+            let opening = opening.to_lowercase();
+            if !get("eco").to_lowercase().starts_with(&opening)
+                && !get("opening").to_lowercase().contains(&opening)
+            {
+                return Ok(false);
+            }
+        }
+        if !same(&self.result, "result")
+            || !same(&self.status, "status")
+            || !same(&self.time_control, "time_control")
+        {
+            return Ok(false);
+        }
+        if self.min_turns.is_some() || self.max_turns.is_some() {
+            return Ok(within(number("turns")?, self.min_turns, self.max_turns));
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+fn lichess_header() -> StringRecord {
+    StringRecord::from(vec![
+        "id",
+        "rated",
+        "created_at",
+        "last_move_at",
+        "turns",
+        "victory_status",
+        "winner",
+        "increment_code",
+        "white_id",
+        "white_rating",
+        "black_id",
+        "black_rating",
+        "moves",
+        "opening_eco",
+        "opening_name",
+        "opening_ply",
+    ])
+}
+
+#[test]
+fn test_schema() {
+    let schema = Schema::new(&lichess_header(), &HashMap::new()).unwrap();
+    assert_eq!(Some(0), schema.column("id"));
+    assert_eq!(Some(12), schema.column("moves"));
+    assert_eq!(Some(7), schema.column("time_control"));
+
+    let header = StringRecord::from(vec!["Moves", "Site", "Result"]);
+    assert!(Schema::new(&header, &HashMap::new()).is_err());
+    let names: HashMap<String, String> = vec![
+        parse_column("id=Site").unwrap(),
+        parse_column("moves=Moves").unwrap(),
+    ]
+    .into_iter()
+    .collect();
+    let schema = Schema::new(&header, &names).unwrap();
+    assert_eq!(Some(1), schema.column("id"));
+    assert!(schema.require(&["result"]).is_err());
+    assert!(parse_column("colour=Site").is_err());
+    assert!(parse_column("id").is_err());
+}
+
+#[test]
+fn test_filter() {
+    let schema = Schema::new(&lichess_header(), &HashMap::new()).unwrap();
+    let record = StringRecord::from(vec![
+        "abc",
+        "TRUE",
+        "0",
+        "0",
+        "13",
+        "mate",
+        "white",
+        "15+2",
+        "alice",
+        "1500",
+        "bob",
+        "1400",
+        "e4 e5 Qh5 Nc6 Bc4 Nf6 Qxf7#",
+        "C20",
+        "King's Pawn Game: Wayward Queen Attack",
+        "3",
+    ]);
+    let matches = |filter: Filter| filter.matches(&schema, &record).unwrap();
+    assert!(matches(Filter::default()));
+    assert!(matches(Filter {
+        player: Some("Bob".to_owned()),
+        min_rating: Some(1400),
+        opening: Some("wayward".to_owned()),
+        result: Some("white".to_owned()),
+        status: Some("mate".to_owned()),
+        max_turns: Some(13),
+        time_control: Some("15+2".to_owned()),
+        ..Filter::default()
+    }));
+    assert!(matches(Filter {
+        opening: Some("C".to_owned()),
+        ..Filter::default()
+    }));
+    assert!(!matches(Filter {
+        max_rating: Some(1450),
+        ..Filter::default()
+    }));
+    assert!(!matches(Filter {
+        min_turns: Some(20),
+        ..Filter::default()
+    }));
+    assert!(!matches(Filter {
+        result: Some("draw".to_owned()),
+        ..Filter::default()
+    }));
+}
@@ -0,0 +1,210 @@
+//! A game as a single HTML page, to replay it in a browser without any other files.
+//!
+//! Every position is drawn as SVG and kept in a script, which shows one of them at a time. The
+//! pieces are defined once in a hidden SVG that the boards refer to. The page has buttons and the arrow keys to step through the game, a field to jump to a ply, and the
+//! moves with the one that led to the current position highlighted. Clicking a move jumps to it.
+
+use crate::annotations::Annotations;
+use crate::render::Renderer;
+use crate::svg;
+use shared::BoardState;
+use std::fmt::Write;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+main { display: flex; flex-wrap: wrap; gap: 2em; align-items: flex-start; }
+#controls { margin-top: 1em; }
+#controls input { width: 4em; }
+#moves { max-width: 30em; line-height: 1.8; }
+#moves span { cursor: pointer; padding: 0.1em 0.3em; border-radius: 0.2em; }
+#moves span.current { background: #e0c040; }
+table { border-collapse: collapse; margin-top: 1em; }
+th, td { text-align: left; padding: 0.2em 1em 0.2em 0; }
+";
+
+const SCRIPT: &str = "
+let ply = 0;
+const board = document.getElementById('board');
+const input = document.getElementById('ply');
+const moves = document.querySelectorAll('#moves span');
+function show(to) {
+  ply = Math.max(0, Math.min(boards.length - 1, to));
+  board.innerHTML = boards[ply];
+  input.value = ply;
+  moves.forEach(move => move.classList.toggle('current', Number(move.dataset.ply) === ply));
+}
+document.getElementById('first').onclick = () => show(0);
+document.getElementById('previous').onclick = () => show(ply - 1);
+document.getElementById('next').onclick = () => show(ply + 1);
+document.getElementById('last').onclick = () => show(boards.length - 1);
+input.onchange = () => show(Number(input.value));
+moves.forEach(move => move.onclick = () => show(Number(move.dataset.ply)));
+document.addEventListener('keydown', event => {
+  if (event.target === input) return;
+  const keys = { ArrowLeft: ply - 1, ArrowRight: ply + 1, Home: 0, End: boards.length - 1 };
+  if (event.key in keys) {
+    show(keys[event.key]);
+    event.preventDefault();
+  }
+});
+show(0);
+";
+
+/// Text with the characters that are special in HTML escaped.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A JavaScript string literal that is safe to put in a `<script>`.
+fn js_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            // Keeps `</script>` from ending the script.
+            '<' => literal.push_str("\\x3c"),
+            _ => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// The page of a game. `metadata` are the other columns of the game in `games.csv`, by name, and
+/// `moves` the moves that led to every position after the first.
+pub fn render(
+    game_id: &str,
+    metadata: &[(String, String)],
+    moves: &[&str],
+    states: &[BoardState],
+    annotations: &[Annotations],
+    renderer: &Renderer,
+) -> String {
+    let mut html = String::new();
+    // Writing to a `String` can't fail.
+    let mut w = |line: String| writeln!(html, "{}", line).unwrap();
+
+    w("<!DOCTYPE html>".to_owned());
+    w("<html>".to_owned());
+    w("<head>".to_owned());
+    w(r#"<meta charset="utf-8">"#.to_owned());
+    w(format!("<title>Game {}</title>", escape(game_id)));
+    w(format!("<style>{}</style>", STYLE));
+    w("</head>".to_owned());
+    w("<body>".to_owned());
+    w(format!("<h1>Game {}</h1>", escape(game_id)));
+    w("<main>".to_owned());
+
+    w("<section>".to_owned());
+    // The boards share the pieces defined here.
+    w(svg::defs_document(renderer));
+    w(r#"<div id="board"></div>"#.to_owned());
+    w(r#"<div id="controls">"#.to_owned());
+    for (id, label) in &[("first", "|&lt;"), ("previous", "&lt;")] {
+        w(format!(r#"<button id="{}">{}</button>"#, id, label));
+    }
+    w(format!(
+        r#"<label>Ply <input id="ply" type="number" min="0" max="{}" value="0"></label>"#,
+        states.len() - 1
+    ));
+    for (id, label) in &[("next", "&gt;"), ("last", "&gt;|")] {
+        w(format!(r#"<button id="{}">{}</button>"#, id, label));
+    }
+    w("</div>".to_owned());
+    w("</section>".to_owned());
+
+    w("<section>".to_owned());
+    let mut list = String::new();
+    for (index, m) in moves.iter().enumerate() {
+        if index % 2 == 0 {
+            write!(list, "{}. ", index / 2 + 1).unwrap();
+        }
+        write!(
+            list,
+            r#"<span data-ply="{}">{}</span> "#,
+            index + 1,
+            escape(m)
+        )
+        .unwrap();
+    }
+    w(format!(r#"<div id="moves">{}</div>"#, list.trim_end()));
+    w("<table>".to_owned());
+    for (name, value) in metadata {
+        w(format!(
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape(name),
+            escape(value)
+        ));
+    }
+    w("</table>".to_owned());
+    w("</section>".to_owned());
+    w("</main>".to_owned());
+
+    let boards: Vec<String> = states
+        .iter()
+        .zip(annotations)
+        .map(|(state, annotations)| {
+            js_string(&svg::render_without_defs(state, renderer, annotations))
+        })
+        .collect();
+    w(format!(
+        "<script>\nconst boards = [\n{}\n];{}</script>",
+        boards.join(",\n"),
+        SCRIPT
+    ));
+    w("</body>".to_owned());
+    w("</html>".to_owned());
+    html
+}
+
+#[test]
+fn test_render_game() {
+    let moves = ["e4", "e5"];
+    let mut states = vec![BoardState::init()];
+    for m in &moves {
+        let mut state = states.last().unwrap().clone();
+        state.make_move(m).unwrap();
+        states.push(state);
+    }
+    let annotations = vec![Annotations::default(); states.len()];
+    let metadata = vec![("opening_name".to_owned(), "King's Pawn <Game>".to_owned())];
+    let html = render(
+        "g1",
+        &metadata,
+        &moves,
+        &states,
+        &annotations,
+        &Renderer::new(Default::default()).unwrap(),
+    );
+    assert!(html.contains(r#"<span data-ply="2">e5</span>"#));
+    assert!(html.contains("King&#39;s Pawn &lt;Game&gt;"));
+    assert_eq!(3, html.matches("\"\\x3csvg ").count());
+    // Nothing in the boards can end the script early.
+    assert_eq!(1, html.matches("</script>").count());
+    // Only the definitions of the pieces are outside of the script, once for all boards.
+    assert_eq!(1, html.matches("</svg>").count());
+    assert_eq!(12, html.matches("<image ").count());
+}
+
+#[test]
+fn test_js_string() {
+    assert_eq!(
+        r#""a\"b\\c\n\x3c/script>""#,
+        js_string("a\"b\\c\n</script>")
+    );
+}
@@ -1,176 +1,455 @@
+//! Usage: `visualiser [game_id] [--input PATH] [--column FIELD=NAME]... [--player NAME]
+//! [--min-rating N] [--max-rating N] [--opening ECO|NAME] [--result white|black|draw]
+//! [--status STATUS] [--min-turns N] [--max-turns N] [--time-control CONTROL] [--limit N]
+//! [--jobs N] [--report PATH.json|PATH.csv] [--format png|svg|gif|html] [--delay MS] [--final-delay MS] [--start-frame true|false] [--coordinates true|false] [--flip true|false|side-to-move]
+//! [--square-size PX] [--theme NAME|PATH] [--last-move true|false] [--check true|false]
+//! [--best-move PLAYOUTS] [--eval-file PATH] [--marks PLY:COMMENT]...
+//! [--terminal unicode|ascii]`
+//!
+//! Replays the games in `--input`, `games.csv` by default, and draws every position. The columns
+//! are found by their names in the header, which `--column` changes for a field, e.g.
+//! `--column moves=Moves`; see `games.rs` for the fields. Only the games that match all filters
+//! are drawn: the game with `game_id`, games of a player, games where both ratings are within
+//! the range, games with an ECO code starting with or an opening name containing `--opening`, and
+//! games with that result, victory status, number of turns or time control. `--limit` stops after
+//! that many games.
+//!
+//! With `--format png` (the default) the positions are written to `board_states/<game_id>/<ply>.png`.
+//! `--format svg` writes them as `<ply>.svg`. The files and ranks are labelled unless
+//! `--coordinates false`. White is at the bottom, unless `--flip true` puts black there or
+//! `--flip side-to-move` the player to move. `--theme` picks the colours and sprites, either one
+//! of the built-in themes `grey` (the default), `brown` and `blue`, or a TOML file as described in
+//! `theme.rs`.
+//! With `--format gif` every game becomes an animation in `board_states/<game_id>.gif`, showing
+//! every position for `--delay` milliseconds and the final position for `--final-delay`.
+//! `--format html` writes a page per game to `board_states/<game_id>.html`, to step through the
+//! game in a browser, with its moves and the other columns of `games.csv`.
+//!
+//! The games are drawn on `--jobs` threads, one per core by default. Games that are already in
+//! `board_states` from an earlier run are skipped, so delete it to draw them again. A game that
+//! can't be replayed or drawn is skipped, and the run ends with the number of games that failed
+//! by the kind of error. `--report` writes those errors, with the ply and move that failed, to a
+//! JSON or CSV file.
+//!
+//! The squares of the last move and a king in check are highlighted, unless `--last-move false` or
+//! `--check false`. `--best-move` draws a blue arrow for the move a search with that many playouts
+//! finds, with the weights of the `tuner` in `--eval-file` if it is given. `--marks` draws the
+//! arrows and circles of a PGN comment like `[%cal Ge2e4][%csl Rd4]` on the position after that
+//! many plies.
+//!
+//! `--terminal` writes no files, but steps through the games in the terminal instead, drawing the
+//! positions with Unicode glyphs on coloured squares or as ASCII letters.
+
+extern crate color_quant;
 extern crate csv;
 #[macro_use]
 extern crate failure;
+extern crate gif;
 extern crate image;
-extern crate shared;
+extern crate random;
+extern crate serde;
 #[macro_use]
-extern crate lazy_static;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate shared;
+extern crate toml;
 
-mod algebraic_notation;
+mod animation;
+mod annotations;
+mod batch;
+mod games;
+mod html;
+mod render;
+mod report;
+mod svg;
+mod terminal;
+mod theme;
 
-use image::{ImageBuffer, ImageDecoder, Rgb, Rgba};
-use shared::enum_primitive::FromPrimitive;
-use shared::{BoardState, Piece, Result};
+use crate::animation::AnimationOptions;
+use crate::annotations::{Annotations, Arrow, Colour};
+use crate::batch::Game;
+use crate::games::{Filter, Schema};
+use crate::render::{Orientation, RenderOptions, Renderer};
+use crate::report::{ErrorKind, GameError, Report};
+use crate::theme::Theme;
+use shared::display::Style;
+use shared::evaluation::{StaticEval, Weights};
+use shared::mcts::{Mcts, MctsOptions};
+use shared::{BoardState, Move, Result, UnparseableMove};
 use std::collections::HashMap;
-use std::fs::{self, File};
-
-fn main() {
-    let input_file = "games.csv";
-    let requested_id = std::env::args().nth(1);
+use std::fs;
 
-    let _ = fs::remove_dir_all("board_states");
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Svg,
+    Gif,
+    Html,
+}
 
-    let mut parser = csv::Reader::from_path(input_file).expect("Could not open games.csv");
-    for record in parser.records() {
-        let record = record.expect("Could not parser row");
+#[derive(Debug, Clone)]
+struct Options {
+    input: String,
+    /// The names of the columns of fields, where they differ from the defaults.
+    columns: HashMap<String, String>,
+    filter: Filter,
+    /// The number of games to draw at most.
+    limit: Option<usize>,
+    /// The number of threads drawing games.
+    jobs: usize,
+    /// Where to write the report of the games that failed.
+    report: Option<String>,
+    format: Format,
+    animation: AnimationOptions,
+    render: RenderOptions,
+    last_move: bool,
+    check: bool,
+    /// The number of playouts of the search for the best move, or 0 to not search.
+    best_move: usize,
+    /// The weights of the static evaluation for the search.
+    weights: Weights,
+    /// PGN comments with arrows and circles, by the ply of the position they are drawn on.
+    marks: Vec<(usize, String)>,
+    /// Step through the games in the terminal, instead of writing files.
+    terminal: Option<Style>,
+}
 
-        let game_id = record.get(0).unwrap();
-        if let Some(requested_id) = requested_id.as_ref() {
-            if requested_id != game_id {
-                continue;
+fn parse_options(args: &[String]) -> Result<Options> {
+    let mut options = Options {
+        input: "games.csv".to_owned(),
+        columns: HashMap::new(),
+        filter: Filter::default(),
+        limit: None,
+        jobs: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        report: None,
+        format: Format::Png,
+        animation: AnimationOptions::default(),
+        render: RenderOptions {
+            coordinates: true,
+            ..RenderOptions::default()
+        },
+        last_move: true,
+        check: true,
+        best_move: 0,
+        weights: Weights::default(),
+        marks: Vec::new(),
+        terminal: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") && options.filter.game_id.is_none() {
+            options.filter.game_id = Some(arg.clone());
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format_err!("Missing value for {:?}", arg))?;
+        let filter = &mut options.filter;
+        match arg.as_str() {
+            "--input" => options.input = value.clone(),
+            "--column" => {
+                let (field, name) = games::parse_column(value)?;
+                options.columns.insert(field, name);
+            }
+            "--player" => filter.player = Some(value.clone()),
+            "--min-rating" => filter.min_rating = Some(value.parse()?),
+            "--max-rating" => filter.max_rating = Some(value.parse()?),
+            "--opening" => filter.opening = Some(value.clone()),
+            "--result" => {
+                if !["white", "black", "draw"].contains(&value.as_str()) {
+                    bail!(
+                        "Expected white, black or draw for --result, got {:?}",
+                        value
+                    );
+                }
+                filter.result = Some(value.clone());
+            }
+            "--status" => filter.status = Some(value.clone()),
+            "--min-turns" => filter.min_turns = Some(value.parse()?),
+            "--max-turns" => filter.max_turns = Some(value.parse()?),
+            "--time-control" => filter.time_control = Some(value.clone()),
+            "--limit" => options.limit = Some(value.parse()?),
+            "--jobs" => {
+                options.jobs = value.parse()?;
+                if options.jobs == 0 {
+                    bail!("--jobs needs at least one thread");
+                }
+            }
+            "--report" => {
+                if !value.ends_with(".json") && !value.ends_with(".csv") {
+                    bail!(
+                        "Expected a .json or .csv file for --report, got {:?}",
+                        value
+                    );
+                }
+                options.report = Some(value.clone());
             }
+            "--format" => {
+                options.format = match value.as_str() {
+                    "png" => Format::Png,
+                    "svg" => Format::Svg,
+                    "gif" => Format::Gif,
+                    "html" => Format::Html,
+                    _ => bail!("Unknown format {:?}, expected png, svg, gif or html", value),
+                }
+            }
+            "--delay" => options.animation.delay = value.parse()?,
+            "--final-delay" => options.animation.final_delay = value.parse()?,
+            "--start-frame" => options.animation.start_frame = value.parse()?,
+            "--coordinates" => options.render.coordinates = value.parse()?,
+            "--flip" => {
+                options.render.orientation = match value.as_str() {
+                    "false" => Orientation::White,
+                    "true" => Orientation::Black,
+                    "side-to-move" => Orientation::SideToMove,
+                    _ => bail!(
+                        "Expected true, false or side-to-move for --flip, got {:?}",
+                        value
+                    ),
+                }
+            }
+            "--square-size" => {
+                options.render.square_size = value.parse()?;
+                if options.render.square_size < 8 {
+                    bail!("The squares should be at least 8 pixels");
+                }
+            }
+            "--theme" => options.render.theme = Theme::find(value)?,
+            "--last-move" => options.last_move = value.parse()?,
+            "--check" => options.check = value.parse()?,
+            "--best-move" => options.best_move = value.parse()?,
+            "--eval-file" => options.weights = Weights::load(value)?,
+            "--marks" => {
+                let mut parts = value.splitn(2, ':');
+                let ply = parts.next().unwrap().parse()?;
+                let comment = parts.next().ok_or_else(|| {
+                    format_err!("Expected PLY:COMMENT for --marks, got {:?}", value)
+                })?;
+                // Check the comment now, rather than after rendering half the games.
+                Annotations::default().add_comment(comment)?;
+                options.marks.push((ply, comment.to_owned()));
+            }
+            "--terminal" => {
+                options.terminal = match value.as_str() {
+                    "unicode" => Some(Style::Unicode),
+                    "ascii" => Some(Style::Ascii),
+                    _ => bail!("Expected unicode or ascii for --terminal, got {:?}", value),
+                }
+            }
+            _ => bail!("Unknown argument {:?}", arg),
         }
-        let moves = record.get(12).unwrap();
-        println!("Generating game {:?}", game_id);
+    }
+    Ok(options)
+}
 
-        fs::create_dir_all(&format!("board_states/{}", game_id))
-            .expect("Could not create directory");
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_options(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage: visualiser [game_id] [--input PATH] [--column FIELD=NAME]... [--player NAME] [--min-rating N] [--max-rating N] [--opening ECO|NAME] [--result white|black|draw] [--status STATUS] [--min-turns N] [--max-turns N] [--time-control CONTROL] [--limit N] [--jobs N] [--report PATH.json|PATH.csv] [--format png|svg|gif|html] [--delay MS] [--final-delay MS] [--start-frame true|false] [--coordinates true|false] [--flip true|false|side-to-move] [--square-size PX] [--theme NAME|PATH] [--last-move true|false] [--check true|false] [--best-move PLAYOUTS] [--eval-file PATH] [--marks PLY:COMMENT]... [--terminal unicode|ascii]");
+            std::process::exit(2);
+        }
+    };
+    let mut parser = match csv::Reader::from_path(&options.input) {
+        Ok(parser) => parser,
+        Err(e) => {
+            println!("Could not open {:?}: {}", options.input, e);
+            std::process::exit(1);
+        }
+    };
+    let headers = match parser.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => {
+            println!("Could not read the header of {:?}: {}", options.input, e);
+            std::process::exit(1);
+        }
+    };
+    let schema = Schema::new(&headers, &options.columns)
+        .and_then(|schema| schema.require(&options.filter.fields()).map(|()| schema));
+    let schema = match schema {
+        Ok(schema) => schema,
+        Err(e) => {
+            println!("{} in {:?}", e, options.input);
+            std::process::exit(1);
+        }
+    };
 
-        let mut boardstate = BoardState::init();
+    let renderer = match Renderer::new(options.render.clone()) {
+        Ok(renderer) => renderer,
+        Err(e) => {
+            println!("Could not load the theme: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-        generate_image(&boardstate, &format!("board_states/{}/{}.png", game_id, 0))
-            .expect("Cannot generate image");
+    let mut games = Vec::new();
+    let mut report = Report::default();
+    for record in parser.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                println!("Skipping row: {}", e);
+                report.add(GameError::new(ErrorKind::InvalidRow, "", e.to_string()));
+                continue;
+            }
+        };
 
-        for (index, m) in moves.split(' ').enumerate() {
-            // println!("{:?}: {:?} {}", index + 1, boardstate.current_player, m);
-            let _notation = algebraic_notation::Notation::parse(m).expect("Could not parse");
-            /*notation
-                .apply(&mut boardstate)
-                .expect("Could not apply notation");
-            /*if let Err(e) = boardstate.make_move(m) {
-                println!("{:?}", e);
-                break;
-            }*/
-            generate_image(
-            &boardstate,
-            &format!("board_states/{}/{}.png", game_id, index + 1),
-            )
-            .expect("Cannot generate image");*/
+        let game_id = schema.get(&record, "id");
+        match options.filter.matches(&schema, &record) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                let error = GameError::new(ErrorKind::InvalidRow, game_id, e.to_string());
+                println!("Skipping game {:?}: {}", game_id, error);
+                report.add(error);
+                continue;
+            }
+        }
+        if options.limit == Some(games.len()) {
+            break;
         }
-        println!("Done generating game {:?}", game_id);
+        let metadata = headers
+            .iter()
+            .zip(record.iter())
+            .enumerate()
+            .filter(|(column, _)| Some(*column) != schema.column("moves"))
+            .map(|(_, (name, value))| (name.to_owned(), value.to_owned()))
+            .collect();
+        games.push(Game {
+            id: game_id.to_owned(),
+            moves: schema.get(&record, "moves").to_owned(),
+            metadata,
+        });
     }
-}
 
-const PIXEL_DARK_GRAY: Rgb<u8> = Rgb { data: [50, 50, 50] };
-const PIXEL_LIGHT_GRAY: Rgb<u8> = Rgb {
-    data: [200, 200, 200],
-};
-
-lazy_static! {
-    static ref BOARD_IMAGE: ImageBuffer<Rgb<u8>, Vec<u8>> = {
-        let container = vec![0; 3 * 400 * 400];
-        let mut image = ImageBuffer::<Rgb<u8>, _>::from_raw(400, 400, container)
-            .expect("Board ImageBuffer not big enough");
-        for x in 0..8 {
-            for y in 0..8 {
-                for w in 0..50 {
-                    for h in 0..50 {
-                        let color = if (x + y) % 2 == 0 {
-                            PIXEL_LIGHT_GRAY
-                        } else {
-                            PIXEL_DARK_GRAY
-                        };
-                        let x = x * 50 + w;
-                        let y = y * 50 + h;
-                        image.put_pixel(x, y, color);
-                    }
+    match options.terminal {
+        Some(style) => step(&games, &options, style),
+        None => {
+            if let Err(e) = fs::create_dir_all("board_states") {
+                println!("Could not create board_states: {}", e);
+                std::process::exit(1);
+            }
+            report.games = games.len() + report.errors.len();
+            batch::render_all(&games, &options, &renderer, &mut report);
+            println!("{}", report.summary());
+            if let Some(path) = &options.report {
+                match report.write(path) {
+                    Ok(()) => println!("Wrote the report to {:?}", path),
+                    Err(e) => println!("Could not write the report to {:?}: {}", path, e),
                 }
             }
         }
-        image
-    };
+    }
 }
 
-fn generate_image(state: &BoardState, out: &str) -> Result<()> {
-    let mut board = BOARD_IMAGE.clone();
-    for x in 0..8 {
-        for y in 0..8 {
-            let piece = state.get_piece(x, y);
-            if piece != Piece::None {
-                let image = get_image(piece);
-                for w in 0..50 {
-                    for h in 0..50 {
-                        let pixel = *image.get_pixel(w, h);
-                        if pixel.data[3] > 150 {
-                            board.put_pixel(
-                                u32::from(x) * 50 + w,
-                                u32::from(y) * 50 + h,
-                                Rgb {
-                                    data: [pixel.data[0], pixel.data[1], pixel.data[2]],
-                                },
-                            );
-                        }
-                    }
-                }
+/// Step through the games in the terminal, until the user quits.
+fn step(games: &[Game], options: &Options, style: Style) {
+    for game in games {
+        let states = match replay(&game.id, &game.moves) {
+            Ok(states) => states,
+            Err(e) => {
+                println!("Skipping game {:?}: {}", game.id, e);
+                continue;
+            }
+        };
+        let annotations = annotate(&states, options);
+        let (moves, render) = (game.moves(), &options.render);
+        match terminal::step(&game.id, &moves, &states, &annotations, render, style) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                println!("Could not step through game {:?}: {}", game.id, e);
+                break;
             }
         }
     }
-
-    board.save(out)?;
-    Ok(())
 }
 
-lazy_static! {
-    static ref PIECE_SPRITES: HashMap<Piece, ImageBuffer<Rgba<u8>, Vec<u8>>> = {
-        let mut map = HashMap::new();
-        for piece in Piece::WhiteKing as u8..=Piece::BlackRookMoved as u8 {
-            let url = match Piece::from_u8(piece).unwrap() {
-                Piece::WhiteKing | Piece::WhiteKingMoved => "visualiser/sprites/whiteKing.png",
-                Piece::WhiteRook | Piece::WhiteRookMoved => "visualiser/sprites/whiteRook.png",
-                Piece::WhiteBishop => "visualiser/sprites/whiteBishop.png",
-                Piece::WhiteKnight => "visualiser/sprites/whiteKnight.png",
-                Piece::WhiteQueen => "visualiser/sprites/whiteQueen.png",
-                Piece::WhitePawn | Piece::WhitePawnMoved => "visualiser/sprites/whitePawn.png",
-
-                Piece::BlackKing | Piece::BlackKingMoved => "visualiser/sprites/blackKing.png",
-                Piece::BlackRook | Piece::BlackRookMoved => "visualiser/sprites/blackRook.png",
-                Piece::BlackBishop => "visualiser/sprites/blackBishop.png",
-                Piece::BlackKnight => "visualiser/sprites/blackKnight.png",
-                Piece::BlackQueen => "visualiser/sprites/blackQueen.png",
-                Piece::BlackPawn | Piece::BlackPawnMoved => "visualiser/sprites/blackPawn.png",
-                Piece::None => unreachable!(),
-            };
-            let mut image = image::png::PNGDecoder::new(File::open(url).unwrap());
-            assert_eq!(image::RGBA(8), image.colortype().unwrap());
-            let image = image.read_image().unwrap();
-            let image = match image {
-                image::DecodingResult::U8(b) => b,
-                _ => unimplemented!(),
+/// What to draw on every position of a game.
+fn annotate(states: &[BoardState], options: &Options) -> Vec<Annotations> {
+    let mut source = random::default().seed([0x5eed, 0]);
+    let mut eval = StaticEval::new(options.weights.clone());
+    let mut result = Vec::with_capacity(states.len());
+    for (ply, state) in states.iter().enumerate() {
+        let before = if ply > 0 && options.last_move {
+            Some(&states[ply - 1])
+        } else {
+            None
+        };
+        let mut annotations = Annotations::new(before, state);
+        if !options.check {
+            annotations.check = None;
+        }
+        if options.best_move > 0 && !state.legal_moves().is_empty() {
+            let search = MctsOptions {
+                dirichlet_alpha: 0.0,
+                ..MctsOptions::default()
             };
+            let mut mcts = Mcts::new(state.clone(), search);
+            let searched = mcts.search(&mut eval, options.best_move, &mut source);
+            if let (Ok(()), Some(m)) = (searched, mcts.select_move(0.0, &mut source)) {
+                annotations.arrows.push(Arrow {
+                    from: m.from,
+                    to: m.to,
+                    colour: Colour::Blue,
+                });
+            }
+        }
+        for (_, comment) in options.marks.iter().filter(|(p, _)| *p == ply) {
+            // The comments were checked when parsing the options.
+            annotations.add_comment(comment).unwrap();
+        }
+        result.push(annotations);
+    }
+    result
+}
 
-            let buffer = vec![0u8; 50 * 50 * 4];
-            let mut buffer = ImageBuffer::from_raw(50, 50, buffer).unwrap();
-
-            for x in 0..50 {
-                for y in 0..50 {
-                    let offset = x * 4 + y * 50 * 4;
-                    let rgba = Rgba {
-                        data: [
-                            image[offset],
-                            image[offset + 1],
-                            image[offset + 2],
-                            image[offset + 3],
-                        ],
-                    };
-                    buffer.put_pixel(x as u32, y as u32, rgba);
-                }
+/// Every position of the game, starting with the initial position. A move that can't be played
+/// ends the game with an error.
+fn replay(game_id: &str, moves: &str) -> std::result::Result<Vec<BoardState>, GameError> {
+    let mut state = BoardState::init();
+    let mut states = vec![state.clone()];
+    for (index, m) in moves.split(' ').enumerate() {
+        let ply = index + 1;
+        let error = |kind, message| Err(GameError::at_move(kind, game_id, ply, m, message));
+        let before = state.clone();
+        let message = match state.make_move(m) {
+            Ok(()) => match Move::between(&before, &state) {
+                Ok(played) if before.legal_moves().contains(&played) => None,
+                Ok(played) => Some(format!("{} is not a legal move", played)),
+                Err(e) => Some(e.to_string()),
+            },
+            Err(e) if e.downcast_ref::<UnparseableMove>().is_some() => {
+                return error(ErrorKind::UnparseableMove, e.to_string());
             }
-            map.insert(Piece::from_u8(piece).unwrap(), buffer);
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(message) = message {
+            return error(ErrorKind::IllegalMove, message);
         }
-        map
-    };
+        states.push(state.clone());
+    }
+    Ok(states)
 }
 
-fn get_image(piece: Piece) -> &'static ImageBuffer<Rgba<u8>, Vec<u8>> {
-    PIECE_SPRITES.get(&piece).unwrap()
+#[test]
+fn test_replay_errors() {
+    let states = replay("a", "e4 d5 exd5 c5 dxc6 Nf6 cxb7 O-O-O").unwrap_err();
+    assert_eq!(ErrorKind::IllegalMove, states.kind);
+    assert_eq!(Some(8), states.ply);
+    assert_eq!(
+        8,
+        replay("a", "e4 d5 exd5 c5 dxc6 Nf6 cxb7 e6").unwrap().len() - 1
+    );
+
+    let error = replay("b", "e4 e5 Zz9").unwrap_err();
+    assert_eq!(ErrorKind::UnparseableMove, error.kind);
+    assert_eq!(
+        (Some(3), Some("Zz9")),
+        (error.ply, error.move_text.as_deref())
+    );
+    assert_eq!(ErrorKind::IllegalMove, replay("c", "e5").unwrap_err().kind);
+    assert_eq!(ErrorKind::IllegalMove, replay("d", "Ke2").unwrap_err().kind);
 }
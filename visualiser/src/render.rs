@@ -0,0 +1,312 @@
+//! Drawing a `BoardState` as a PNG image, and the options every output format shares.
+
+use crate::annotations::{Annotations, ArrowShape};
+use crate::svg;
+use crate::theme::{Hex, Theme};
+use image::imageops::{self, FilterType};
+use image::{ImageBuffer, Rgb, RgbImage, RgbaImage};
+use shared::{BoardState, CurrentPlayer, PieceKind, Result};
+use std::collections::HashMap;
+
+/// How opaque highlights, arrows and circles are, out of 255.
+const HIGHLIGHT_ALPHA: u32 = 128;
+const MARK_ALPHA: u32 = 200;
+
+/// Which side of the board is at the bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    White,
+    Black,
+    /// The player to move in the position that is drawn.
+    SideToMove,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// The size of a square in pixels. The sprites are scaled to fit.
+    pub square_size: u32,
+    /// Whether to label the files and ranks in a border around the board.
+    pub coordinates: bool,
+    pub orientation: Orientation,
+    pub theme: Theme,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            square_size: 50,
+            coordinates: false,
+            orientation: Orientation::White,
+            theme: Theme::default(),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// The width of the border with the coordinates, if there is one.
+    pub fn border(&self) -> u32 {
+        if self.coordinates {
+            self.square_size / 2
+        } else {
+            0
+        }
+    }
+
+    /// The width and height of the image.
+    pub fn size(&self) -> u32 {
+        8 * self.square_size + 2 * self.border()
+    }
+
+    /// The centre of a square on the screen, in pixels.
+    pub fn centre(&self, square: (u8, u8), flipped: bool) -> (f32, f32) {
+        let (column, row) = screen(square, flipped);
+        let offset = self.border() as f32 + self.square_size as f32 / 2.0;
+        (
+            offset + (column * self.square_size) as f32,
+            offset + (row * self.square_size) as f32,
+        )
+    }
+
+    /// Whether the board is drawn from black's side.
+    pub fn flipped(&self, state: &BoardState) -> bool {
+        match self.orientation {
+            Orientation::White => false,
+            Orientation::Black => true,
+            Orientation::SideToMove => state.current_player == CurrentPlayer::Black,
+        }
+    }
+}
+
+/// The column and row on the screen of a square, from the top left. `BoardState` numbers the
+/// files from h to a, so from white's side `x` goes from right to left.
+pub fn screen((x, y): (u8, u8), flipped: bool) -> (u32, u32) {
+    if flipped {
+        (u32::from(x), u32::from(y))
+    } else {
+        (7 - u32::from(x), 7 - u32::from(y))
+    }
+}
+
+/// The labels of the files from left to right and the ranks from top to bottom.
+pub fn labels(flipped: bool) -> ([char; 8], [char; 8]) {
+    let mut files = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
+    let mut ranks = ['8', '7', '6', '5', '4', '3', '2', '1'];
+    if flipped {
+        files.reverse();
+        ranks.reverse();
+    }
+    (files, ranks)
+}
+
+/// Mix `colour` into a pixel, with `alpha` out of 255.
+fn blend(pixel: &mut Rgb<u8>, colour: &[u8], alpha: u32) {
+    for (under, over) in pixel.data.iter_mut().zip(colour) {
+        *under = ((u32::from(*over) * alpha + u32::from(*under) * (255 - alpha)) / 255) as u8;
+    }
+}
+
+/// A 5 by 7 pixel font for the coordinates, a row per byte with the leftmost pixel in bit 4.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        'a' => [0x00, 0x00, 0x0e, 0x01, 0x0f, 0x11, 0x0f],
+        'b' => [0x10, 0x10, 0x1e, 0x11, 0x11, 0x11, 0x1e],
+        'c' => [0x00, 0x00, 0x0f, 0x10, 0x10, 0x10, 0x0f],
+        'd' => [0x01, 0x01, 0x0f, 0x11, 0x11, 0x11, 0x0f],
+        'e' => [0x00, 0x00, 0x0e, 0x11, 0x1f, 0x10, 0x0e],
+        'f' => [0x06, 0x08, 0x08, 0x1c, 0x08, 0x08, 0x08],
+        'g' => [0x00, 0x0f, 0x11, 0x11, 0x0f, 0x01, 0x0e],
+        'h' => [0x10, 0x10, 0x1e, 0x11, 0x11, 0x11, 0x11],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x0e, 0x11, 0x01, 0x06, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        _ => [0; 7],
+    }
+}
+
+/// Draw a character of `glyph` centred on `(x, y)`, with every font pixel `scale` pixels wide.
+fn draw_char(image: &mut RgbImage, c: char, (x, y): (u32, u32), scale: u32, colour: Rgb<u8>) {
+    let left = x.saturating_sub(5 * scale / 2);
+    let top = y.saturating_sub(7 * scale / 2);
+    for (row, bits) in glyph(c).iter().enumerate() {
+        for column in 0..5 {
+            if bits & (0x10 >> column) == 0 {
+                continue;
+            }
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let px = left + column * scale + dx;
+                    let py = top + row as u32 * scale + dy;
+                    if px < image.width() && py < image.height() {
+                        image.put_pixel(px, py, colour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pixel(colour: Hex) -> Rgb<u8> {
+    Rgb { data: colour.0 }
+}
+
+/// Draws boards with the sprites of the theme scaled to the size of the squares.
+pub struct Renderer {
+    pub options: RenderOptions,
+    sprites: HashMap<(PieceKind, CurrentPlayer), RgbaImage>,
+    /// The sprites at their own size as SVG definitions, see `svg::piece_def`.
+    svg_defs: String,
+}
+
+impl Renderer {
+    pub fn new(options: RenderOptions) -> Result<Renderer> {
+        let mut sprites = HashMap::new();
+        let mut svg_defs = String::new();
+        let size = options.square_size;
+        for player in &[CurrentPlayer::White, CurrentPlayer::Black] {
+            for kind in &[
+                PieceKind::Pawn,
+                PieceKind::Knight,
+                PieceKind::Bishop,
+                PieceKind::Rook,
+                PieceKind::Queen,
+                PieceKind::King,
+            ] {
+                let mut sprite = options.theme.sprite(*kind, *player)?;
+                svg_defs.push_str(&svg::piece_def(*kind, *player, &sprite, size)?);
+                if sprite.width() != size {
+                    sprite = imageops::resize(&sprite, size, size, FilterType::Triangle);
+                }
+                sprites.insert((*kind, *player), sprite);
+            }
+        }
+        Ok(Renderer {
+            options,
+            sprites,
+            svg_defs,
+        })
+    }
+
+    /// The `<defs>` of the pieces for SVG boards.
+    pub fn svg_defs(&self) -> &str {
+        &self.svg_defs
+    }
+
+    /// Draw a board into `board`, which can be the image of an earlier position so a new one
+    /// doesn't have to be allocated for every position. It is resized if it has another size.
+    pub fn render(&self, state: &BoardState, annotations: &Annotations, board: &mut RgbImage) {
+        let options = &self.options;
+        let (square, border, size) = (options.square_size, options.border(), options.size());
+        let flipped = options.flipped(state);
+        let theme = &options.theme;
+        if board.dimensions() != (size, size) {
+            *board = ImageBuffer::new(size, size);
+        }
+        for background in board.pixels_mut() {
+            *background = pixel(theme.background);
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                let (column, row) = screen((x, y), flipped);
+                let mut colour = if (column + row) % 2 == 0 {
+                    pixel(theme.light_square)
+                } else {
+                    pixel(theme.dark_square)
+                };
+                if let Some(m) = annotations.last_move {
+                    if m.from == (x, y) || m.to == (x, y) {
+                        blend(&mut colour, &theme.last_move.0, HIGHLIGHT_ALPHA);
+                    }
+                }
+                if annotations.check == Some((x, y)) {
+                    blend(&mut colour, &theme.check.0, HIGHLIGHT_ALPHA);
+                }
+                let (left, top) = (border + column * square, border + row * square);
+                for w in 0..square {
+                    for h in 0..square {
+                        board.put_pixel(left + w, top + h, colour);
+                    }
+                }
+
+                let piece = state.get_piece(x, y);
+                let sprite = match (piece.kind(), piece.owner()) {
+                    (Some(kind), Some(player)) => &self.sprites[&(kind, player)],
+                    _ => continue,
+                };
+                for w in 0..square {
+                    for h in 0..square {
+                        let pixel = sprite.get_pixel(w, h).data;
+                        let under = board.get_pixel_mut(left + w, top + h);
+                        blend(under, &pixel[..3], u32::from(pixel[3]));
+                    }
+                }
+            }
+        }
+
+        let square_pixels = |(x, y): (u8, u8)| {
+            let (column, row) = screen((x, y), flipped);
+            let (left, top) = (border + column * square, border + row * square);
+            (left..left + square).flat_map(move |px| (top..top + square).map(move |py| (px, py)))
+        };
+        for circle in &annotations.circles {
+            let (cx, cy) = options.centre(circle.square, flipped);
+            let outer = square as f32 * 0.46;
+            let inner = outer - square as f32 * 0.08;
+            for (px, py) in square_pixels(circle.square) {
+                let (dx, dy) = (px as f32 + 0.5 - cx, py as f32 + 0.5 - cy);
+                let distance = (dx * dx + dy * dy).sqrt();
+                if distance >= inner && distance <= outer {
+                    blend(
+                        board.get_pixel_mut(px, py),
+                        &circle.colour.rgb(),
+                        MARK_ALPHA,
+                    );
+                }
+            }
+        }
+        for arrow in &annotations.arrows {
+            let shape = ArrowShape::new(
+                options.centre(arrow.from, flipped),
+                options.centre(arrow.to, flipped),
+                square,
+            );
+            let ((x0, y0), (x1, y1)) = (shape.start, shape.end);
+            let margin = shape.head_width;
+            let (left, right) = (x0.min(x1) - margin, x0.max(x1) + margin);
+            let (top, bottom) = (y0.min(y1) - margin, y0.max(y1) + margin);
+            for px in left.max(0.0) as u32..(right as u32).min(size) {
+                for py in top.max(0.0) as u32..(bottom as u32).min(size) {
+                    if shape.contains((px as f32 + 0.5, py as f32 + 0.5)) {
+                        blend(board.get_pixel_mut(px, py), &arrow.colour.rgb(), MARK_ALPHA);
+                    }
+                }
+            }
+        }
+
+        if options.coordinates {
+            let scale = (square / 20).max(1);
+            let (files, ranks) = labels(flipped);
+            for i in 0..8 {
+                let centre = border + i as u32 * square + square / 2;
+                for edge in &[border / 2, size - border / 2] {
+                    draw_char(board, files[i], (centre, *edge), scale, pixel(theme.label));
+                    draw_char(board, ranks[i], (*edge, centre), scale, pixel(theme.label));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_screen_coordinates() {
+    // e1 from white's side is in the bottom row and the fifth column.
+    assert_eq!((4, 7), screen((3, 0), false));
+    assert_eq!((3, 0), screen((3, 0), true));
+    let (files, ranks) = labels(true);
+    assert_eq!('h', files[0]);
+    assert_eq!('1', ranks[0]);
+}
@@ -0,0 +1,190 @@
+//! The games that could not be drawn, and why, collected over a run to report at the end.
+//!
+//! `--report` writes the report as JSON, with the number of games, the counts per kind of error and
+//! every error, or as CSV with a row per error. Which one depends on the extension of the file.
+
+use shared::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// A row of the input that could not be read, or has a value a filter can't use.
+    InvalidRow,
+    /// A move that is not in algebraic notation.
+    UnparseableMove,
+    /// A move in algebraic notation that can't be played in the position.
+    IllegalMove,
+    /// The game was replayed, but writing its files failed.
+    RenderFailure,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::InvalidRow => "invalid row",
+            ErrorKind::UnparseableMove => "unparseable move",
+            ErrorKind::IllegalMove => "illegal move",
+            ErrorKind::RenderFailure => "render failure",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GameError {
+    pub kind: ErrorKind,
+    pub game_id: String,
+    /// The ply of the move that failed, from 1 for the first move.
+    pub ply: Option<usize>,
+    #[serde(rename = "move")]
+    pub move_text: Option<String>,
+    pub message: String,
+}
+
+impl GameError {
+    pub fn new(kind: ErrorKind, game_id: &str, message: String) -> GameError {
+        GameError {
+            kind,
+            game_id: game_id.to_owned(),
+            ply: None,
+            move_text: None,
+            message,
+        }
+    }
+
+    /// An error in the move of a ply.
+    pub fn at_move(
+        kind: ErrorKind,
+        game_id: &str,
+        ply: usize,
+        m: &str,
+        message: String,
+    ) -> GameError {
+        GameError {
+            ply: Some(ply),
+            move_text: Some(m.to_owned()),
+            ..GameError::new(kind, game_id, message)
+        }
+    }
+}
+
+impl fmt::Display for GameError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.kind)?;
+        if let (Some(ply), Some(m)) = (self.ply, &self.move_text) {
+            write!(fmt, " {:?} at ply {}", m, ply)?;
+        }
+        write!(fmt, ": {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub games: usize,
+    pub generated: usize,
+    pub already_done: usize,
+    pub counts: BTreeMap<ErrorKind, usize>,
+    pub errors: Vec<GameError>,
+}
+
+impl Report {
+    pub fn add(&mut self, error: GameError) {
+        *self.counts.entry(error.kind).or_insert(0) += 1;
+        self.errors.push(error);
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for error in &self.errors {
+            writer.serialize(error)?;
+        }
+        if self.errors.is_empty() {
+            writer.write_record(["kind", "game_id", "ply", "move", "message"])?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+
+    /// Write the report as JSON or CSV, depending on the extension of `path`.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let report = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("json") => self.to_json()?,
+            Some("csv") => self.to_csv()?,
+            _ => bail!(
+                "Expected a .json or .csv file for the report, got {:?}",
+                path
+            ),
+        };
+        fs::write(path, report)?;
+        Ok(())
+    }
+
+    /// A line with the number of games and the errors by kind.
+    pub fn summary(&self) -> String {
+        let failed: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        format!(
+            "Generated {} of {} games, {} were already done and {} failed{}",
+            self.generated,
+            self.games,
+            self.already_done,
+            self.errors.len(),
+            if failed.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", failed.join(", "))
+            }
+        )
+    }
+}
+
+#[test]
+fn test_report() {
+    let mut report = Report {
+        games: 3,
+        generated: 1,
+        ..Report::default()
+    };
+    let error = "Could not find pawn to move to \"e5\"".to_owned();
+    report.add(GameError::at_move(
+        ErrorKind::IllegalMove,
+        "a",
+        3,
+        "e5",
+        error,
+    ));
+    report.add(GameError::at_move(
+        ErrorKind::UnparseableMove,
+        "b",
+        1,
+        "Zz9",
+        "?".to_owned(),
+    ));
+    assert_eq!(
+        "Generated 1 of 3 games, 0 were already done and 2 failed (1 unparseable move, 1 illegal move)",
+        report.summary()
+    );
+    assert_eq!(
+        "illegal move \"e5\" at ply 3: Could not find pawn to move to \"e5\"",
+        report.errors[0].to_string()
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(1, json["counts"]["illegal_move"]);
+    assert_eq!("Zz9", json["errors"][1]["move"]);
+    let csv = report.to_csv().unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(Some("kind,game_id,ply,move,message"), lines.next());
+    assert_eq!(Some("unparseable_move,b,1,Zz9,?"), lines.nth(1));
+    assert!(Report::default().to_csv().unwrap().starts_with("kind,"));
+}
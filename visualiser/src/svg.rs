@@ -0,0 +1,276 @@
+//! Boards as SVG, which scale to any size for docs and print.
+//!
+//! The pieces are the sprites of the theme, the same as in PNG images. Each one is embedded once in
+//! the `<defs>` of the document as a PNG `<image>` and placed with `<use>`, so the document needs
+//! no other files or fonts. The size of a square in SVG units is `RenderOptions::square_size`, but
+//! the diagram scales to any size, and the sprites keep their own resolution.
+
+use crate::annotations::{Annotations, ArrowShape};
+use crate::render::{labels, screen, Renderer};
+use image::png::PNGEncoder;
+use image::{ColorType, RgbaImage};
+use shared::{BoardState, CurrentPlayer, PieceKind, Result};
+use std::fmt::Write;
+
+/// The id of the definition of a piece, e.g. `white-king`.
+fn piece_id(kind: PieceKind, player: CurrentPlayer) -> String {
+    let player = match player {
+        CurrentPlayer::White => "white",
+        CurrentPlayer::Black => "black",
+    };
+    format!("{}-{}", player, format!("{:?}", kind).to_lowercase())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, byte)| bits | u32::from(*byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The definition of a piece: its sprite as a PNG image filling a square of `square` units.
+pub fn piece_def(
+    kind: PieceKind,
+    player: CurrentPlayer,
+    sprite: &RgbaImage,
+    square: u32,
+) -> Result<String> {
+    let mut png = Vec::new();
+    let (width, height) = sprite.dimensions();
+    PNGEncoder::new(&mut png).encode(sprite, width, height, ColorType::RGBA(8))?;
+    Ok(format!(
+        r#"<image id="{}" width="{1}" height="{1}" xlink:href="data:image/png;base64,{2}"/>"#,
+        piece_id(kind, player),
+        square,
+        base64(&png)
+    ))
+}
+
+/// The definitions of the pieces in an SVG of their own, for a page that shows boards rendered
+/// without them.
+pub fn defs_document(renderer: &Renderer) -> String {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="0" height="0" style="position: absolute"><defs>{}</defs></svg>"#,
+        renderer.svg_defs()
+    )
+}
+
+/// A board as a standalone document.
+pub fn render(state: &BoardState, renderer: &Renderer, annotations: &Annotations) -> String {
+    document(state, renderer, annotations, true)
+}
+
+/// A board that uses the definitions of the pieces from `defs_document` elsewhere on the page.
+pub fn render_without_defs(
+    state: &BoardState,
+    renderer: &Renderer,
+    annotations: &Annotations,
+) -> String {
+    document(state, renderer, annotations, false)
+}
+
+fn document(
+    state: &BoardState,
+    renderer: &Renderer,
+    annotations: &Annotations,
+    defs: bool,
+) -> String {
+    let options = &renderer.options;
+    let (square, border, size) = (options.square_size, options.border(), options.size());
+    let flipped = options.flipped(state);
+    let theme = &options.theme;
+    let mut svg = String::new();
+    // Writing to a `String` can't fail.
+    let mut w = |line: String| writeln!(svg, "{}", line).unwrap();
+
+    w(format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="0 0 {0} {0}" width="{0}" height="{0}">"#,
+        size
+    ));
+    if defs {
+        w(format!("<defs>{}</defs>", renderer.svg_defs()));
+    }
+
+    if options.coordinates {
+        w(format!(
+            r#"<rect width="{0}" height="{0}" fill="{1}"/>"#,
+            size, theme.background
+        ));
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let (column, row) = screen((x, y), flipped);
+            let fill = if (column + row) % 2 == 0 {
+                theme.light_square
+            } else {
+                theme.dark_square
+            };
+            let mut overlays = Vec::new();
+            if let Some(m) = annotations.last_move {
+                if m.from == (x, y) || m.to == (x, y) {
+                    overlays.push(theme.last_move);
+                }
+            }
+            if annotations.check == Some((x, y)) {
+                overlays.push(theme.check);
+            }
+            for (fill, opacity) in
+                std::iter::once((fill, 1.0)).chain(overlays.into_iter().map(|o| (o, 0.5)))
+            {
+                w(format!(
+                    r#"<rect x="{}" y="{}" width="{2}" height="{2}" fill="{3}" fill-opacity="{4}"/>"#,
+                    border + column * square,
+                    border + row * square,
+                    square,
+                    fill,
+                    opacity
+                ));
+            }
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = state.get_piece(x, y);
+            let id = match (piece.kind(), piece.owner()) {
+                (Some(kind), Some(player)) => piece_id(kind, player),
+                _ => continue,
+            };
+            let (column, row) = screen((x, y), flipped);
+            w(format!(
+                r##"<use xlink:href="#{}" x="{}" y="{}"/>"##,
+                id,
+                border + column * square,
+                border + row * square
+            ));
+        }
+    }
+    for circle in &annotations.circles {
+        let (cx, cy) = options.centre(circle.square, flipped);
+        let width = square as f32 * 0.08;
+        w(format!(
+            r#"<circle cx="{}" cy="{}" r="{}" fill="none" stroke="{}" stroke-width="{}" stroke-opacity="0.8"/>"#,
+            cx,
+            cy,
+            square as f32 * 0.46 - width / 2.0,
+            circle.colour.hex(),
+            width
+        ));
+    }
+    for arrow in &annotations.arrows {
+        let shape = ArrowShape::new(
+            options.centre(arrow.from, flipped),
+            options.centre(arrow.to, flipped),
+            square,
+        );
+        let points: Vec<String> = shape
+            .polygon()
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+            .collect();
+        w(format!(
+            r#"<polygon points="{}" fill="{}" fill-opacity="0.8"/>"#,
+            points.join(" "),
+            arrow.colour.hex()
+        ));
+    }
+    if options.coordinates {
+        let font = square / 3;
+        let (files, ranks) = labels(flipped);
+        for i in 0..8 {
+            let centre = border + i as u32 * square + square / 2;
+            for edge in &[border / 2, size - border / 2] {
+                w(format!(
+                    r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle" dominant-baseline="central" font-family="sans-serif" fill="{}">{}</text>"#,
+                    centre, edge, font, theme.label, files[i]
+                ));
+                w(format!(
+                    r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle" dominant-baseline="central" font-family="sans-serif" fill="{}">{}</text>"#,
+                    edge, centre, font, theme.label, ranks[i]
+                ));
+            }
+        }
+    }
+    w("</svg>".to_owned());
+    svg
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!("", base64(b""));
+    assert_eq!("TQ==", base64(b"M"));
+    assert_eq!("TWE=", base64(b"Ma"));
+    assert_eq!("TWFu", base64(b"Man"));
+    assert_eq!("/+8A", base64(&[0xff, 0xef, 0x00]));
+}
+
+#[test]
+fn test_render_initial_position() {
+    let options = crate::render::RenderOptions {
+        coordinates: true,
+        ..Default::default()
+    };
+    let renderer = Renderer::new(options).unwrap();
+    let mut annotations = Annotations::default();
+    annotations.add_comment("[%cal Ge2e4][%csl Rd4]").unwrap();
+    let svg = render(&BoardState::init(), &renderer, &annotations);
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    // Every piece is defined once, as a PNG.
+    assert_eq!(12, svg.matches("<image ").count());
+    assert_eq!(
+        12,
+        svg.matches(r#"xlink:href="data:image/png;base64,iVBORw0KGgo"#)
+            .count()
+    );
+    assert_eq!(32, svg.matches("<use ").count());
+    let options = &renderer.options;
+    let last_move = options.theme.last_move.to_string();
+    assert_eq!(0, svg.matches(&last_move).count());
+    assert_eq!(1, svg.matches("<polygon ").count());
+    assert_eq!(1, svg.matches("<circle ").count());
+    // A label on both sides for every file and rank.
+    assert_eq!(2, svg.matches(">a</text>").count());
+    assert_eq!(2, svg.matches(">8</text>").count());
+    // The white king on e1, in the bottom row and the fifth column.
+    let square = options.square_size;
+    let king = format!(
+        r##"<use xlink:href="#white-king" x="{}" y="{}"/>"##,
+        square / 2 + 4 * square,
+        square / 2 + 7 * square
+    );
+    assert!(svg.contains(&king), "{}", svg);
+    assert!(svg.contains(r#"<image id="white-king" width="50" height="50" "#));
+
+    let board = render_without_defs(&BoardState::init(), &renderer, &annotations);
+    assert_eq!(0, board.matches("<image ").count());
+    assert_eq!(32, board.matches("<use ").count());
+    assert_eq!(12, defs_document(&renderer).matches("<image ").count());
+}
+
+#[test]
+fn test_render_last_move_and_check() {
+    let mut state = BoardState::init();
+    for m in &["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6"] {
+        state.make_move(m).unwrap();
+    }
+    let before = state.clone();
+    state.make_move("Qxf7#").unwrap();
+    let annotations = Annotations::new(Some(&before), &state);
+    let renderer = Renderer::new(Default::default()).unwrap();
+    let svg = render(&state, &renderer, &annotations);
+    let theme = &renderer.options.theme;
+    assert_eq!(2, svg.matches(&theme.last_move.to_string()).count());
+    assert_eq!(1, svg.matches(&theme.check.to_string()).count());
+}
@@ -0,0 +1,90 @@
+//! Stepping through a game in the terminal, a position at a time.
+
+use crate::annotations::Annotations;
+use crate::render::RenderOptions;
+use shared::display::{DisplayOptions, Style};
+use shared::{BoardState, Result};
+use std::io::{self, BufRead, Write};
+
+/// What to do after a line of input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Next,
+    Previous,
+    Jump(usize),
+    NextGame,
+    Quit,
+}
+
+fn parse_command(line: &str) -> Option<Command> {
+    match line.trim() {
+        "" => Some(Command::Next),
+        "p" => Some(Command::Previous),
+        "n" => Some(Command::NextGame),
+        "q" => Some(Command::Quit),
+        ply => ply.parse().ok().map(Command::Jump),
+    }
+}
+
+/// Show the positions of a game until the user moves on to the next game, or quits. Returns
+/// whether to go on with the next game.
+pub fn step(
+    game_id: &str,
+    moves: &[&str],
+    states: &[BoardState],
+    annotations: &[Annotations],
+    options: &RenderOptions,
+    style: Style,
+) -> Result<bool> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let last = states.len() - 1;
+    let mut ply = 0;
+    let mut message = String::new();
+    loop {
+        let state = &states[ply];
+        let display = DisplayOptions {
+            style,
+            coordinates: options.coordinates,
+            flipped: options.flipped(state),
+            last_move: annotations[ply].last_move,
+        };
+        // Clear the screen and go to the top left.
+        print!("\x1b[2J\x1b[H");
+        let played = if ply == 0 { "start" } else { moves[ply - 1] };
+        println!("Game {:?}, ply {} of {}: {}", game_id, ply, last, played);
+        println!();
+        print!("{}", state.display(display));
+        println!();
+        if !message.is_empty() {
+            println!("{}", message);
+            message.clear();
+        }
+        print!("[Enter] next, p previous, PLY to jump, n next game, q quit: ");
+        io::stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(false),
+        };
+        match parse_command(&line) {
+            Some(Command::Next) if ply == last => return Ok(true),
+            Some(Command::Next) => ply += 1,
+            Some(Command::Previous) => ply = ply.saturating_sub(1),
+            Some(Command::Jump(to)) if to <= last => ply = to,
+            Some(Command::Jump(to)) => message = format!("There is no ply {}", to),
+            Some(Command::NextGame) => return Ok(true),
+            Some(Command::Quit) => return Ok(false),
+            None => message = format!("Unknown command {:?}", line.trim()),
+        }
+    }
+}
+
+#[test]
+fn test_parse_command() {
+    assert_eq!(Some(Command::Next), parse_command("\n"));
+    assert_eq!(Some(Command::Previous), parse_command("p"));
+    assert_eq!(Some(Command::Jump(12)), parse_command(" 12 "));
+    assert_eq!(Some(Command::Quit), parse_command("q"));
+    assert_eq!(None, parse_command("x"));
+}
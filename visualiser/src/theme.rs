@@ -0,0 +1,195 @@
+//! The colours of the board and the piece sprites, built in or loaded from a TOML file.
+//!
+//! A theme file sets any of the colours as `"#rrggbb"`, and optionally a directory with sprites
+//! named like `whiteKing.png` and `blackPawn.png`. A relative directory is relative to the theme
+//! file. Everything that is left out comes from the `grey` theme, and the sprites from the ones
+//! built into the visualiser:
+//!
+//! ```toml
+//! light_square = "#f0d9b5"
+//! dark_square = "#b58863"
+//! sprites = "my-sprites"
+//! ```
+
+use image::RgbaImage;
+use serde::de::{Deserialize, Deserializer, Error};
+use shared::{CurrentPlayer, PieceKind, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A colour written as `"#rrggbb"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hex(pub [u8; 3]);
+
+impl Hex {
+    pub fn parse(s: &str) -> Result<Hex> {
+        let digits = s.trim_start_matches('#');
+        if digits.len() != 6 || !s.starts_with('#') {
+            bail!("Expected a colour like \"#rrggbb\", got {:?}", s);
+        }
+        let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16);
+        Ok(Hex([channel(0)?, channel(2)?, channel(4)?]))
+    }
+}
+
+impl std::fmt::Display for Hex {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let [r, g, b] = self.0;
+        write!(fmt, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Hex, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Hex::parse(&s).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub light_square: Hex,
+    pub dark_square: Hex,
+    /// The border around the board, with the coordinates.
+    pub background: Hex,
+    pub label: Hex,
+    /// The squares of the last move.
+    pub last_move: Hex,
+    /// The square of a king in check.
+    pub check: Hex,
+    /// The directory with the sprites, or `None` for the built-in sprites.
+    pub sprites: Option<PathBuf>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            light_square: Hex([200, 200, 200]),
+            dark_square: Hex([50, 50, 50]),
+            background: Hex([255, 255, 255]),
+            label: Hex([50, 50, 50]),
+            last_move: Hex([224, 192, 64]),
+            check: Hex([220, 40, 40]),
+            sprites: None,
+        }
+    }
+}
+
+/// The names of the built-in themes.
+pub const BUILT_IN: [&str; 3] = ["grey", "brown", "blue"];
+
+impl Theme {
+    pub fn built_in(name: &str) -> Option<Theme> {
+        let (light_square, dark_square) = match name {
+            "grey" => return Some(Theme::default()),
+            "brown" => (Hex([240, 217, 181]), Hex([181, 136, 99])),
+            "blue" => (Hex([222, 227, 230]), Hex([140, 162, 173])),
+            _ => return None,
+        };
+        Some(Theme {
+            light_square,
+            dark_square,
+            label: dark_square,
+            last_move: Hex([205, 210, 106]),
+            ..Theme::default()
+        })
+    }
+
+    pub fn parse(toml: &str) -> Result<Theme> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    pub fn load(path: &str) -> Result<Theme> {
+        let mut theme = Theme::parse(&fs::read_to_string(path)?)
+            .map_err(|e| format_err!("Invalid theme {:?}: {}", path, e))?;
+        if let Some(sprites) = &theme.sprites {
+            let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+            theme.sprites = Some(directory.join(sprites));
+        }
+        Ok(theme)
+    }
+
+    /// A built-in theme by its name, or else a theme file.
+    pub fn find(name: &str) -> Result<Theme> {
+        match Theme::built_in(name) {
+            Some(theme) => Ok(theme),
+            None if Path::new(name).is_file() => Theme::load(name),
+            None => bail!(
+                "{:?} is not a theme file or one of the themes {}",
+                name,
+                BUILT_IN.join(", ")
+            ),
+        }
+    }
+
+    /// The sprite of a piece, from the sprite directory of the theme or else built in.
+    pub fn sprite(&self, kind: PieceKind, player: CurrentPlayer) -> Result<RgbaImage> {
+        let name = sprite_name(kind, player);
+        let image = match &self.sprites {
+            Some(directory) => {
+                let path = directory.join(&name);
+                image::open(&path).map_err(|e| format_err!("Could not load {:?}: {}", path, e))?
+            }
+            None => image::load_from_memory(built_in_sprite(kind, player))?,
+        };
+        let image = image.to_rgba();
+        if image.width() != image.height() {
+            bail!("The sprite {:?} is not square", name);
+        }
+        Ok(image)
+    }
+}
+
+fn sprite_name(kind: PieceKind, player: CurrentPlayer) -> String {
+    let player = match player {
+        CurrentPlayer::White => "white",
+        CurrentPlayer::Black => "black",
+    };
+    format!("{}{:?}.png", player, kind)
+}
+
+fn built_in_sprite(kind: PieceKind, player: CurrentPlayer) -> &'static [u8] {
+    use shared::CurrentPlayer::{Black, White};
+    use shared::PieceKind::*;
+    match (player, kind) {
+        (White, King) => include_bytes!("../sprites/whiteKing.png"),
+        (White, Queen) => include_bytes!("../sprites/whiteQueen.png"),
+        (White, Rook) => include_bytes!("../sprites/whiteRook.png"),
+        (White, Bishop) => include_bytes!("../sprites/whiteBishop.png"),
+        (White, Knight) => include_bytes!("../sprites/whiteKnight.png"),
+        (White, Pawn) => include_bytes!("../sprites/whitePawn.png"),
+        (Black, King) => include_bytes!("../sprites/blackKing.png"),
+        (Black, Queen) => include_bytes!("../sprites/blackQueen.png"),
+        (Black, Rook) => include_bytes!("../sprites/blackRook.png"),
+        (Black, Bishop) => include_bytes!("../sprites/blackBishop.png"),
+        (Black, Knight) => include_bytes!("../sprites/blackKnight.png"),
+        (Black, Pawn) => include_bytes!("../sprites/blackPawn.png"),
+    }
+}
+
+#[test]
+fn test_parse_theme() {
+    let theme = Theme::parse("light_square = \"#f0d9b5\"\nsprites = \"pieces\"\n").unwrap();
+    assert_eq!(Hex([240, 217, 181]), theme.light_square);
+    assert_eq!(Theme::default().dark_square, theme.dark_square);
+    assert_eq!(Some(PathBuf::from("pieces")), theme.sprites);
+    assert!(Theme::parse("light_square = \"f0d9b5\"").is_err());
+    assert!(Theme::parse("light = \"#f0d9b5\"").is_err());
+    assert_eq!("#f0d9b5", Hex([240, 217, 181]).to_string());
+}
+
+#[test]
+fn test_built_in_sprites() {
+    for name in &BUILT_IN {
+        let theme = Theme::built_in(name).unwrap();
+        let sprite = theme
+            .sprite(PieceKind::Knight, CurrentPlayer::Black)
+            .unwrap();
+        assert_eq!((50, 50), sprite.dimensions());
+    }
+    assert_eq!(
+        "whiteKing.png",
+        sprite_name(PieceKind::King, CurrentPlayer::White)
+    );
+}